@@ -2,7 +2,8 @@ use std::{cell::RefCell, rc::Rc};
 
 use monkey_lang::{
     ast::Program, compiler::Compiler, enviroment::Enviroment, evaluator::Evaluator, lexer::Lexer,
-    parser::Parser, vm::Vm,
+    parser::Parser,
+    vm::{Vm, VmConfig},
 };
 
 use criterion::{criterion_group, criterion_main, Criterion};
@@ -31,7 +32,7 @@ fn eval(program: &Program) {
 fn vm(program: &Program) {
     let mut compiler = Compiler::new();
     compiler.compile(program);
-    let mut vm = Vm::new(compiler.bytecode());
+    let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
     vm.run();
 }
 
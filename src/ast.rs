@@ -5,6 +5,22 @@ pub enum Statement {
     Let(LetStatement),
     Return(ReturnStatement),
     Expression(ExpressionStatement),
+    Struct(StructStatement),
+    Enum(EnumStatement),
+    Import(ImportStatement),
+    Export(ExportStatement),
+}
+
+impl Statement {
+    pub fn contains_yield(&self) -> bool {
+        match self {
+            Self::Let(s) => s.value.contains_yield(),
+            Self::Return(s) => s.return_value.contains_yield(),
+            Self::Expression(s) => s.expression.contains_yield(),
+            Self::Export(s) => s.statement.contains_yield(),
+            Self::Struct(_) | Self::Enum(_) | Self::Import(_) => false,
+        }
+    }
 }
 
 impl Display for Statement {
@@ -13,25 +29,92 @@ impl Display for Statement {
             Self::Let(s) => write!(f, "{}", s),
             Self::Return(s) => write!(f, "{}", s),
             Self::Expression(s) => write!(f, "{}", s),
+            Self::Struct(s) => write!(f, "{}", s),
+            Self::Enum(s) => write!(f, "{}", s),
+            Self::Import(s) => write!(f, "{}", s),
+            Self::Export(s) => write!(f, "{}", s),
         }
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct ImportStatement {
+    pub path: String,
+    pub name: Identifier,
+}
+
+impl ImportStatement {
+    pub fn new(path: String, name: Identifier) -> Self {
+        Self { path, name }
+    }
+}
+
+impl Display for ImportStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "import \"{}\" as {}", self.path, self.name)
+    }
+}
+
+/// A `let`, `const`, `struct` or `enum` at the top of a module whose names
+/// importers can use.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExportStatement {
+    pub statement: Box<Statement>,
+}
+
+impl ExportStatement {
+    pub fn new(statement: Statement) -> Self {
+        Self {
+            statement: Box::new(statement),
+        }
+    }
+
+    /// The names the statement binds. An enum binds its variants.
+    pub fn names(&self) -> Vec<String> {
+        match *self.statement {
+            Statement::Let(ref s) => vec![s.name.value.to_owned()],
+            Statement::Struct(ref s) => vec![s.name.value.to_owned()],
+            Statement::Enum(ref s) => s.variants.iter().map(|v| v.name.value.to_owned()).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl Display for ExportStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "export {}", self.statement)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct LetStatement {
     pub name: Identifier,
     pub value: Expression,
+    pub is_const: bool,
 }
 
 impl LetStatement {
     pub fn new(name: Identifier, value: Expression) -> Self {
-        Self { name, value }
+        Self {
+            name,
+            value,
+            is_const: false,
+        }
+    }
+
+    pub fn constant(name: Identifier, value: Expression) -> Self {
+        Self {
+            name,
+            value,
+            is_const: true,
+        }
     }
 }
 
 impl Display for LetStatement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "let {} = {}", self.name, self.value)
+        let keyword = if self.is_const { "const" } else { "let" };
+        write!(f, "{} {} = {}", keyword, self.name, self.value)
     }
 }
 
@@ -69,6 +152,91 @@ impl Display for ExpressionStatement {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct StructStatement {
+    pub name: Identifier,
+    pub fields: Vec<Identifier>,
+}
+
+impl StructStatement {
+    pub fn new(name: Identifier, fields: Vec<Identifier>) -> Self {
+        Self { name, fields }
+    }
+}
+
+impl Display for StructStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "struct {} {{ {} }}",
+            self.name,
+            self.fields
+                .iter()
+                .map(|i| format!("{}", i))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumStatement {
+    pub name: Identifier,
+    pub variants: Vec<EnumVariant>,
+}
+
+impl EnumStatement {
+    pub fn new(name: Identifier, variants: Vec<EnumVariant>) -> Self {
+        Self { name, variants }
+    }
+}
+
+impl Display for EnumStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "enum {} {{ {} }}",
+            self.name,
+            self.variants
+                .iter()
+                .map(|v| format!("{}", v))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumVariant {
+    pub name: Identifier,
+    pub fields: Vec<Identifier>,
+}
+
+impl EnumVariant {
+    pub fn new(name: Identifier, fields: Vec<Identifier>) -> Self {
+        Self { name, fields }
+    }
+}
+
+impl Display for EnumVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.fields.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(
+                f,
+                "{}({})",
+                self.name,
+                self.fields
+                    .iter()
+                    .map(|i| format!("{}", i))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct BlockStatement {
     pub statements: Vec<Statement>,
@@ -76,6 +244,10 @@ pub struct BlockStatement {
 
 impl BlockStatement {
     pub fn new(statements: Vec<Statement>) -> Self { Self { statements } }
+
+    pub fn contains_yield(&self) -> bool {
+        self.statements.iter().any(|s| s.contains_yield())
+    }
 }
 
 impl Display for BlockStatement {
@@ -108,6 +280,194 @@ pub enum Expression {
     Call(CallExpression),
     If(IfExpression),
     Index(IndexExpression),
+    Slice(SliceExpression),
+    Match(MatchExpression),
+    Spread(SpreadExpression),
+    ListComprehension(ListComprehension),
+    HashComprehension(HashComprehension),
+    Yield(YieldExpression),
+    Assign(AssignExpression),
+    /// A `?.` chain in parentheses, which cuts short only the chain inside
+    /// them.
+    Group(GroupedExpression),
+    /// Statements run in the enclosing scope, as a whole. Only quoted code
+    /// has these, for macros that expand to more than an expression.
+    Block(BlockStatement),
+}
+
+impl Expression {
+    /// Reports whether evaluating this expression can reach a `yield`. Nested
+    /// function literals are not searched, as their yields suspend them
+    /// rather than the enclosing function.
+    pub fn contains_yield(&self) -> bool {
+        match self {
+            Self::Identifier(_)
+            | Self::NullLiteral(_)
+            | Self::IntegerLiteral(_)
+            | Self::StringLiteral(_)
+            | Self::Boolean(_)
+            | Self::FunctionLiteral(_)
+            | Self::MacroLiteral(_) => false,
+            Self::Yield(_) => true,
+            Self::ArrayLiteral(e) => e.elements.iter().any(|e| e.contains_yield()),
+            Self::HashLiteral(e) => e
+                .members
+                .iter()
+                .any(|m| m.key.contains_yield() || m.value.contains_yield()),
+            Self::Prefix(e) => e.right.contains_yield(),
+            Self::Infix(e) => e.left.contains_yield() || e.right.contains_yield(),
+            Self::Index(e) => e.left.contains_yield() || e.index.contains_yield(),
+            Self::Slice(e) => {
+                e.left.contains_yield()
+                    || matches!(e.start, Some(ref start) if start.contains_yield())
+                    || matches!(e.end, Some(ref end) if end.contains_yield())
+            }
+            Self::Call(e) => {
+                e.callee.contains_yield() || e.arguments.iter().any(|a| a.contains_yield())
+            }
+            Self::If(e) => {
+                e.condition.contains_yield()
+                    || e.consequence.contains_yield()
+                    || matches!(e.alternative, Some(ref alternative) if alternative.contains_yield())
+            }
+            Self::Match(e) => {
+                e.subject.contains_yield() || e.arms.iter().any(|a| a.body.contains_yield())
+            }
+            Self::Spread(e) => e.value.contains_yield(),
+            Self::Assign(e) => e.value.contains_yield(),
+            Self::Group(e) => e.value.contains_yield(),
+            Self::ListComprehension(e) => e.element.contains_yield() || e.clause.contains_yield(),
+            Self::HashComprehension(e) => {
+                e.key.contains_yield() || e.value.contains_yield() || e.clause.contains_yield()
+            }
+            Self::Block(e) => e.contains_yield(),
+        }
+    }
+
+    /// Reports whether this is a chain of calls, indexes and slices with a
+    /// `?.` or `?[` in it.
+    pub fn is_optional_chain(&self) -> bool {
+        match self {
+            Self::Index(e) => e.optional || e.left.is_optional_chain(),
+            Self::Call(e) => e.callee.is_optional_chain(),
+            Self::Slice(e) => e.left.is_optional_chain(),
+            _ => false,
+        }
+    }
+
+    /// Names the kind of node this is, for macros inspecting quoted code.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Identifier(_) => "IDENTIFIER",
+            Self::NullLiteral(_) => "NULL",
+            Self::IntegerLiteral(_) => "INTEGER",
+            Self::StringLiteral(_) => "STRING",
+            Self::ArrayLiteral(_) => "ARRAY",
+            Self::HashLiteral(_) => "HASH",
+            Self::FunctionLiteral(_) => "FUNCTION",
+            Self::MacroLiteral(_) => "MACRO",
+            Self::Boolean(_) => "BOOLEAN",
+            Self::Prefix(_) => "PREFIX",
+            Self::Infix(_) => "INFIX",
+            Self::Call(_) => "CALL",
+            Self::If(_) => "IF",
+            Self::Index(_) => "INDEX",
+            Self::Slice(_) => "SLICE",
+            Self::Match(_) => "MATCH",
+            Self::Spread(_) => "SPREAD",
+            Self::ListComprehension(_) => "LIST_COMPREHENSION",
+            Self::HashComprehension(_) => "HASH_COMPREHENSION",
+            Self::Yield(_) => "YIELD",
+            Self::Assign(_) => "ASSIGN",
+            Self::Group(_) => "GROUP",
+            Self::Block(_) => "BLOCK",
+        }
+    }
+
+    /// Returns the nodes directly inside this one, in source order. Bodies
+    /// come back as blocks, and so do statements other than expressions.
+    /// Missing parts, such as the bounds of `a[:]`, are left out.
+    pub fn children(&self) -> Vec<Expression> {
+        let block = |block: &BlockStatement| Expression::Block(block.to_owned());
+        let ident = |ident: &Identifier| Expression::Identifier(ident.to_owned());
+        let clause = |clause: &ComprehensionClause| {
+            let mut children: Vec<_> = clause.bindings.iter().map(ident).collect();
+            children.push(*clause.iterable.to_owned());
+            children.extend(clause.condition.iter().map(|c| *c.to_owned()));
+            children
+        };
+
+        match self {
+            Self::Identifier(_)
+            | Self::NullLiteral(_)
+            | Self::IntegerLiteral(_)
+            | Self::StringLiteral(_)
+            | Self::Boolean(_) => vec![],
+            Self::ArrayLiteral(e) => e.elements.to_owned(),
+            Self::HashLiteral(e) => e
+                .members
+                .iter()
+                .flat_map(|m| [m.key.to_owned(), m.value.to_owned()])
+                .collect(),
+            Self::FunctionLiteral(e) => {
+                let mut children: Vec<_> = e.parameters.iter().map(ident).collect();
+                children.push(block(&e.body));
+                children
+            }
+            Self::MacroLiteral(e) => {
+                let mut children: Vec<_> = e.parameters.iter().map(ident).collect();
+                children.push(block(&e.body));
+                children
+            }
+            Self::Prefix(e) => vec![*e.right.to_owned()],
+            Self::Infix(e) => vec![*e.left.to_owned(), *e.right.to_owned()],
+            Self::Call(e) => {
+                let mut children = vec![*e.callee.to_owned()];
+                children.extend(e.arguments.iter().cloned());
+                children
+            }
+            Self::If(e) => {
+                let mut children = vec![*e.condition.to_owned(), block(&e.consequence)];
+                children.extend(e.alternative.iter().map(block));
+                children
+            }
+            Self::Index(e) => vec![*e.left.to_owned(), *e.index.to_owned()],
+            Self::Slice(e) => {
+                let mut children = vec![*e.left.to_owned()];
+                children.extend(e.start.iter().chain(e.end.iter()).map(|e| *e.to_owned()));
+                children
+            }
+            Self::Match(e) => {
+                let mut children = vec![*e.subject.to_owned()];
+                children.extend(e.arms.iter().map(|arm| block(&arm.body)));
+                children
+            }
+            Self::Spread(e) => vec![*e.value.to_owned()],
+            Self::Yield(e) => vec![*e.value.to_owned()],
+            Self::Assign(e) => vec![ident(&e.name), *e.value.to_owned()],
+            Self::Group(e) => vec![*e.value.to_owned()],
+            Self::ListComprehension(e) => {
+                let mut children = vec![*e.element.to_owned()];
+                children.extend(clause(&e.clause));
+                children
+            }
+            Self::HashComprehension(e) => {
+                let mut children = vec![*e.key.to_owned(), *e.value.to_owned()];
+                children.extend(clause(&e.clause));
+                children
+            }
+            Self::Block(e) => e
+                .statements
+                .iter()
+                .map(|stmt| match stmt {
+                    Statement::Expression(stmt) => stmt.expression.to_owned(),
+                    stmt => Expression::Block(BlockStatement {
+                        statements: vec![stmt.to_owned()],
+                    }),
+                })
+                .collect(),
+        }
+    }
 }
 
 impl Display for Expression {
@@ -125,8 +485,17 @@ impl Display for Expression {
             Self::Prefix(e) => write!(f, "{}", e),
             Self::Infix(e) => write!(f, "{}", e),
             Self::Index(e) => write!(f, "{}", e),
+            Self::Slice(e) => write!(f, "{}", e),
             Self::Call(e) => write!(f, "{}", e),
             Self::If(e) => write!(f, "{}", e),
+            Self::Match(e) => write!(f, "{}", e),
+            Self::Spread(e) => write!(f, "{}", e),
+            Self::ListComprehension(e) => write!(f, "{}", e),
+            Self::HashComprehension(e) => write!(f, "{}", e),
+            Self::Yield(e) => write!(f, "{}", e),
+            Self::Assign(e) => write!(f, "{}", e),
+            Self::Group(e) => write!(f, "{}", e),
+            Self::Block(e) => write!(f, "{{ {} }}", e),
         }
     }
 }
@@ -251,11 +620,28 @@ impl HashMember {
     pub fn new(key: Expression, value: Expression) -> Self {
         Self { key, value }
     }
+
+    /// A `...hash` member. The spread is kept in `key` so members stay in
+    /// source order, and `value` is unused.
+    pub fn spread(hash: Expression) -> Self {
+        Self {
+            key: Expression::Spread(SpreadExpression::new(hash)),
+            value: Expression::NullLiteral(NullLiteral {}),
+        }
+    }
+
+    pub fn is_spread(&self) -> bool {
+        matches!(self.key, Expression::Spread(_))
+    }
 }
 
 impl Display for HashMember {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}", self.key, self.value)
+        if self.is_spread() {
+            write!(f, "{}", self.key)
+        } else {
+            write!(f, "{}: {}", self.key, self.value)
+        }
     }
 }
 
@@ -264,11 +650,18 @@ pub struct FunctionLiteral {
     pub name: String,
     pub parameters: Vec<Identifier>,
     pub body: BlockStatement,
+    /// Set for `fn*`, whose calls return a generator instead of running the body.
+    pub is_generator: bool,
 }
 
 impl FunctionLiteral {
     pub fn new(name: String, parameters: Vec<Identifier>, body: BlockStatement) -> Self {
-        Self { name, parameters, body }
+        Self {
+            name,
+            parameters,
+            body,
+            is_generator: false,
+        }
     }
 }
 
@@ -276,7 +669,8 @@ impl Display for FunctionLiteral {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "fn {}({}) {}",
+            "{} {}({}) {}",
+            if self.is_generator { "fn*" } else { "fn" },
             self.name,
             self.parameters
                 .iter()
@@ -431,6 +825,12 @@ impl Display for CallExpression {
 pub struct IndexExpression {
     pub left: Box<Expression>,
     pub index: Box<Expression>,
+    /// Set for `a?[k]` and `a?.k`, which yield null without evaluating the
+    /// index when `left` is null.
+    pub optional: bool,
+    /// Set for `a.k`, whose index is the string `k`. Unlike `a["k"]`, it
+    /// falls back to the builtin methods, as in `a.len()`.
+    pub member: bool,
 }
 
 impl IndexExpression {
@@ -438,13 +838,327 @@ impl IndexExpression {
         Self {
             left: Box::new(left),
             index: Box::new(index),
+            optional: false,
+            member: false,
+        }
+    }
+
+    pub fn optional(left: Expression, index: Expression) -> Self {
+        Self {
+            optional: true,
+            ..Self::new(left, index)
+        }
+    }
+
+    pub fn member(left: Expression, name: String, optional: bool) -> Self {
+        Self {
+            optional,
+            member: true,
+            ..Self::new(left, Expression::StringLiteral(StringLiteral::new(name)))
         }
     }
 }
 
 impl Display for IndexExpression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({}[{}])", self.left, self.index)
+        if let (true, Expression::StringLiteral(name)) = (self.member, &*self.index) {
+            let dot = if self.optional { "?." } else { "." };
+            write!(f, "({}{}{})", self.left, dot, name.value)
+        } else if self.optional {
+            write!(f, "({}?[{}])", self.left, self.index)
+        } else {
+            write!(f, "({}[{}])", self.left, self.index)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SliceExpression {
+    pub left: Box<Expression>,
+    pub start: Option<Box<Expression>>,
+    pub end: Option<Box<Expression>>,
+}
+
+impl SliceExpression {
+    pub fn new(left: Expression, start: Option<Expression>, end: Option<Expression>) -> Self {
+        Self {
+            left: Box::new(left),
+            start: start.map(Box::new),
+            end: end.map(Box::new),
+        }
+    }
+}
+
+impl Display for SliceExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}[", self.left)?;
+        if let Some(ref start) = self.start {
+            write!(f, "{}", start)?;
+        }
+        write!(f, ":")?;
+        if let Some(ref end) = self.end {
+            write!(f, "{}", end)?;
+        }
+        write!(f, "])")
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpreadExpression {
+    pub value: Box<Expression>,
+}
+
+impl SpreadExpression {
+    pub fn new(value: Expression) -> Self {
+        Self {
+            value: Box::new(value),
+        }
+    }
+}
+
+impl Display for SpreadExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "...{}", self.value)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupedExpression {
+    pub value: Box<Expression>,
+}
+
+impl GroupedExpression {
+    pub fn new(value: Expression) -> Self {
+        Self {
+            value: Box::new(value),
+        }
+    }
+}
+
+impl Display for GroupedExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({})", self.value)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ListComprehension {
+    pub element: Box<Expression>,
+    pub clause: ComprehensionClause,
+}
+
+impl ListComprehension {
+    pub fn new(element: Expression, clause: ComprehensionClause) -> Self {
+        Self {
+            element: Box::new(element),
+            clause,
+        }
+    }
+}
+
+impl Display for ListComprehension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{} {}]", self.element, self.clause)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HashComprehension {
+    pub key: Box<Expression>,
+    pub value: Box<Expression>,
+    pub clause: ComprehensionClause,
+}
+
+impl HashComprehension {
+    pub fn new(key: Expression, value: Expression, clause: ComprehensionClause) -> Self {
+        Self {
+            key: Box::new(key),
+            value: Box::new(value),
+            clause,
+        }
+    }
+}
+
+impl Display for HashComprehension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}: {} {}}}", self.key, self.value, self.clause)
+    }
+}
+
+/// The `for a, b in iterable if condition` part shared by both comprehensions.
+/// A second binding destructures each item as a `[first, second]` pair.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ComprehensionClause {
+    pub bindings: Vec<Identifier>,
+    pub iterable: Box<Expression>,
+    pub condition: Option<Box<Expression>>,
+}
+
+impl ComprehensionClause {
+    pub fn new(
+        bindings: Vec<Identifier>,
+        iterable: Expression,
+        condition: Option<Expression>,
+    ) -> Self {
+        Self {
+            bindings,
+            iterable: Box::new(iterable),
+            condition: condition.map(Box::new),
+        }
+    }
+}
+
+impl ComprehensionClause {
+    pub fn contains_yield(&self) -> bool {
+        self.iterable.contains_yield()
+            || matches!(self.condition, Some(ref condition) if condition.contains_yield())
+    }
+}
+
+impl Display for ComprehensionClause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "for {} in {}",
+            self.bindings
+                .iter()
+                .map(|b| format!("{}", b))
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.iterable
+        )?;
+        if let Some(ref condition) = self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct YieldExpression {
+    pub value: Box<Expression>,
+}
+
+impl YieldExpression {
+    pub fn new(value: Expression) -> Self {
+        Self {
+            value: Box::new(value),
+        }
+    }
+}
+
+impl Display for YieldExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "yield {}", self.value)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AssignExpression {
+    pub name: Identifier,
+    /// Set for `name.field = value`, which gives `name` a copy of its struct
+    /// with the field replaced.
+    pub field: Option<String>,
+    pub value: Box<Expression>,
+}
+
+impl AssignExpression {
+    pub fn new(name: Identifier, value: Expression) -> Self {
+        Self {
+            name,
+            field: None,
+            value: Box::new(value),
+        }
+    }
+
+    pub fn field(name: Identifier, field: String, value: Expression) -> Self {
+        Self {
+            field: Some(field),
+            ..Self::new(name, value)
+        }
+    }
+}
+
+impl Display for AssignExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.field {
+            Some(ref field) => write!(f, "({}.{} = {})", self.name, field, self.value),
+            None => write!(f, "({} = {})", self.name, self.value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchExpression {
+    pub subject: Box<Expression>,
+    pub arms: Vec<MatchArm>,
+}
+
+impl MatchExpression {
+    pub fn new(subject: Expression, arms: Vec<MatchArm>) -> Self {
+        Self {
+            subject: Box::new(subject),
+            arms,
+        }
+    }
+}
+
+impl Display for MatchExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "match {} {{ {} }}",
+            self.subject,
+            self.arms
+                .iter()
+                .map(|a| format!("{}", a))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: BlockStatement,
+}
+
+impl MatchArm {
+    pub fn new(pattern: Pattern, body: BlockStatement) -> Self {
+        Self { pattern, body }
+    }
+}
+
+impl Display for MatchArm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} => {}", self.pattern, self.body)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    Wildcard,
+    /// Matches an enum variant by name and binds its payload positionally.
+    Variant(Identifier, Vec<Identifier>),
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Wildcard => write!(f, "_"),
+            Self::Variant(name, bindings) if bindings.is_empty() => write!(f, "{}", name),
+            Self::Variant(name, bindings) => write!(
+                f,
+                "{}({})",
+                name,
+                bindings
+                    .iter()
+                    .map(|b| format!("{}", b))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
     }
 }
 
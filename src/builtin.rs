@@ -1,9 +1,42 @@
 use std::rc::Rc;
 
-use crate::object::{Array, Integer, Null, Object, RuntimeError};
+use crate::{
+    ast::{BlockStatement, CallExpression, Expression, Identifier, LetStatement, Statement},
+    makro::{self, EvalUnqupteCalls},
+    object::{
+        Array, BuiltinFunction, Generator, HashKeyable, Inspector, Integer, Null, Object, Quote,
+        RuntimeError, Str,
+    },
+};
 
+pub static BUILTINS: [&str; 17] = [
+    "len",
+    "puts",
+    "first",
+    "last",
+    "rest",
+    "push",
+    "next",
+    "spawn",
+    "channel",
+    "send",
+    "recv",
+    "gensym",
+    "ast_kind",
+    "ast_children",
+    "ast_call",
+    "ast_ident",
+    "ast_let",
+];
 
-pub static BUILTINS: [&str; 6] =  ["len", "puts", "first", "last", "rest", "push"];
+/// Builtins whose first argument can be given as a receiver, as in
+/// `arr.push(4)`.
+pub static METHODS: [&str; 8] = [
+    "len", "first", "last", "rest", "push", "next", "send", "recv",
+];
+
+/// Builtins backed by the VM's task scheduler.
+pub static TASK_BUILTINS: [&str; 4] = ["spawn", "channel", "send", "recv"];
 
 #[derive(Debug, Default)]
 pub struct Builtin {}
@@ -13,6 +46,69 @@ impl Builtin {
         BUILTINS.contains(&func)
     }
 
+    /// Looks up `name` as a method on `receiver`, so that `arr.push(4)` can be
+    /// called like `push(arr, 4)`. Struct fields and hash keys of the same
+    /// name take precedence.
+    pub fn lookup_method(&self, receiver: &Rc<Object>, name: &str) -> Option<Rc<Object>> {
+        let shadowed = match **receiver {
+            Object::Struct(_) => true,
+            Object::Hash(ref hash) => hash.get(&Str::new(name.into()).hash_key()).is_some(),
+            _ => false,
+        };
+        if shadowed || !METHODS.contains(&name) {
+            return None;
+        }
+
+        Some(
+            Object::BuiltinFunction(BuiltinFunction::with_receiver(
+                name.into(),
+                Rc::clone(receiver),
+            ))
+            .into(),
+        )
+    }
+
+    pub fn call(&self, func: &BuiltinFunction, args: Vec<Rc<Object>>) -> Option<Rc<Object>> {
+        self.apply_function(&func.name, self.bind_receiver(func, args))
+    }
+
+    pub fn bind_receiver(
+        &self,
+        func: &BuiltinFunction,
+        mut args: Vec<Rc<Object>>,
+    ) -> Vec<Rc<Object>> {
+        if let Some(ref receiver) = func.receiver {
+            args.insert(0, Rc::clone(receiver));
+        }
+        args
+    }
+
+    /// Checks the arguments of a `next` call and returns the generator to
+    /// resume. Resuming runs code, so each engine does that part itself.
+    pub fn next_generator(
+        &self,
+        func: &BuiltinFunction,
+        args: Vec<Rc<Object>>,
+    ) -> Result<Generator, Rc<Object>> {
+        let args = self.bind_receiver(func, args);
+        if args.len() != 1 {
+            return Err(Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of arguments. got={}, want=1",
+                args.len()
+            )))
+            .into());
+        }
+
+        match *args[0] {
+            Object::Generator(ref generator) => Ok(generator.to_owned()),
+            _ => Err(Object::RuntimeError(RuntimeError::new(format!(
+                "argument to `next` must be GENERATOR, got {}",
+                args[0].kind()
+            )))
+            .into()),
+        }
+    }
+
     pub fn apply_function(&self, func: &str, args: Vec<Rc<Object>>) -> Option<Rc<Object>> {
         match func {
             "len" => Some(self.len(args)),
@@ -21,6 +117,19 @@ impl Builtin {
             "rest" => Some(self.rest(args)),
             "puts" => Some(self.puts(args)),
             "push" => Some(self.push(args)),
+            "gensym" => Some(self.gensym(args)),
+            "ast_kind" => Some(self.ast_kind(args)),
+            "ast_children" => Some(self.ast_children(args)),
+            "ast_call" => Some(self.ast_call(args)),
+            "ast_ident" => Some(self.ast_ident(args)),
+            "ast_let" => Some(self.ast_let(args)),
+            _ if TASK_BUILTINS.contains(&func) => Some(
+                Object::RuntimeError(RuntimeError::new(format!(
+                    "`{}` is only available in the vm engine",
+                    func
+                )))
+                .into(),
+            ),
             _ => None,
         }
     }
@@ -36,11 +145,20 @@ impl Builtin {
 
         match *args[0] {
             Object::Str(ref string) => {
-                Object::Integer(Integer::new(string.value.len() as i64)).into()
+                Object::Integer(Integer::new(string.char_count() as i64)).into()
             }
             Object::Array(ref array) => {
                 Object::Integer(Integer::new(array.elements.len() as i64)).into()
             }
+            Object::Hash(ref hash) => Object::Integer(Integer::new(hash.value.len() as i64)).into(),
+            Object::Range(ref range) => match range.len().and_then(|len| i64::try_from(len).ok()) {
+                Some(len) => Object::Integer(Integer::new(len)).into(),
+                None => Object::RuntimeError(RuntimeError::new(format!(
+                    "range {} is too long for `len`",
+                    range.inspect()
+                )))
+                .into(),
+            },
             _ => Object::RuntimeError(RuntimeError::new(format!(
                 "argument to `len` not supported, got {}",
                 args[0].kind()
@@ -145,6 +263,184 @@ impl Builtin {
         }
     }
 
+    /// Returns a quoted identifier no other name can clash with, for macros to
+    /// splice into the code they expand to. An optional string argument
+    /// gives the name to base it on.
+    pub fn gensym(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
+        let prefix = match args[..] {
+            [] => "g".to_owned(),
+            [ref prefix] => match **prefix {
+                Object::Str(ref prefix) => prefix.value.to_owned(),
+                _ => {
+                    return Object::RuntimeError(RuntimeError::new(format!(
+                        "argument to `gensym` must be STRING, got {}",
+                        prefix.kind()
+                    )))
+                    .into()
+                }
+            },
+            _ => {
+                return Object::RuntimeError(RuntimeError::new(format!(
+                    "wrong number of arguments. got={}, want=0 or 1",
+                    args.len()
+                )))
+                .into()
+            }
+        };
+
+        let name = Identifier::new(makro::gensym(&prefix));
+        Object::Quote(Quote::new(Expression::Identifier(name))).into()
+    }
+
+    fn quoted_argument<'a>(
+        &self,
+        func: &str,
+        args: &'a [Rc<Object>],
+    ) -> Result<&'a Expression, Rc<Object>> {
+        if args.len() != 1 {
+            return Err(Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of arguments. got={}, want=1",
+                args.len()
+            )))
+            .into());
+        }
+
+        match *args[0] {
+            Object::Quote(ref quote) => Ok(&quote.node),
+            _ => Err(Object::RuntimeError(RuntimeError::new(format!(
+                "argument to `{}` must be QUOTE, got {}",
+                func,
+                args[0].kind()
+            )))
+            .into()),
+        }
+    }
+
+    pub fn ast_kind(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
+        match self.quoted_argument("ast_kind", &args) {
+            Ok(node) => Object::Str(Str::new(node.kind().into())).into(),
+            Err(err) => err,
+        }
+    }
+
+    pub fn ast_children(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
+        match self.quoted_argument("ast_children", &args) {
+            Ok(node) => Object::Array(Array::new(
+                node.children()
+                    .into_iter()
+                    .map(|child| Object::Quote(Quote::new(child)).into())
+                    .collect(),
+            ))
+            .into(),
+            Err(err) => err,
+        }
+    }
+
+    /// Builds a call to `name`, which is either a string or quoted code.
+    /// Arguments that aren't already quoted are rendered back into code.
+    pub fn ast_call(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
+        if args.len() != 2 {
+            return Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of arguments. got={}, want=2",
+                args.len()
+            )))
+            .into();
+        }
+
+        let callee = match *args[0] {
+            Object::Str(ref name) => Expression::Identifier(Identifier::new(name.value.to_owned())),
+            Object::Quote(ref quote) => quote.node.to_owned(),
+            _ => {
+                return Object::RuntimeError(RuntimeError::new(format!(
+                    "first argument to `ast_call` must be STRING or QUOTE, got {}",
+                    args[0].kind()
+                )))
+                .into()
+            }
+        };
+
+        match *args[1] {
+            Object::Array(ref array) => {
+                let arguments = array
+                    .elements
+                    .iter()
+                    .map(|e| EvalUnqupteCalls::convert_object_to_ast_node(e))
+                    .collect();
+                let call = CallExpression::new(callee, arguments);
+                Object::Quote(Quote::new(Expression::Call(call))).into()
+            }
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "second argument to `ast_call` must be ARRAY, got {}",
+                args[1].kind()
+            )))
+            .into(),
+        }
+    }
+
+    pub fn ast_ident(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
+        if args.len() != 1 {
+            return Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of arguments. got={}, want=1",
+                args.len()
+            )))
+            .into();
+        }
+
+        match *args[0] {
+            Object::Str(ref name) => Object::Quote(Quote::new(Expression::Identifier(
+                Identifier::new(name.value.to_owned()),
+            )))
+            .into(),
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "argument to `ast_ident` must be STRING, got {}",
+                args[0].kind()
+            )))
+            .into(),
+        }
+    }
+
+    /// Builds a `let` binding `name`, which is either a string or a quoted
+    /// identifier. As a block, a macro returning it at statement level
+    /// defines the name in the caller's scope.
+    pub fn ast_let(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
+        if args.len() != 2 {
+            return Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of arguments. got={}, want=2",
+                args.len()
+            )))
+            .into();
+        }
+
+        let name = match *args[0] {
+            Object::Str(ref name) => Identifier::new(name.value.to_owned()),
+            Object::Quote(Quote {
+                node: Expression::Identifier(ref name),
+            }) => name.to_owned(),
+            _ => {
+                return Object::RuntimeError(RuntimeError::new(format!(
+                    "first argument to `ast_let` must be STRING or quoted identifier, got {}",
+                    args[0].kind()
+                )))
+                .into()
+            }
+        };
+
+        let mut value = match *args[1] {
+            Object::Quote(ref quote) => quote.node.to_owned(),
+            _ => EvalUnqupteCalls::convert_object_to_ast_node(&args[1]),
+        };
+        // Like `let f = fn...`, the function can call itself by name.
+        if let Expression::FunctionLiteral(ref mut func) = value {
+            func.name = name.value.to_owned();
+        }
+
+        let statement = Statement::Let(LetStatement::new(name, value));
+        Object::Quote(Quote::new(Expression::Block(BlockStatement::new(vec![
+            statement,
+        ]))))
+        .into()
+    }
+
     pub fn puts(&self, args: Vec<Rc<Object>>) -> Rc<Object> {
         for arg in args.iter() {
             println!("{}", arg.inspect());
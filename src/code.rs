@@ -35,6 +35,32 @@ pub enum Opcode {
     Closure = 27,
     GetFree = 28,
     CurrentClosure = 29,
+    Slice = 30,
+    TestTag = 31,
+    GetPayload = 32,
+    JumpIfNull = 33,
+    ConcatArrays = 34,
+    MergeHashes = 35,
+    CallSpread = 36,
+    GetIter = 37,
+    IterNext = 38,
+    Unpack = 39,
+    Collect = 40,
+    Range = 41,
+    In = 42,
+    Yield = 43,
+    TailCall = 44,
+    Wide = 45,
+    SetFree = 46,
+    CaptureLocal = 47,
+    CaptureFree = 48,
+    AssignLocal = 49,
+    Import = 50,
+    Export = 51,
+    Member = 52,
+    SetField = 53,
+    CaptureGlobal = 54,
+    AssignGlobal = 55,
 }
 
 impl Opcode {
@@ -70,43 +96,90 @@ impl Opcode {
             27 => Self::Closure,
             28 => Self::GetFree,
             29 => Self::CurrentClosure,
+            30 => Self::Slice,
+            31 => Self::TestTag,
+            32 => Self::GetPayload,
+            33 => Self::JumpIfNull,
+            34 => Self::ConcatArrays,
+            35 => Self::MergeHashes,
+            36 => Self::CallSpread,
+            37 => Self::GetIter,
+            38 => Self::IterNext,
+            39 => Self::Unpack,
+            40 => Self::Collect,
+            41 => Self::Range,
+            42 => Self::In,
+            43 => Self::Yield,
+            44 => Self::TailCall,
+            45 => Self::Wide,
+            46 => Self::SetFree,
+            47 => Self::CaptureLocal,
+            48 => Self::CaptureFree,
+            49 => Self::AssignLocal,
+            50 => Self::Import,
+            51 => Self::Export,
+            52 => Self::Member,
+            53 => Self::SetField,
+            54 => Self::CaptureGlobal,
+            55 => Self::AssignGlobal,
             _ => unreachable!(),
         }
     }
 
-    pub fn make(&self, operands: Vec<u16>) -> Instructions {
+    /// Encodes the instruction, panicking if an operand does not fit its
+    /// width.
+    pub fn make(&self, operands: Vec<usize>) -> Instructions {
         if let Some(def) = DEFINITIONS.get(self) {
-            let mut instruction_len = 1;
-            for w in def.1.iter() {
-                instruction_len += w;
-            }
-
             let mut instruction = Instructions(vec![]);
             instruction.0.push(self.to_owned() as u8);
-
-            for (i, operand) in operands.iter().enumerate() {
-                let width = def.1[i];
-                match width {
-                    1 => instruction.0.write_u8(*operand as u8).unwrap(),
-                    2 => instruction.0.write_u16::<BigEndian>(*operand).unwrap(),
-                    _ => unreachable!(),
-                }
-            }
-
+            instruction.write_operands(def, &operands);
             instruction
         } else {
             Instructions::default()
         }
     }
 
-    pub fn definition(&self) -> &Definition {
+    /// Encodes the instruction behind a `Wide` prefix, which doubles the
+    /// width of each of its operands.
+    pub fn make_wide(&self, operands: Vec<usize>) -> Instructions {
+        let mut instruction = Instructions(vec![Opcode::Wide as u8, self.to_owned() as u8]);
+        instruction.write_operands(&self.definition().wide(), &operands);
+        instruction
+    }
+
+    /// Reports whether the operands fit without a `Wide` prefix.
+    pub fn fits(&self, operands: &[usize]) -> bool {
+        self.definition()
+            .1
+            .iter()
+            .zip(operands.iter())
+            .all(|(width, operand)| fits_in(*operand, *width))
+    }
+
+    pub fn definition(&self) -> &'static Definition {
         DEFINITIONS.get(self).unwrap()
     }
+
+    /// The length of the instruction in bytes, operands included.
+    pub fn width(&self) -> usize {
+        1 + self.definition().1.iter().sum::<u64>() as usize
+    }
+}
+
+fn fits_in(operand: usize, width: u64) -> bool {
+    (operand as u64) < 1 << (8 * width)
 }
 
 #[derive(Debug)]
 pub struct Definition(&'static str, Vec<u64>);
 
+impl Definition {
+    /// The definition of the instruction behind a `Wide` prefix.
+    fn wide(&self) -> Self {
+        Self(self.0, self.1.iter().map(|w| w * 2).collect())
+    }
+}
+
 lazy_static! {
     static ref DEFINITIONS: HashMap<Opcode, Definition> = {
         HashMap::from([
@@ -123,8 +196,8 @@ lazy_static! {
             (Opcode::GreaterThan, Definition("OpGreaterThan", vec![])),
             (Opcode::Minus, Definition("OpMinus", vec![])),
             (Opcode::Bang, Definition("OpBang", vec![])),
-            (Opcode::JumpNotTruth, Definition("OpJumpNotTruth", vec![2])),
-            (Opcode::Jump, Definition("OpJump", vec![2])),
+            (Opcode::JumpNotTruth, Definition("OpJumpNotTruth", vec![4])),
+            (Opcode::Jump, Definition("OpJump", vec![4])),
             (Opcode::Null, Definition("OpNull", vec![])),
             (Opcode::GetGlobal, Definition("OpGetGlobal", vec![2])),
             (Opcode::SetGlobal, Definition("OpSetGlobal", vec![2])),
@@ -133,12 +206,42 @@ lazy_static! {
             (Opcode::Index, Definition("OpIndex", vec![])),
             (Opcode::Call, Definition("OpCall", vec![1])),
             (Opcode::ReturnValue, Definition("OpReturnValue", vec![])),
+            (Opcode::Return, Definition("OpReturn", vec![])),
             (Opcode::GetLocal, Definition("OpGetLocal", vec![1])),
             (Opcode::SetLocal, Definition("OpSetLocal", vec![1])),
             (Opcode::GetBuiltin, Definition("OpGetBuiltin", vec![1])),
             (Opcode::Closure, Definition("OpClosure", vec![2, 1])),
             (Opcode::GetFree, Definition("OpGetFree", vec![1])),
             (Opcode::CurrentClosure, Definition("OpCurrentClosure", vec![])),
+            (Opcode::Slice, Definition("OpSlice", vec![])),
+            (Opcode::TestTag, Definition("OpTestTag", vec![1])),
+            (Opcode::GetPayload, Definition("OpGetPayload", vec![1])),
+            (Opcode::JumpIfNull, Definition("OpJumpIfNull", vec![4])),
+            (Opcode::ConcatArrays, Definition("OpConcatArrays", vec![2])),
+            (Opcode::MergeHashes, Definition("OpMergeHashes", vec![2])),
+            (Opcode::CallSpread, Definition("OpCallSpread", vec![])),
+            (Opcode::GetIter, Definition("OpGetIter", vec![])),
+            (Opcode::IterNext, Definition("OpIterNext", vec![4])),
+            (Opcode::Unpack, Definition("OpUnpack", vec![1])),
+            (Opcode::Collect, Definition("OpCollect", vec![1])),
+            (Opcode::Range, Definition("OpRange", vec![1])),
+            (Opcode::In, Definition("OpIn", vec![])),
+            (Opcode::Yield, Definition("OpYield", vec![])),
+            (Opcode::TailCall, Definition("OpTailCall", vec![1])),
+            (Opcode::Wide, Definition("OpWide", vec![])),
+            (Opcode::SetFree, Definition("OpSetFree", vec![1])),
+            (Opcode::CaptureLocal, Definition("OpCaptureLocal", vec![1])),
+            (Opcode::CaptureFree, Definition("OpCaptureFree", vec![1])),
+            (Opcode::AssignLocal, Definition("OpAssignLocal", vec![1])),
+            (Opcode::Import, Definition("OpImport", vec![2])),
+            (Opcode::Export, Definition("OpExport", vec![])),
+            (Opcode::Member, Definition("OpMember", vec![])),
+            (Opcode::SetField, Definition("OpSetField", vec![2])),
+            (
+                Opcode::CaptureGlobal,
+                Definition("OpCaptureGlobal", vec![2]),
+            ),
+            (Opcode::AssignGlobal, Definition("OpAssignGlobal", vec![2])),
         ])
     };
 }
@@ -167,27 +270,64 @@ impl Instructions {
         BigEndian::read_u16(&self.0[pos..])
     }
 
+    pub fn read_u32_from(&self, pos: usize) -> u32 {
+        BigEndian::read_u32(&self.0[pos..])
+    }
+
+    /// Reads an operand of `width` bytes at `pos`.
+    pub fn read_operand(&self, pos: usize, width: usize) -> usize {
+        match width {
+            1 => self.read_u8_from(pos) as usize,
+            2 => self.read_u16_from(pos) as usize,
+            4 => self.read_u32_from(pos) as usize,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The length in bytes of the instruction at `pos`, including any `Wide`
+    /// prefix.
+    pub fn width_at(&self, pos: usize) -> usize {
+        match self.read_op_at(pos) {
+            Opcode::Wide => 2 + (self.read_op_at(pos + 1).width() - 1) * 2,
+            op => op.width(),
+        }
+    }
+
     pub fn read_op_at(&self, pos: usize) -> Opcode {
         Opcode::from(self.0[pos])
     }
 
-    pub fn read_operands(&self, def: &Definition) -> (Vec<u16>, usize) {
+    pub fn read_operands(&self, def: &Definition) -> (Vec<usize>, usize) {
         let mut operands = vec![0; def.1.len()];
 
         let mut offset = 0;
         for (i, width) in def.1.iter().enumerate() {
-            match width {
-                1 => operands[i] = self.0[offset + 1] as u16,
-                2 => operands[i] = BigEndian::read_u16(&self.0[offset + 1..]),
-                _ => unreachable!(),
-            }
+            operands[i] = self.read_operand(offset + 1, *width as usize);
             offset += width.to_owned() as usize;
         }
 
         (operands, offset)
     }
 
-    fn fmt_instruction(&self, def: &Definition, operands: &Vec<u16>) -> String {
+    fn write_operands(&mut self, def: &Definition, operands: &[usize]) {
+        for (i, operand) in operands.iter().enumerate() {
+            let width = def.1[i];
+            if !fits_in(*operand, width) {
+                panic!(
+                    "operand {} of {} does not fit in {} bytes",
+                    operand, def.0, width
+                );
+            }
+            match width {
+                1 => self.0.write_u8(*operand as u8).unwrap(),
+                2 => self.0.write_u16::<BigEndian>(*operand as u16).unwrap(),
+                4 => self.0.write_u32::<BigEndian>(*operand as u32).unwrap(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn fmt_instruction(&self, def: &Definition, operands: &Vec<usize>) -> String {
         let operand_count = def.1.len();
         if operands.len() != operand_count {
             format!(
@@ -217,20 +357,27 @@ impl Display for Instructions {
         let mut out = String::new();
         let mut i = 0;
         while i < self.0.len() {
-            let op = Opcode::from(self.0[i]);
-            let def = op.definition();
-            let (operands, read) = &self
+            let wide_def;
+            let (start, prefix, def) = match Opcode::from(self.0[i]) {
+                Opcode::Wide => {
+                    wide_def = Opcode::from(self.0[i + 1]).definition().wide();
+                    (i + 1, "OpWide ", &wide_def)
+                }
+                op => (i, "", op.definition()),
+            };
+            let (operands, _) = &self
                 .take_range(Range {
-                    start: i,
+                    start,
                     end: self.0.len(),
                 })
                 .read_operands(def);
             out.push_str(&format!(
-                "{:04} {}\n",
+                "{:04} {}{}\n",
                 i,
+                prefix,
                 self.fmt_instruction(def, operands)
             ));
-            i += 1 + read;
+            i += self.width_at(i);
         }
         write!(f, "{}", out)
     }
@@ -248,12 +395,16 @@ mod tests {
             Opcode::Constant.make(vec![2]),
             Opcode::Constant.make(vec![65535]),
             Opcode::Closure.make(vec![65535, 255]),
+            Opcode::GetLocal.make_wide(vec![256]),
+            Opcode::Jump.make(vec![70000]),
         ];
         let expected = "0000 OpAdd
 0001 OpGetLocal 1
 0003 OpConstant 2
 0006 OpConstant 65535
 0009 OpClosure 65535 255
+0013 OpWide OpGetLocal 256
+0017 OpJump 70000
 ";
         let concated = Instructions(instructions.iter().flat_map(|i| i.0.to_owned()).collect());
         assert_eq!(format!("{}", concated), expected);
@@ -278,6 +429,11 @@ mod tests {
                 vec![65534, 255],
                 Instructions(vec![Opcode::Closure as u8, 0xFF, 0xFE, 0xFF]),
             ),
+            (
+                Opcode::Jump,
+                vec![65536],
+                Instructions(vec![Opcode::Jump as u8, 0x00, 0x01, 0x00, 0x00]),
+            ),
         ];
 
         for test in tests.iter() {
@@ -287,12 +443,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_make_wide() {
+        let instruction = Opcode::Closure.make_wide(vec![65536, 256]);
+        assert_eq!(
+            instruction,
+            Instructions(vec![
+                Opcode::Wide as u8,
+                Opcode::Closure as u8,
+                0x00,
+                0x01,
+                0x00,
+                0x00,
+                0x01,
+                0x00
+            ])
+        );
+        assert_eq!(instruction.width_at(0), 8);
+
+        assert!(Opcode::GetLocal.fits(&[255]));
+        assert!(!Opcode::GetLocal.fits(&[256]));
+    }
+
+    #[test]
+    #[should_panic(expected = "operand 256 of OpGetLocal does not fit in 1 bytes")]
+    fn test_make_rejects_operands_that_do_not_fit() {
+        Opcode::GetLocal.make(vec![256]);
+    }
+
     #[test]
     fn test_read_operands() {
         let tests = [
             (Opcode::Constant, vec![65534], 2),
             (Opcode::GetLocal, vec![255], 1),
             (Opcode::Closure, vec![65535, 255], 3),
+            (Opcode::TestTag, vec![2], 1),
+            (Opcode::GetPayload, vec![255], 1),
         ];
 
         for (op, operands, bytes_read) in tests.iter() {
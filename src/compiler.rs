@@ -1,15 +1,20 @@
-use std::{cell::RefCell, ops::Range, rc::Rc};
+use std::{cell::RefCell, mem, ops::Range, path::Path, rc::Rc};
 
 use crate::{
     ast::{
-        ArrayLiteral, BlockStatement, BooleanExpression, CallExpression, Expression,
-        ExpressionStatement, FunctionLiteral, HashLiteral, IfExpression, IndexExpression,
-        InfixExpression, IntegerLiteral, LetStatement, PrefixExpression, Program, ReturnStatement,
-        Statement, StringLiteral,
+        ArrayLiteral, AssignExpression, BlockStatement, BooleanExpression, CallExpression,
+        ComprehensionClause, EnumStatement, Expression, ExpressionStatement, FunctionLiteral,
+        HashComprehension, HashLiteral, IfExpression, ImportStatement, IndexExpression,
+        InfixExpression, IntegerLiteral, LetStatement, ListComprehension, MatchExpression, Pattern,
+        PrefixExpression, Program, ReturnStatement, SliceExpression, Statement, StringLiteral,
+        StructStatement,
     },
     builtin::BUILTINS,
     code::{Instructions, Opcode},
-    object::{CompiledFunction, Integer, Object, Str},
+    module::Modules,
+    object::{
+        CompiledFunction, Integer, Object, Str, StructDefinition, Variant, VariantDefinition,
+    },
     symbol_table::{Symbol, SymbolScope, SymbolTable},
 };
 
@@ -40,6 +45,14 @@ pub struct Compiler {
     pub scope_index: usize,
 
     pub symbol_table: Rc<RefCell<SymbolTable>>,
+
+    pub errors: Vec<String>,
+
+    /// The constant index of each imported module's compiled body, shared
+    /// with the compilers of those modules.
+    modules: Rc<RefCell<Modules<usize>>>,
+    /// The module being compiled, 0 being the main program.
+    module: usize,
 }
 
 impl Compiler {
@@ -57,6 +70,11 @@ impl Compiler {
 
             scopes: vec![main_scope],
             scope_index: 0,
+
+            errors: vec![],
+
+            modules: Rc::new(RefCell::new(Modules::default())),
+            module: 0,
         }
     }
 
@@ -64,11 +82,18 @@ impl Compiler {
         Self {
             symbol_table: other.symbol_table,
             constants: other.constants,
+            modules: other.modules,
 
             ..Self::new()
         }
     }
 
+    /// Sets the file the program being compiled was read from, so its
+    /// imports resolve relative to it.
+    pub fn set_main_file(&mut self, path: &Path) {
+        self.modules.borrow_mut().set_main_file(path);
+    }
+
     pub fn compile(&mut self, node: &Program) {
         self.compile_program(node);
     }
@@ -85,27 +110,165 @@ impl Compiler {
         }
     }
 
+    /// Compiles a block in a scope of its own, so the names it defines are
+    /// not visible after it.
+    fn compile_scoped_block(&mut self, node: &BlockStatement) {
+        self.symbol_table.borrow_mut().enter_block();
+        self.compile_block_statsment(node);
+        self.symbol_table.borrow_mut().leave_block();
+    }
+
+    /// Leaves the value of the block just compiled on the stack: that of its
+    /// last expression, or null if it ends in some other statement.
+    fn keep_block_value(&mut self) {
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_pop();
+        } else if !self.last_instruction_is(Opcode::ReturnValue) {
+            self.emit(Opcode::Null, vec![]);
+        }
+    }
+
     fn compile_statement(&mut self, node: &Statement) {
         match node {
             Statement::Let(node) => self.compile_let_statement(node),
             Statement::Return(node) => self.compile_return_statement(node),
             Statement::Expression(node) => self.compile_expression_statement(node),
+            Statement::Struct(node) => self.compile_struct_statement(node),
+            Statement::Enum(node) => self.compile_enum_statement(node),
+            Statement::Import(node) => self.compile_import_statement(node),
+            Statement::Export(node) => self.compile_statement(&node.statement),
+        }
+    }
+
+    /// Defines `name` in the current scope, recording an error if that would
+    /// rebind a constant or make an existing binding constant.
+    fn define(&mut self, name: &str, is_const: bool) -> Rc<Symbol> {
+        let existing = self.symbol_table.borrow().defined_in_scope(name);
+        match existing {
+            Some(ref symbol) if symbol.is_const => {
+                self.errors
+                    .push(format!("cannot redefine constant {}", name));
+            }
+            Some(_) if is_const => {
+                self.errors
+                    .push(format!("cannot redefine {} as a constant", name));
+            }
+            _ => {}
+        }
+
+        let mut symbol_table = self.symbol_table.borrow_mut();
+        if is_const {
+            symbol_table.define_const(name)
+        } else {
+            symbol_table.define(name)
         }
     }
 
     fn compile_let_statement(&mut self, node: &LetStatement) {
-        let symbol = self
+        let rebinding = self
             .symbol_table
-            .borrow_mut()
-            .define(node.name.value.as_str());
+            .borrow()
+            .defined_in_scope(node.name.value.as_str())
+            .is_some();
+        let symbol = self.define(node.name.value.as_str(), node.is_const);
         self.compile_expression(&node.value);
-        let symbol_index = symbol.index;
-        let opcode = match symbol.scope {
-            SymbolScope::Global => Opcode::SetGlobal,
-            SymbolScope::Local => Opcode::SetLocal,
-            _ => unreachable!(),
+        if rebinding {
+            self.assign_symbol(&symbol);
+        } else {
+            self.store_symbol(&symbol);
+        }
+    }
+
+    fn compile_struct_statement(&mut self, node: &StructStatement) {
+        let symbol = self.define(node.name.value.as_str(), false);
+        let definition = StructDefinition::new(
+            node.name.value.to_owned(),
+            node.fields.iter().map(|f| f.value.to_owned()).collect(),
+        );
+        let const_index = self.add_constant(Object::StructDefinition(definition));
+        self.emit(Opcode::Constant, vec![const_index]);
+        self.store_symbol(&symbol);
+    }
+
+    fn compile_enum_statement(&mut self, node: &EnumStatement) {
+        for variant in node.variants.iter() {
+            let symbol = self.define(variant.name.value.as_str(), false);
+            let definition = VariantDefinition::new(
+                node.name.value.to_owned(),
+                variant.name.value.to_owned(),
+                variant.fields.iter().map(|f| f.value.to_owned()).collect(),
+            );
+            let value = if definition.fields.is_empty() {
+                Object::Variant(Variant::new(definition, vec![]))
+            } else {
+                Object::VariantDefinition(definition)
+            };
+            let const_index = self.add_constant(value);
+            self.emit(Opcode::Constant, vec![const_index]);
+            self.store_symbol(&symbol);
+        }
+    }
+
+    fn compile_import_statement(&mut self, node: &ImportStatement) {
+        let result = self.import(&node.path);
+        // Defined either way, so the rest of the program still compiles.
+        let symbol = self.define(node.name.value.as_str(), false);
+        match result {
+            Ok(const_index) => {
+                self.emit(Opcode::Import, vec![const_index]);
+                self.store_symbol(&symbol);
+            }
+            Err(message) => self.errors.push(message),
+        }
+    }
+
+    /// Compiles the module at `path` the first time it is imported, into a
+    /// function with globals of its own that returns a hash of the names the
+    /// module exports. Returns the function's constant index.
+    fn import(&mut self, path: &str) -> Result<usize, String> {
+        let path = self.modules.borrow().resolve(path)?;
+        if let Some(const_index) = self.modules.borrow().get(&path) {
+            return Ok(const_index);
+        }
+
+        let module = self.modules.borrow_mut().load(path)?;
+        let mut compiler = Compiler {
+            constants: mem::take(&mut self.constants),
+            modules: Rc::clone(&self.modules),
+            module: module.id,
+            ..Compiler::new()
         };
-        self.emit(opcode, vec![symbol_index]);
+        compiler.compile(&module.program);
+        let exports = module.exports();
+        for name in exports.iter() {
+            let symbol = compiler
+                .symbol_table
+                .borrow_mut()
+                .resolve(name)
+                .expect("exported names are defined by the module");
+            let key = compiler.add_constant(Object::Str(Str::new(name.to_owned())));
+            compiler.emit(Opcode::Constant, vec![key]);
+            compiler.load_symbol(&symbol);
+        }
+        compiler.emit(Opcode::Hash, vec![exports.len() * 2]);
+        compiler.emit(Opcode::Export, vec![]);
+        compiler.emit(Opcode::ReturnValue, vec![]);
+        self.constants = mem::take(&mut compiler.constants);
+
+        if !compiler.errors.is_empty() {
+            self.modules.borrow_mut().finish(&module, None);
+            return Err(format!(
+                "{}: {}",
+                module.path.display(),
+                compiler.errors.join(", ")
+            ));
+        }
+
+        let mut body = CompiledFunction::new(compiler.bytecode().instructions, 0, 0);
+        body.module = module.id;
+        let const_index = self.add_constant(Object::CompiledFunction(body));
+        self.modules.borrow_mut().finish(&module, Some(const_index));
+        Ok(const_index)
     }
 
     fn compile_return_statement(&mut self, node: &ReturnStatement) {
@@ -122,7 +285,9 @@ impl Compiler {
         match node {
             Expression::IntegerLiteral(node) => self.compile_integer_literal(node),
             Expression::StringLiteral(node) => self.compile_string_literal(node),
-            Expression::NullLiteral(_) => todo!(),
+            Expression::NullLiteral(_) => {
+                self.emit(Opcode::Null, vec![]);
+            }
             Expression::ArrayLiteral(node) => self.compile_array_literal(node),
             Expression::HashLiteral(node) => self.compile_hash_literal(node),
             Expression::FunctionLiteral(node) => self.compile_function_literal(node),
@@ -137,13 +302,38 @@ impl Compiler {
             Expression::Infix(node) => self.compile_infix_expression(node),
             Expression::Prefix(node) => self.compile_prefix_expression(node),
             Expression::If(node) => self.compile_if_expression(node),
-            Expression::Call(node) => self.compile_call_expression(node),
-            Expression::Index(node) => self.compile_index_expression(node),
-            Expression::MacroLiteral(_) => unreachable!(),
+            Expression::Match(node) => self.compile_match_expression(node),
+            // Only macros can put a spread here, as the parser rejects one.
+            Expression::Spread(_) => {
+                self.errors
+                    .push("spread operator is not allowed here".into());
+                self.emit(Opcode::Null, vec![]);
+            }
+            Expression::ListComprehension(node) => self.compile_list_comprehension(node),
+            Expression::HashComprehension(node) => self.compile_hash_comprehension(node),
+            Expression::Call(_) | Expression::Index(_) | Expression::Slice(_) => {
+                self.compile_chain(node)
+            }
+            // Macro expansion takes out the definitions it can use. Any left,
+            // like those in a function body, are null as in the evaluator.
+            Expression::MacroLiteral(_) => {
+                self.emit(Opcode::Null, vec![]);
+            }
+            Expression::Assign(node) => self.compile_assign_expression(node),
+            Expression::Group(node) => self.compile_expression(&node.value),
+            Expression::Block(node) => self.compile_block_expression(node),
+            Expression::Yield(node) => {
+                self.compile_expression(&node.value);
+                self.emit(Opcode::Yield, vec![]);
+            }
         }
     }
 
     fn compile_infix_expression(&mut self, node: &InfixExpression) {
+        if &node.operator == "??" {
+            return self.compile_null_coalesce_expression(node);
+        }
+
         if &node.operator == "<" {
             self.compile_expression(&node.right);
             self.compile_expression(&node.left);
@@ -160,10 +350,29 @@ impl Compiler {
             ">" | "<" => self.emit(Opcode::GreaterThan, vec![]),
             "==" => self.emit(Opcode::Equal, vec![]),
             "!=" => self.emit(Opcode::NotEqual, vec![]),
+            ".." => self.emit(Opcode::Range, vec![0]),
+            "..=" => self.emit(Opcode::Range, vec![1]),
+            "in" => self.emit(Opcode::In, vec![]),
             _ => panic!("unknown operator {}", node.operator),
         };
     }
 
+    fn compile_null_coalesce_expression(&mut self, node: &InfixExpression) {
+        self.compile_expression(&node.left);
+
+        let jump_if_null_pos = self.emit(Opcode::JumpIfNull, vec![9999]);
+        let jump_pos = self.emit(Opcode::Jump, vec![9999]);
+
+        let null_pos = self.current_scope().instructions.len();
+        self.change_operand(jump_if_null_pos, null_pos);
+
+        self.emit(Opcode::Pop, vec![]);
+        self.compile_expression(&node.right);
+
+        let after_right_pos = self.current_scope().instructions.len();
+        self.change_operand(jump_pos, after_right_pos);
+    }
+
     fn compile_prefix_expression(&mut self, node: &PrefixExpression) {
         self.compile_expression(&node.right);
         match node.operator.as_str() {
@@ -178,44 +387,217 @@ impl Compiler {
 
         let jump_not_truth_pos = self.emit(Opcode::JumpNotTruth, vec![9999]);
 
-        self.compile_block_statsment(&node.consequence);
-
-        if self.last_instruction_is(Opcode::Pop) {
-            self.remove_last_pop();
-        }
+        self.compile_scoped_block(&node.consequence);
+        self.keep_block_value();
 
         let jump_pos = self.emit(Opcode::Jump, vec![9999]);
 
         let after_consequence_pos = self.current_scope().instructions.len();
-        self.change_operand(jump_not_truth_pos, after_consequence_pos as u16);
+        self.change_operand(jump_not_truth_pos, after_consequence_pos);
 
         if let Some(ref alternative) = node.alternative {
-            self.compile_block_statsment(alternative);
-
-            if self.last_instruction_is(Opcode::Pop) {
-                self.remove_last_pop();
-            }
+            self.compile_scoped_block(alternative);
+            self.keep_block_value();
         } else {
             self.emit(Opcode::Null, vec![]);
         }
 
         let afte_alternative_pos = self.current_scope().instructions.len();
-        self.change_operand(jump_pos, afte_alternative_pos as u16);
+        self.change_operand(jump_pos, afte_alternative_pos);
+    }
+
+    fn compile_block_expression(&mut self, node: &BlockStatement) {
+        if node.statements.is_empty() {
+            self.emit(Opcode::Null, vec![]);
+        } else {
+            self.compile_block_statsment(node);
+            self.keep_block_value();
+        }
     }
 
-    fn compile_call_expression(&mut self, node: &CallExpression) {
-        self.compile_expression(&node.callee);
+    fn compile_match_expression(&mut self, node: &MatchExpression) {
+        // The subject stays on the stack while arms test it and is popped
+        // once an arm is taken.
+        self.compile_expression(&node.subject);
+
+        let mut jump_positions = vec![];
+
+        for arm in node.arms.iter() {
+            let mut jump_not_truth_pos = None;
+
+            // The payload bindings are scoped to the arm along with its body.
+            self.symbol_table.borrow_mut().enter_block();
+
+            if let Pattern::Variant(ref name, ref bindings) = arm.pattern {
+                // The subject is tested against the variant the name is bound
+                // to, so variants of other enums with the same name differ.
+                let symbol = self.symbol_table.borrow_mut().resolve(&name.value);
+                match symbol {
+                    Some(ref symbol) => self.load_symbol(symbol),
+                    None => {
+                        self.errors.push(format!("unknown variant {}", name.value));
+                        self.emit(Opcode::Null, vec![]);
+                    }
+                }
+                self.emit(Opcode::TestTag, vec![bindings.len()]);
+                jump_not_truth_pos = Some(self.emit(Opcode::JumpNotTruth, vec![9999]));
+
+                for (i, binding) in bindings.iter().enumerate() {
+                    self.emit(Opcode::GetPayload, vec![i]);
+                    let symbol = self.define(binding.value.as_str(), false);
+                    self.store_symbol(&symbol);
+                }
+            }
+
+            self.emit(Opcode::Pop, vec![]);
+            self.compile_block_statsment(&arm.body);
+            self.symbol_table.borrow_mut().leave_block();
+
+            if let Some(Statement::Expression(_)) = arm.body.statements.last() {
+                self.remove_last_pop();
+            } else {
+                self.emit(Opcode::Null, vec![]);
+            }
+
+            jump_positions.push(self.emit(Opcode::Jump, vec![9999]));
+
+            if let Some(pos) = jump_not_truth_pos {
+                let after_arm_pos = self.current_scope().instructions.len();
+                self.change_operand(pos, after_arm_pos);
+            }
+        }
+
+        self.emit(Opcode::Pop, vec![]);
+        self.emit(Opcode::Null, vec![]);
+
+        let after_match_pos = self.current_scope().instructions.len();
+        for pos in jump_positions {
+            self.change_operand(pos, after_match_pos);
+        }
+    }
+
+    fn compile_list_comprehension(&mut self, node: &ListComprehension) {
+        self.emit(Opcode::Array, vec![0]);
+        self.compile_comprehension_clause(&node.clause, |this| {
+            this.compile_expression(&node.element);
+            this.emit(Opcode::Collect, vec![1]);
+        });
+    }
+
+    fn compile_hash_comprehension(&mut self, node: &HashComprehension) {
+        self.emit(Opcode::Hash, vec![0]);
+        self.compile_comprehension_clause(&node.clause, |this| {
+            this.compile_expression(&node.key);
+            this.compile_expression(&node.value);
+            this.emit(Opcode::Collect, vec![2]);
+        });
+    }
+
+    /// Compiles the loop around `collect`. The iterated items and the loop
+    /// index sit on the stack above the accumulator until `IterNext` runs out
+    /// and pops them, leaving the accumulator as the result.
+    fn compile_comprehension_clause(
+        &mut self,
+        clause: &ComprehensionClause,
+        collect: impl FnOnce(&mut Self),
+    ) {
+        self.compile_expression(&clause.iterable);
+        self.emit(Opcode::GetIter, vec![]);
+
+        let loop_start = self.current_scope().instructions.len();
+        let iter_next_pos = self.emit(Opcode::IterNext, vec![9999]);
+
+        if clause.bindings.len() > 1 {
+            self.emit(Opcode::Unpack, vec![clause.bindings.len()]);
+        }
+
+        // The bindings are scoped to the comprehension.
+        self.symbol_table.borrow_mut().enter_block();
+        let symbols: Vec<Rc<Symbol>> = clause
+            .bindings
+            .iter()
+            .map(|binding| self.define(binding.value.as_str(), false))
+            .collect();
+        for symbol in symbols.iter().rev() {
+            self.store_symbol(symbol);
+        }
+
+        if let Some(ref condition) = clause.condition {
+            self.compile_expression(condition);
+            self.emit(Opcode::JumpNotTruth, vec![loop_start]);
+        }
+
+        collect(self);
+        self.symbol_table.borrow_mut().leave_block();
+        self.emit(Opcode::Jump, vec![loop_start]);
+
+        let after_loop_pos = self.current_scope().instructions.len();
+        self.change_operand(iter_next_pos, after_loop_pos);
+    }
+
+    /// Compiles a chain of calls, indexes and slices such as `a?.b.c(d)`. A
+    /// `?.` or `?[` that finds null skips the rest of the chain, which is then
+    /// null as well.
+    fn compile_chain(&mut self, node: &Expression) {
+        let mut null_jumps = vec![];
+        self.compile_chain_link(node, &mut null_jumps);
+
+        let after_chain_pos = self.current_scope().instructions.len();
+        for pos in null_jumps {
+            self.change_operand(pos, after_chain_pos);
+        }
+    }
+
+    fn compile_chain_link(&mut self, node: &Expression, null_jumps: &mut Vec<usize>) {
+        match node {
+            Expression::Call(node) => self.compile_call_expression(node, null_jumps),
+            Expression::Index(node) => self.compile_index_expression(node, null_jumps),
+            Expression::Slice(node) => self.compile_slice_expression(node, null_jumps),
+            _ => self.compile_expression(node),
+        }
+    }
+
+    fn compile_call_expression(&mut self, node: &CallExpression, null_jumps: &mut Vec<usize>) {
+        self.compile_chain_link(&node.callee, null_jumps);
+        if node.arguments.iter().any(|a| matches!(a, Expression::Spread(_))) {
+            self.compile_spread_elements(&node.arguments);
+            self.emit(Opcode::CallSpread, vec![]);
+            return;
+        }
         for arg in node.arguments.iter() {
             self.compile_expression(arg);
         }
-        self.emit(Opcode::Call, vec![node.arguments.len() as u16]);
+        self.emit(Opcode::Call, vec![node.arguments.len()]);
     }
 
-    fn compile_index_expression(&mut self, node: &IndexExpression) {
-        self.compile_expression(&node.left);
+    fn compile_index_expression(&mut self, node: &IndexExpression, null_jumps: &mut Vec<usize>) {
+        self.compile_chain_link(&node.left, null_jumps);
+
+        if node.optional {
+            null_jumps.push(self.emit(Opcode::JumpIfNull, vec![9999]));
+        }
+
         self.compile_expression(&node.index);
 
-        self.emit(Opcode::Index, vec![]);
+        if node.member {
+            self.emit(Opcode::Member, vec![]);
+        } else {
+            self.emit(Opcode::Index, vec![]);
+        }
+    }
+
+    fn compile_slice_expression(&mut self, node: &SliceExpression, null_jumps: &mut Vec<usize>) {
+        self.compile_chain_link(&node.left, null_jumps);
+        for bound in [&node.start, &node.end] {
+            match bound {
+                Some(ref bound) => self.compile_expression(bound),
+                None => {
+                    self.emit(Opcode::Null, vec![]);
+                }
+            }
+        }
+
+        self.emit(Opcode::Slice, vec![]);
     }
 
     fn compile_integer_literal(&mut self, node: &IntegerLiteral) {
@@ -231,20 +613,80 @@ impl Compiler {
     }
 
     fn compile_array_literal(&mut self, node: &ArrayLiteral) {
+        if node.elements.iter().any(|e| matches!(e, Expression::Spread(_))) {
+            return self.compile_spread_elements(&node.elements);
+        }
         for el in node.elements.iter() {
             self.compile_expression(el);
         }
-        self.emit(Opcode::Array, vec![node.elements.len() as u16]);
+        self.emit(Opcode::Array, vec![node.elements.len()]);
+    }
+
+    /// Leaves one array holding `elements` on the stack. Runs of plain
+    /// elements are built with `Array` and joined with the spread arrays by
+    /// `ConcatArrays`.
+    fn compile_spread_elements(&mut self, elements: &[Expression]) {
+        let mut segments = 0;
+        let mut run = 0;
+        for el in elements.iter() {
+            if let Expression::Spread(ref spread) = *el {
+                if run > 0 {
+                    self.emit(Opcode::Array, vec![run]);
+                    segments += 1;
+                    run = 0;
+                }
+                self.compile_expression(&spread.value);
+                segments += 1;
+            } else {
+                self.compile_expression(el);
+                run += 1;
+            }
+        }
+        if run > 0 {
+            self.emit(Opcode::Array, vec![run]);
+            segments += 1;
+        }
+        self.emit(Opcode::ConcatArrays, vec![segments]);
     }
 
     fn compile_hash_literal(&mut self, node: &HashLiteral) {
+        if node.members.iter().any(|m| m.is_spread()) {
+            return self.compile_spread_members(node);
+        }
         let mut members = node.members.to_owned();
         members.sort_by(|a, b| format!("{}", a.key).cmp(&format!("{}", b.key)));
         for member in members.iter() {
             self.compile_expression(&member.key);
             self.compile_expression(&member.value);
         }
-        self.emit(Opcode::Hash, vec![node.members.len() as u16 * 2]);
+        self.emit(Opcode::Hash, vec![node.members.len() * 2]);
+    }
+
+    /// Like `compile_spread_elements` but for hashes. Members keep their
+    /// source order so that later keys override earlier ones.
+    fn compile_spread_members(&mut self, node: &HashLiteral) {
+        let mut segments = 0;
+        let mut run = 0;
+        for member in node.members.iter() {
+            if let Expression::Spread(ref spread) = member.key {
+                if run > 0 {
+                    self.emit(Opcode::Hash, vec![run * 2]);
+                    segments += 1;
+                    run = 0;
+                }
+                self.compile_expression(&spread.value);
+                segments += 1;
+            } else {
+                self.compile_expression(&member.key);
+                self.compile_expression(&member.value);
+                run += 1;
+            }
+        }
+        if run > 0 {
+            self.emit(Opcode::Hash, vec![run * 2]);
+            segments += 1;
+        }
+        self.emit(Opcode::MergeHashes, vec![segments]);
     }
 
     fn compile_function_literal(&mut self, node: &FunctionLiteral) {
@@ -266,19 +708,24 @@ impl Compiler {
         if !self.last_instruction_is(Opcode::ReturnValue) {
             self.emit(Opcode::Return, vec![]);
         }
+        if !node.is_generator {
+            self.mark_tail_calls();
+        }
 
         let free_symbols = self.symbol_table.borrow().free_symbols.to_owned();
-        let num_locals = self.symbol_table.borrow().num_definitions;
+        let num_locals = self.symbol_table.borrow().max_definitions;
         let instructions = self.leave_scope();
 
         for sym in free_symbols.iter() {
-            self.load_symbol(sym);
+            self.capture_symbol(sym);
         }
 
-        let pos = self.add_constant(Object::CompiledFunction(
-            CompiledFunction::new(instructions, num_locals, node.parameters.len() as u8)
-        ));
-        self.emit(Opcode::Closure, vec![pos, free_symbols.len() as u16]);
+        let mut func =
+            CompiledFunction::new(instructions, num_locals, node.parameters.len() as u16);
+        func.is_generator = node.is_generator;
+        func.module = self.module;
+        let pos = self.add_constant(Object::CompiledFunction(func));
+        self.emit(Opcode::Closure, vec![pos, free_symbols.len()]);
     }
 
     fn compile_boolean_expression(&mut self, node: &BooleanExpression) {
@@ -290,17 +737,106 @@ impl Compiler {
 
     fn load_symbol(&mut self, symbol: &Symbol) {
         match symbol.scope {
-            SymbolScope::Global => self.emit(Opcode::GetGlobal, vec![symbol.index.to_owned()]),
-            SymbolScope::Local => self.emit(Opcode::GetLocal, vec![symbol.index.to_owned()]),
-            SymbolScope::Builtin => self.emit(Opcode::GetBuiltin, vec![symbol.index.to_owned()]),
-            SymbolScope::Free => self.emit(Opcode::GetFree, vec![symbol.index.to_owned()]),
+            SymbolScope::Global | SymbolScope::BlockGlobal => {
+                self.emit(Opcode::GetGlobal, vec![symbol.index as usize])
+            }
+            SymbolScope::Local => self.emit(Opcode::GetLocal, vec![symbol.index as usize]),
+            SymbolScope::Builtin => self.emit(Opcode::GetBuiltin, vec![symbol.index as usize]),
+            SymbolScope::Free => self.emit(Opcode::GetFree, vec![symbol.index as usize]),
             SymbolScope::Function => self.emit(Opcode::CurrentClosure, vec![]),
         };
     }
 
-    fn add_constant(&mut self, object: Object) -> u16 {
+    fn store_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global | SymbolScope::BlockGlobal => {
+                self.emit(Opcode::SetGlobal, vec![symbol.index as usize])
+            }
+            SymbolScope::Local => self.emit(Opcode::SetLocal, vec![symbol.index as usize]),
+            _ => unreachable!(),
+        };
+    }
+
+    /// Stores into an existing variable. Unlike `store_symbol`, which starts a
+    /// new binding, this writes through to closures sharing the variable.
+    fn assign_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Opcode::SetGlobal, vec![symbol.index as usize]),
+            SymbolScope::BlockGlobal => {
+                self.emit(Opcode::AssignGlobal, vec![symbol.index as usize])
+            }
+            SymbolScope::Local => self.emit(Opcode::AssignLocal, vec![symbol.index as usize]),
+            SymbolScope::Free => self.emit(Opcode::SetFree, vec![symbol.index as usize]),
+            _ => unreachable!(),
+        };
+    }
+
+    /// Loads a variable for the closure being created to capture. Locals, block
+    /// globals and free variables are captured as shared cells rather than by
+    /// value, so assignments on either side are seen by both.
+    fn capture_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::BlockGlobal => {
+                self.emit(Opcode::CaptureGlobal, vec![symbol.index as usize]);
+            }
+            SymbolScope::Local => {
+                self.emit(Opcode::CaptureLocal, vec![symbol.index as usize]);
+            }
+            SymbolScope::Free => {
+                self.emit(Opcode::CaptureFree, vec![symbol.index as usize]);
+            }
+            _ => self.load_symbol(symbol),
+        }
+    }
+
+    fn compile_assign_expression(&mut self, node: &AssignExpression) {
+        let symbol = self.symbol_table.borrow_mut().resolve(&node.name.value);
+        let symbol = match symbol {
+            Some(symbol)
+                if matches!(
+                    symbol.scope,
+                    SymbolScope::Global
+                        | SymbolScope::BlockGlobal
+                        | SymbolScope::Local
+                        | SymbolScope::Free
+                ) =>
+            {
+                symbol
+            }
+            symbol => {
+                let error = match symbol {
+                    Some(_) => "cannot assign to",
+                    None => "undefined variable",
+                };
+                self.errors.push(format!("{} {}", error, node.name.value));
+                // The value still stands in for the assignment.
+                return self.compile_expression(&node.value);
+            }
+        };
+        if symbol.is_const {
+            self.errors
+                .push(format!("cannot assign to constant {}", node.name.value));
+        }
+
+        self.compile_expression(&node.value);
+        match node.field {
+            // Leaves the value under the updated struct, which is stored.
+            Some(ref field) => {
+                let field = self.add_constant(Object::Str(Str::new(field.to_owned())));
+                self.load_symbol(&symbol);
+                self.emit(Opcode::SetField, vec![field]);
+                self.assign_symbol(&symbol);
+            }
+            None => {
+                self.assign_symbol(&symbol);
+                self.load_symbol(&symbol);
+            }
+        }
+    }
+
+    fn add_constant(&mut self, object: Object) -> usize {
         self.constants.push(object);
-        (self.constants.len() - 1).try_into().unwrap()
+        self.constants.len() - 1
     }
 
     fn add_instructions(&mut self, ins: &mut Instructions) -> usize {
@@ -310,8 +846,12 @@ impl Compiler {
         new_ins_pos
     }
 
-    fn emit(&mut self, op: Opcode, operands: Vec<u16>) -> usize {
-        let mut ins = op.make(operands);
+    fn emit(&mut self, op: Opcode, operands: Vec<usize>) -> usize {
+        let mut ins = if op.fits(&operands) {
+            op.make(operands)
+        } else {
+            op.make_wide(operands)
+        };
         let pos = self.add_instructions(&mut ins);
         self.set_last_instruction(op, pos);
         pos
@@ -360,7 +900,25 @@ impl Compiler {
         self.replace_instruction(las_pos, Opcode::ReturnValue.make(vec![]));
     }
 
-    fn change_operand(&mut self, pos: usize, operand: u16) {
+    /// Turns every call whose result is returned straight away, possibly
+    /// after some jumps, into a `TailCall`.
+    fn mark_tail_calls(&mut self) {
+        let instructions = &mut self.current_mut_scope().instructions;
+        let mut pos = 0;
+        while pos < instructions.len() {
+            let op_pos = match instructions.read_op_at(pos) {
+                Opcode::Wide => pos + 1,
+                _ => pos,
+            };
+            let next = pos + instructions.width_at(pos);
+            if instructions.read_op_at(op_pos) == Opcode::Call && returns_at(instructions, next) {
+                instructions.0[op_pos] = Opcode::TailCall as u8;
+            }
+            pos = next;
+        }
+    }
+
+    fn change_operand(&mut self, pos: usize, operand: usize) {
         let op = Opcode::from(self.current_scope().instructions.0[pos]);
         let new_instruction = op.make(vec![operand]);
         self.replace_instruction(pos, new_instruction);
@@ -405,6 +963,15 @@ impl Compiler {
     }
 }
 
+/// Reports whether the instruction at `pos` returns, following any jumps on
+/// the way.
+fn returns_at(instructions: &Instructions, mut pos: usize) -> bool {
+    while pos < instructions.len() && instructions.read_op_at(pos) == Opcode::Jump {
+        pos = instructions.read_u32_from(pos + 1) as usize;
+    }
+    pos < instructions.len() && instructions.read_op_at(pos) == Opcode::ReturnValue
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -413,7 +980,7 @@ mod tests {
         test_helper::{
             parse, test_array_object, test_boolean_object, test_closure_object, test_error_object,
             test_hash_object, test_instructions, test_integer_object, test_null_object,
-            test_string_object, ExpectedValue,
+            test_string_object, write_modules, ExpectedValue,
         },
     };
 
@@ -612,18 +1179,18 @@ mod tests {
                     // 0000
                     Opcode::True.make(vec![]),
                     // 0001
-                    Opcode::JumpNotTruth.make(vec![10]),
-                    // 0004
+                    Opcode::JumpNotTruth.make(vec![14]),
+                    // 0006
                     Opcode::Constant.make(vec![0]),
-                    // 0007
-                    Opcode::Jump.make(vec![11]),
-                    // 0010
+                    // 0009
+                    Opcode::Jump.make(vec![15]),
+                    // 0014
                     Opcode::Null.make(vec![]),
-                    // 0011
+                    // 0015
                     Opcode::Pop.make(vec![]),
-                    // 0012
+                    // 0016
                     Opcode::Constant.make(vec![1]),
-                    // 0015
+                    // 0019
                     Opcode::Pop.make(vec![]),
                 ],
             ),
@@ -638,18 +1205,18 @@ mod tests {
                     // 0000
                     Opcode::True.make(vec![]),
                     // 0001
-                    Opcode::JumpNotTruth.make(vec![10]),
-                    // 0004
+                    Opcode::JumpNotTruth.make(vec![14]),
+                    // 0006
                     Opcode::Constant.make(vec![0]),
-                    // 0007
-                    Opcode::Jump.make(vec![13]),
-                    // 00010
+                    // 0009
+                    Opcode::Jump.make(vec![17]),
+                    // 0014
                     Opcode::Constant.make(vec![1]),
-                    // 00013
+                    // 0017
                     Opcode::Pop.make(vec![]),
-                    // 00014
+                    // 0018
                     Opcode::Constant.make(vec![2]),
-                    // 00017
+                    // 0021
                     Opcode::Pop.make(vec![]),
                 ],
             ),
@@ -882,6 +1449,297 @@ mod tests {
         run_compiler_tests(&tests);
     }
 
+    #[test]
+    fn test_slice_expressions() {
+        let tests = [
+            CompilerTestCase(
+                "[1, 2][1:2]",
+                vec![
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(2),
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(2),
+                ],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Array.make(vec![2]),
+                    Opcode::Constant.make(vec![2]),
+                    Opcode::Constant.make(vec![3]),
+                    Opcode::Slice.make(vec![]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                r#""monkey"[:-1]"#,
+                vec![ExpectedValue::String("monkey"), ExpectedValue::Integer(1)],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Null.make(vec![]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Minus.make(vec![]),
+                    Opcode::Slice.make(vec![]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_spread_expressions() {
+        let tests = [
+            CompilerTestCase(
+                "[1, ...[2]]",
+                vec![ExpectedValue::Integer(1), ExpectedValue::Integer(2)],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Array.make(vec![1]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Array.make(vec![1]),
+                    Opcode::ConcatArrays.make(vec![2]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                "{...{}, 1: 2}",
+                vec![ExpectedValue::Integer(1), ExpectedValue::Integer(2)],
+                vec![
+                    Opcode::Hash.make(vec![0]),
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Hash.make(vec![2]),
+                    Opcode::MergeHashes.make(vec![2]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                "len(...[[1]])",
+                vec![ExpectedValue::Integer(1)],
+                vec![
+                    Opcode::GetBuiltin.make(vec![0]),
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Array.make(vec![1]),
+                    Opcode::Array.make(vec![1]),
+                    Opcode::ConcatArrays.make(vec![1]),
+                    Opcode::CallSpread.make(vec![]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let tests = [
+            CompilerTestCase(
+                "1..2",
+                vec![ExpectedValue::Integer(1), ExpectedValue::Integer(2)],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Range.make(vec![0]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                "1 in 1..=2",
+                vec![
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(2),
+                ],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Constant.make(vec![2]),
+                    Opcode::Range.make(vec![1]),
+                    Opcode::In.make(vec![]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_comprehensions() {
+        let tests = [
+            CompilerTestCase(
+                "[x for x in [1] if x]",
+                vec![ExpectedValue::Integer(1)],
+                vec![
+                    // 0000
+                    Opcode::Array.make(vec![0]),
+                    // 0003
+                    Opcode::Constant.make(vec![0]),
+                    // 0006
+                    Opcode::Array.make(vec![1]),
+                    // 0009
+                    Opcode::GetIter.make(vec![]),
+                    // 0010
+                    Opcode::IterNext.make(vec![36]),
+                    // 0015
+                    Opcode::SetGlobal.make(vec![0]),
+                    // 0018
+                    Opcode::GetGlobal.make(vec![0]),
+                    // 0021
+                    Opcode::JumpNotTruth.make(vec![10]),
+                    // 0026
+                    Opcode::GetGlobal.make(vec![0]),
+                    // 0029
+                    Opcode::Collect.make(vec![1]),
+                    // 0031
+                    Opcode::Jump.make(vec![10]),
+                    // 0036
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                "{k: v for k, v in {}}",
+                vec![],
+                vec![
+                    // 0000
+                    Opcode::Hash.make(vec![0]),
+                    // 0003
+                    Opcode::Hash.make(vec![0]),
+                    // 0006
+                    Opcode::GetIter.make(vec![]),
+                    // 0007
+                    Opcode::IterNext.make(vec![33]),
+                    // 0012
+                    Opcode::Unpack.make(vec![2]),
+                    // 0014
+                    Opcode::SetGlobal.make(vec![1]),
+                    // 0017
+                    Opcode::SetGlobal.make(vec![0]),
+                    // 0020
+                    Opcode::GetGlobal.make(vec![0]),
+                    // 0023
+                    Opcode::GetGlobal.make(vec![1]),
+                    // 0026
+                    Opcode::Collect.make(vec![2]),
+                    // 0028
+                    Opcode::Jump.make(vec![7]),
+                    // 0033
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_null_safe_expressions() {
+        let tests = [
+            CompilerTestCase(
+                "{}?[1] ?? 2",
+                vec![ExpectedValue::Integer(1), ExpectedValue::Integer(2)],
+                vec![
+                    // 0000
+                    Opcode::Hash.make(vec![0]),
+                    // 0003
+                    Opcode::JumpIfNull.make(vec![12]),
+                    // 0008
+                    Opcode::Constant.make(vec![0]),
+                    // 0011
+                    Opcode::Index.make(vec![]),
+                    // 0012
+                    Opcode::JumpIfNull.make(vec![22]),
+                    // 0017
+                    Opcode::Jump.make(vec![26]),
+                    // 0022
+                    Opcode::Pop.make(vec![]),
+                    // 0023
+                    Opcode::Constant.make(vec![1]),
+                    // 0026
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                "{}?.a.b()",
+                vec![ExpectedValue::String("a"), ExpectedValue::String("b")],
+                vec![
+                    // 0000
+                    Opcode::Hash.make(vec![0]),
+                    // 0003
+                    Opcode::JumpIfNull.make(vec![18]),
+                    // 0008
+                    Opcode::Constant.make(vec![0]),
+                    // 0011
+                    Opcode::Member.make(vec![]),
+                    // 0012
+                    Opcode::Constant.make(vec![1]),
+                    // 0015
+                    Opcode::Member.make(vec![]),
+                    // 0016
+                    Opcode::Call.make(vec![0]),
+                    // 0018
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_match_expressions() {
+        let program = parse("enum E { A(x) } match (1) { A(x) => x, _ => 2 }");
+        let mut compiler = Compiler::new();
+        compiler.compile(&program);
+
+        assert_eq!(compiler.constants.len(), 3);
+        test_instructions(
+            &compiler.current_scope().instructions,
+            &[
+                // 0000
+                Opcode::Constant.make(vec![0]),
+                // 0003
+                Opcode::SetGlobal.make(vec![0]),
+                // 0006
+                Opcode::Constant.make(vec![1]),
+                // 0009
+                Opcode::GetGlobal.make(vec![0]),
+                // 0012
+                Opcode::TestTag.make(vec![1]),
+                // 0014
+                Opcode::JumpNotTruth.make(vec![33]),
+                // 0019
+                Opcode::GetPayload.make(vec![0]),
+                // 0021
+                Opcode::SetGlobal.make(vec![1]),
+                // 0024
+                Opcode::Pop.make(vec![]),
+                // 0025
+                Opcode::GetGlobal.make(vec![1]),
+                // 0028
+                Opcode::Jump.make(vec![44]),
+                // 0033
+                Opcode::Pop.make(vec![]),
+                // 0034
+                Opcode::Constant.make(vec![2]),
+                // 0037
+                Opcode::Jump.make(vec![44]),
+                // 0042
+                Opcode::Pop.make(vec![]),
+                // 0043
+                Opcode::Null.make(vec![]),
+                // 0044
+                Opcode::Pop.make(vec![]),
+            ],
+        );
+
+        let mut compiler = Compiler::new();
+        compiler.compile(&parse("match (1) { B(x) => x }"));
+        assert_eq!(compiler.errors, vec!["unknown variant B"]);
+    }
+
     #[test]
     fn test_functions() {
         let tests = [
@@ -937,6 +1795,27 @@ mod tests {
         run_compiler_tests(&tests);
     }
 
+    #[test]
+    fn test_generators() {
+        let tests = [CompilerTestCase(
+            "fn*() { let x = yield 1; yield }",
+            vec![
+                ExpectedValue::Integer(1),
+                ExpectedValue::Function(vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::Yield.make(vec![]),
+                    Opcode::SetLocal.make(vec![0]),
+                    Opcode::Null.make(vec![]),
+                    Opcode::Yield.make(vec![]),
+                    Opcode::ReturnValue.make(vec![]),
+                ]),
+            ],
+            vec![Opcode::Closure.make(vec![1, 0]), Opcode::Pop.make(vec![])],
+        )];
+
+        run_compiler_tests(&tests);
+    }
+
     #[test]
     fn test_compiler_scopes() {
         let mut compiler = Compiler::new();
@@ -1178,7 +2057,7 @@ mod tests {
                 vec![ExpectedValue::Function(vec![
                     Opcode::GetBuiltin.make(vec![0]),
                     Opcode::Array.make(vec![0]),
-                    Opcode::Call.make(vec![1]),
+                    Opcode::TailCall.make(vec![1]),
                     Opcode::ReturnValue.make(vec![]),
                 ])],
                 vec![Opcode::Closure.make(vec![0, 0]), Opcode::Pop.make(vec![])],
@@ -1205,7 +2084,7 @@ mod tests {
                         Opcode::ReturnValue.make(vec![]),
                     ]),
                     ExpectedValue::Function(vec![
-                        Opcode::GetLocal.make(vec![0]),
+                        Opcode::CaptureLocal.make(vec![0]),
                         Opcode::Closure.make(vec![0, 1]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
@@ -1230,13 +2109,13 @@ mod tests {
                         Opcode::ReturnValue.make(vec![]),
                     ]),
                     ExpectedValue::Function(vec![
-                        Opcode::GetFree.make(vec![0]),
-                        Opcode::GetLocal.make(vec![0]),
+                        Opcode::CaptureFree.make(vec![0]),
+                        Opcode::CaptureLocal.make(vec![0]),
                         Opcode::Closure.make(vec![0, 2]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
                     ExpectedValue::Function(vec![
-                        Opcode::GetLocal.make(vec![0]),
+                        Opcode::CaptureLocal.make(vec![0]),
                         Opcode::Closure.make(vec![1, 1]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
@@ -1279,15 +2158,15 @@ mod tests {
                     ExpectedValue::Function(vec![
                         Opcode::Constant.make(vec![2]),
                         Opcode::SetLocal.make(vec![0]),
-                        Opcode::GetFree.make(vec![0]),
-                        Opcode::GetLocal.make(vec![0]),
+                        Opcode::CaptureFree.make(vec![0]),
+                        Opcode::CaptureLocal.make(vec![0]),
                         Opcode::Closure.make(vec![4, 2]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
                     ExpectedValue::Function(vec![
                         Opcode::Constant.make(vec![1]),
                         Opcode::SetLocal.make(vec![0]),
-                        Opcode::GetLocal.make(vec![0]),
+                        Opcode::CaptureLocal.make(vec![0]),
                         Opcode::Closure.make(vec![5, 1]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
@@ -1316,7 +2195,7 @@ mod tests {
                         Opcode::GetLocal.make(vec![0]),
                         Opcode::Constant.make(vec![0]),
                         Opcode::Sub.make(vec![]),
-                        Opcode::Call.make(vec![1]),
+                        Opcode::TailCall.make(vec![1]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
                     ExpectedValue::Integer(1),
@@ -1343,7 +2222,7 @@ mod tests {
                         Opcode::GetLocal.make(vec![0]),
                         Opcode::Constant.make(vec![0]),
                         Opcode::Sub.make(vec![]),
-                        Opcode::Call.make(vec![1]),
+                        Opcode::TailCall.make(vec![1]),
                         Opcode::ReturnValue.make(vec![]),
                     ]),
                     ExpectedValue::Integer(1),
@@ -1352,7 +2231,7 @@ mod tests {
                         Opcode::SetLocal.make(vec![0]),
                         Opcode::GetLocal.make(vec![0]),
                         Opcode::Constant.make(vec![2]),
-                        Opcode::Call.make(vec![1]),
+                        Opcode::TailCall.make(vec![1]),
                         Opcode::ReturnValue.make(vec![]),
                     ])
                 ],
@@ -1368,4 +2247,279 @@ mod tests {
 
         run_compiler_tests(&tests);
     }
+
+    #[test]
+    fn test_tail_calls() {
+        let tests = [CompilerTestCase(
+            "fn(f) { if (f) { f(1) } else { f(2) + 1 } }",
+            vec![
+                ExpectedValue::Integer(1),
+                ExpectedValue::Integer(2),
+                ExpectedValue::Integer(1),
+                ExpectedValue::Function(vec![
+                    Opcode::GetLocal.make(vec![0]),
+                    Opcode::JumpNotTruth.make(vec![19]),
+                    Opcode::GetLocal.make(vec![0]),
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::TailCall.make(vec![1]),
+                    Opcode::Jump.make(vec![30]),
+                    Opcode::GetLocal.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::Call.make(vec![1]),
+                    Opcode::Constant.make(vec![2]),
+                    Opcode::Add.make(vec![]),
+                    Opcode::ReturnValue.make(vec![]),
+                ]),
+            ],
+            vec![Opcode::Closure.make(vec![3, 0]), Opcode::Pop.make(vec![])],
+        )];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_assignments() {
+        let tests = [
+            CompilerTestCase(
+                "let a = 1; a = 2;",
+                vec![ExpectedValue::Integer(1), ExpectedValue::Integer(2)],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::SetGlobal.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::SetGlobal.make(vec![0]),
+                    Opcode::GetGlobal.make(vec![0]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+            CompilerTestCase(
+                "fn() { let a = 1; a = 2 }",
+                vec![
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(2),
+                    ExpectedValue::Function(vec![
+                        Opcode::Constant.make(vec![0]),
+                        Opcode::SetLocal.make(vec![0]),
+                        Opcode::Constant.make(vec![1]),
+                        Opcode::AssignLocal.make(vec![0]),
+                        Opcode::GetLocal.make(vec![0]),
+                        Opcode::ReturnValue.make(vec![]),
+                    ]),
+                ],
+                vec![Opcode::Closure.make(vec![2, 0]), Opcode::Pop.make(vec![])],
+            ),
+            CompilerTestCase(
+                "fn() { let a = 1; fn() { a = a + 1 } }",
+                vec![
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Function(vec![
+                        Opcode::GetFree.make(vec![0]),
+                        Opcode::Constant.make(vec![1]),
+                        Opcode::Add.make(vec![]),
+                        Opcode::SetFree.make(vec![0]),
+                        Opcode::GetFree.make(vec![0]),
+                        Opcode::ReturnValue.make(vec![]),
+                    ]),
+                    ExpectedValue::Function(vec![
+                        Opcode::Constant.make(vec![0]),
+                        Opcode::SetLocal.make(vec![0]),
+                        Opcode::CaptureLocal.make(vec![0]),
+                        Opcode::Closure.make(vec![2, 1]),
+                        Opcode::ReturnValue.make(vec![]),
+                    ]),
+                ],
+                vec![Opcode::Closure.make(vec![3, 0]), Opcode::Pop.make(vec![])],
+            ),
+            CompilerTestCase(
+                "let p = 1; p.x = 2;",
+                vec![
+                    ExpectedValue::Integer(1),
+                    ExpectedValue::Integer(2),
+                    ExpectedValue::String("x"),
+                ],
+                vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::SetGlobal.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::GetGlobal.make(vec![0]),
+                    Opcode::SetField.make(vec![2]),
+                    Opcode::SetGlobal.make(vec![0]),
+                    Opcode::Pop.make(vec![]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_constants() {
+        let tests = [CompilerTestCase(
+            "const a = 1; fn() { let b = a; const a = 2; a }",
+            vec![
+                ExpectedValue::Integer(1),
+                ExpectedValue::Integer(2),
+                ExpectedValue::Function(vec![
+                    Opcode::GetGlobal.make(vec![0]),
+                    Opcode::SetLocal.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::SetLocal.make(vec![1]),
+                    Opcode::GetLocal.make(vec![1]),
+                    Opcode::ReturnValue.make(vec![]),
+                ]),
+            ],
+            vec![
+                Opcode::Constant.make(vec![0]),
+                Opcode::SetGlobal.make(vec![0]),
+                Opcode::Closure.make(vec![2, 0]),
+                Opcode::Pop.make(vec![]),
+            ],
+        )];
+
+        run_compiler_tests(&tests);
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = write_modules(
+            "compiler",
+            &[(
+                "lib.mk",
+                "export let x = 1; let y = 2; export fn f() { x + y }",
+            )],
+        );
+        let program = parse("import \"lib.mk\" as lib; import \"lib.mk\" as again; lib.x");
+        let mut compiler = Compiler::new();
+        compiler.set_main_file(&dir.join("main.mk"));
+        compiler.compile(&program);
+
+        assert_eq!(compiler.errors, Vec::<String>::new());
+        test_contants(
+            &compiler.constants,
+            &vec![
+                ExpectedValue::Integer(1),
+                ExpectedValue::Integer(2),
+                ExpectedValue::Function(vec![
+                    Opcode::GetGlobal.make(vec![0]),
+                    Opcode::GetGlobal.make(vec![1]),
+                    Opcode::Add.make(vec![]),
+                    Opcode::ReturnValue.make(vec![]),
+                ]),
+                ExpectedValue::String("x"),
+                ExpectedValue::String("f"),
+                ExpectedValue::Function(vec![
+                    Opcode::Constant.make(vec![0]),
+                    Opcode::SetGlobal.make(vec![0]),
+                    Opcode::Constant.make(vec![1]),
+                    Opcode::SetGlobal.make(vec![1]),
+                    Opcode::Closure.make(vec![2, 0]),
+                    Opcode::SetGlobal.make(vec![2]),
+                    Opcode::Constant.make(vec![3]),
+                    Opcode::GetGlobal.make(vec![0]),
+                    Opcode::Constant.make(vec![4]),
+                    Opcode::GetGlobal.make(vec![2]),
+                    Opcode::Hash.make(vec![4]),
+                    Opcode::Export.make(vec![]),
+                    Opcode::ReturnValue.make(vec![]),
+                ]),
+                ExpectedValue::String("x"),
+            ],
+        );
+        test_instructions(
+            &compiler.current_scope().instructions,
+            &[
+                Opcode::Import.make(vec![5]),
+                Opcode::SetGlobal.make(vec![0]),
+                Opcode::Import.make(vec![5]),
+                Opcode::SetGlobal.make(vec![1]),
+                Opcode::GetGlobal.make(vec![0]),
+                Opcode::Constant.make(vec![6]),
+                Opcode::Member.make(vec![]),
+                Opcode::Pop.make(vec![]),
+            ],
+        );
+        for (i, module) in [(2, 1), (5, 1)] {
+            match compiler.constants[i] {
+                Object::CompiledFunction(ref func) => assert_eq!(func.module, module),
+                _ => panic!("not a function"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant_errors() {
+        let tests = [
+            ("const a = 1; a = 2;", vec!["cannot assign to constant a"]),
+            (
+                "const a = 1; let a = 2;",
+                vec!["cannot redefine constant a"],
+            ),
+            (
+                "let a = 1; const a = 2;",
+                vec!["cannot redefine a as a constant"],
+            ),
+            (
+                "const a = 1; const a = 2;",
+                vec!["cannot redefine constant a"],
+            ),
+            (
+                "const a = 1; fn() { a = 2 }",
+                vec!["cannot assign to constant a"],
+            ),
+            (
+                "fn() { const a = 1; fn() { a = 2 } }",
+                vec!["cannot assign to constant a"],
+            ),
+            (
+                "const a = 1; struct a { x }",
+                vec!["cannot redefine constant a"],
+            ),
+            ("const a = 1; if (true) { let a = 2; a = 3 }", vec![]),
+            ("const a = 1; fn() { let a = 2; a = 3 }", vec![]),
+            (
+                "fn() { const a = 1; a = 2; let a = 3 }",
+                vec!["cannot assign to constant a", "cannot redefine constant a"],
+            ),
+            ("b = 1", vec!["undefined variable b"]),
+            ("len = 1", vec!["cannot assign to len"]),
+            ("let f = fn() { f = 1 };", vec!["cannot assign to f"]),
+            ("let p = 1; q.x = 1", vec!["undefined variable q"]),
+            ("...[1]", vec!["spread operator is not allowed here"]),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let mut compiler = Compiler::new();
+            compiler.compile(&parse(input));
+            assert_eq!(compiler.errors, *expected, "{}", input);
+        }
+
+        // As in the REPL, where each line gets a compiler carrying on from
+        // the previous one.
+        let mut compiler = Compiler::new();
+        compiler.compile(&parse("const a = 1;"));
+        let mut compiler = Compiler::from(compiler);
+        compiler.compile(&parse("let a = 2;"));
+        assert_eq!(compiler.errors, vec!["cannot redefine constant a"]);
+    }
+
+    #[test]
+    fn test_wide_operands() {
+        let letter = |i: usize| (b'a' + (i % 26) as u8) as char;
+        let params: Vec<String> = (0..257)
+            .map(|i| format!("x{}{}", letter(i / 26), letter(i)))
+            .collect();
+        let input = format!("fn({}) {{ {} }}", params.join(", "), params[256]);
+
+        let tests = [CompilerTestCase(
+            &input,
+            vec![ExpectedValue::Function(vec![
+                Opcode::GetLocal.make_wide(vec![256]),
+                Opcode::ReturnValue.make(vec![]),
+            ])],
+            vec![Opcode::Closure.make(vec![0, 0]), Opcode::Pop.make(vec![])],
+        )];
+
+        run_compiler_tests(&tests);
+    }
 }
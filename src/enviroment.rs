@@ -1,10 +1,15 @@
-use std::{collections::HashMap, rc::Rc, cell::RefCell};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::object::{Object, Boolean, Null};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Enviroment {
     store: HashMap<String, Rc<Object>>,
+    constants: HashSet<String>,
     pub outer: Option<Rc<RefCell<Enviroment>>>,
     pub true_object: Rc<Object>,
     pub false_object: Rc<Object>,
@@ -15,6 +20,7 @@ impl Default for Enviroment {
     fn default() -> Self {
         Self { 
             store: HashMap::new(),
+            constants: HashSet::new(),
             outer: None,
             true_object: Rc::new(Object::Boolean(Boolean::new(true))),
             false_object: Rc::new(Object::Boolean(Boolean::new(false))),
@@ -27,6 +33,7 @@ impl Enviroment {
     pub fn new(outer: Rc<RefCell<Enviroment>>) -> Self {
         Self {
             store: HashMap::new(),
+            constants: HashSet::new(),
             outer: Some(Rc::clone(&outer)),
             true_object: Rc::clone(&outer.borrow().true_object),
             false_object: Rc::clone(&outer.borrow().false_object),
@@ -49,7 +56,41 @@ impl Enviroment {
         }
     }
 
-    pub fn set(&mut self, name: String, object: Rc<Object>) {
+    /// Binds `name` in this enviroment, unless it is a constant here.
+    pub fn set(&mut self, name: String, object: Rc<Object>) -> Result<(), String> {
+        if self.constants.contains(&name) {
+            return Err(format!("cannot redefine constant {}", name));
+        }
+        self.store.insert(name, object);
+        Ok(())
+    }
+
+    /// Binds `name` as a constant, unless it is already bound here.
+    pub fn set_const(&mut self, name: String, object: Rc<Object>) -> Result<(), String> {
+        if self.constants.contains(&name) {
+            return Err(format!("cannot redefine constant {}", name));
+        }
+        if self.store.contains_key(&name) {
+            return Err(format!("cannot redefine {} as a constant", name));
+        }
+        self.constants.insert(name.to_owned());
         self.store.insert(name, object);
+        Ok(())
+    }
+
+    /// Rebinds `name` in the innermost enviroment defining it, so every
+    /// closure sharing that enviroment sees the new value.
+    pub fn assign(&mut self, name: String, object: Rc<Object>) -> Result<(), String> {
+        if let Some(value) = self.store.get_mut(name.as_str()) {
+            if self.constants.contains(&name) {
+                return Err(format!("cannot assign to constant {}", name));
+            }
+            *value = object;
+            return Ok(());
+        }
+        match self.outer {
+            Some(ref outer) => outer.borrow_mut().assign(name, object),
+            None => Err(format!("identifier not found: {}", name)),
+        }
     }
 }
@@ -1,27 +1,58 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::Path;
 use std::rc::Rc;
+use std::thread;
 
 use crate::ast::Expression;
 use crate::ast::{
-    ArrayLiteral, BlockStatement, CallExpression, FunctionLiteral, HashLiteral, Identifier,
-    IfExpression, IndexExpression, InfixExpression, LetStatement, PrefixExpression, Program,
-    ReturnStatement, Statement,
+    ArrayLiteral, BlockStatement, CallExpression, ComprehensionClause, EnumStatement,
+    FunctionLiteral, HashComprehension, HashLiteral, Identifier, IfExpression, ImportStatement,
+    IndexExpression, InfixExpression, LetStatement, ListComprehension, MatchExpression, Pattern,
+    PrefixExpression, Program, ReturnStatement, SliceExpression, Statement, StructStatement,
 };
 use crate::builtin::Builtin;
 use crate::enviroment::Enviroment;
-use crate::makro::EvalUnqupteCalls;
-use crate::object::Hash;
+use crate::makro::{self, EvalUnqupteCalls};
+use crate::module::Modules;
+use crate::object::{Hash, HashKey, HashPair};
 use crate::object::{
-    Array, BuiltinFunction, Function, HashKeyable, Inspector, Integer, Object, Quote, ReturnValue,
-    RuntimeError, Str,
+    Array, BuiltinFunction, Function, Generator, GeneratorState, Inspector, Integer, Object, Quote,
+    Range, ReturnValue, RuntimeError, Str, Struct, StructDefinition, Variant, VariantDefinition,
 };
 use crate::traverser::Traverable;
+use crate::vm::MAX_FRAMES;
+
+/// How deeply function calls may nest before evaluation fails, as many as
+/// the vm allows by default. Tail calls reuse their caller's depth.
+const MAX_DEPTH: usize = MAX_FRAMES;
+
+/// The stack size of the thread `with_stack` runs on. A level of `MAX_DEPTH`
+/// takes up to about 16 KiB of host stack in debug builds.
+pub const THREAD_STACK_SIZE: usize = 256 << 20;
+
+/// Runs `f` on a thread with a stack deep enough for the evaluator to reach
+/// `MAX_DEPTH` rather than overflow.
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        let handle = thread::Builder::new()
+            .stack_size(THREAD_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("failed to spawn the evaluator thread");
+        handle
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
 
 #[derive(Debug)]
 pub struct Evaluator {
     env: Rc<RefCell<Enviroment>>,
     builtin: Builtin,
+    depth: usize,
+    /// The exports of each module imported so far, shared with the
+    /// evaluators running those modules.
+    modules: Rc<RefCell<Modules<Rc<Object>>>>,
 }
 
 impl Evaluator {
@@ -29,9 +60,17 @@ impl Evaluator {
         Self {
             env: Rc::clone(&env),
             builtin: Builtin::default(),
+            depth: 0,
+            modules: Rc::new(RefCell::new(Modules::default())),
         }
     }
 
+    /// Sets the file the program being evaluated was read from, so its
+    /// imports resolve relative to it.
+    pub fn set_main_file(&mut self, path: &Path) {
+        self.modules.borrow_mut().set_main_file(path);
+    }
+
     pub fn eval(&mut self, program: &Program) -> Rc<Object> {
         self.eval_program(program)
     }
@@ -41,6 +80,10 @@ impl Evaluator {
             Statement::Expression(node) => self.eval_expression(&node.expression),
             Statement::Return(node) => self.eval_return_statement(node),
             Statement::Let(node) => self.eval_let_statement(node),
+            Statement::Struct(node) => self.eval_struct_statement(node),
+            Statement::Enum(node) => self.eval_enum_statement(node),
+            Statement::Import(node) => self.eval_import_statement(node),
+            Statement::Export(node) => self.eval_statement(&node.statement),
         }
     }
 
@@ -52,27 +95,180 @@ impl Evaluator {
             Expression::StringLiteral(node) => Object::Str(Str::new(node.value.to_owned())).into(),
             Expression::ArrayLiteral(node) => self.eval_array_literal(node),
             Expression::HashLiteral(node) => self.eval_hash_literal(node),
-            Expression::Index(node) => self.eval_index_expression(node),
+            Expression::Call(_) | Expression::Index(_) | Expression::Slice(_) => {
+                self.eval_chain(ast)
+            }
             Expression::Boolean(node) => self.native_bool_to_boolean_object(node.value),
             Expression::FunctionLiteral(node) => self.eval_function_literal(node),
             Expression::MacroLiteral(_) => Rc::clone(&self.env.borrow().null_object),
             Expression::Prefix(node) => self.eval_prefix_expression(node),
             Expression::Infix(node) => self.eval_infix_expression(node),
             Expression::If(node) => self.eval_if_expression(node),
-            Expression::Call(node) => self.eval_call_expression(ast, node),
+            Expression::Match(node) => self.eval_match_expression(node),
+            Expression::Spread(_) => Object::RuntimeError(RuntimeError::new(
+                "spread operator is not allowed here".into(),
+            ))
+            .into(),
+            Expression::ListComprehension(node) => self.eval_list_comprehension(node),
+            Expression::HashComprehension(node) => self.eval_hash_comprehension(node),
+            Expression::Assign(node) => {
+                let value = self.eval_expression(&node.value);
+                self.assign(&node.name, node.field.as_deref(), value)
+            }
+            Expression::Group(node) => self.eval_expression(&node.value),
+            Expression::Block(node) => self.eval_block_statement(node),
+            // Generators resume yields through `run_continuation`, and the
+            // parser rejects them everywhere else.
+            Expression::Yield(_) => Object::RuntimeError(RuntimeError::new(
+                "yield is not allowed in this position".into(),
+            ))
+            .into(),
         }
     }
 
     fn eval_let_statement(&mut self, node: &LetStatement) -> Rc<Object> {
         let value = self.eval_expression(&node.value);
         if self.is_error(&value) {
-            value
+            return value;
+        }
+        match self.bind(&node.name.value, value, node.is_const) {
+            // As in the VM, a `let` has no value of its own.
+            Ok(()) => Rc::clone(&self.env.borrow().null_object),
+            Err(error) => error,
+        }
+    }
+
+    /// Binds `name` in the current enviroment, failing if that clashes with
+    /// a constant.
+    fn bind(&mut self, name: &str, value: Rc<Object>, is_const: bool) -> Result<(), Rc<Object>> {
+        let mut env = self.env.borrow_mut();
+        let result = if is_const {
+            env.set_const(name.to_owned(), value)
         } else {
-            self.env
-                .borrow_mut()
-                .set(node.name.value.to_owned(), value.clone());
-            value
+            env.set(name.to_owned(), value)
+        };
+        result.map_err(|message| Object::RuntimeError(RuntimeError::new(message)).into())
+    }
+
+    /// Assigns `value` to `name`, or to its struct's `field` if one is given.
+    fn assign(&mut self, name: &Identifier, field: Option<&str>, value: Rc<Object>) -> Rc<Object> {
+        if self.is_error(&value) {
+            return value;
+        }
+        let target = match field {
+            Some(field) => match self.with_field(name, field, Rc::clone(&value)) {
+                Ok(target) => target,
+                Err(error) => return error,
+            },
+            None => Rc::clone(&value),
+        };
+        match self.env.borrow_mut().assign(name.value.to_owned(), target) {
+            Ok(()) => value,
+            Err(message) => Object::RuntimeError(RuntimeError::new(message)).into(),
+        }
+    }
+
+    /// Copies the struct bound to `name` with `field` set to `value`.
+    fn with_field(
+        &mut self,
+        name: &Identifier,
+        field: &str,
+        value: Rc<Object>,
+    ) -> Result<Rc<Object>, Rc<Object>> {
+        let target = self.eval_indentifier(name);
+        if self.is_error(&target) {
+            return Err(target);
+        }
+        match *target {
+            Object::Struct(ref instance) => match instance.with_field(field, value) {
+                Some(updated) => Ok(Object::Struct(updated).into()),
+                None => Err(instance.unknown_field(field).into()),
+            },
+            _ => Err(Object::RuntimeError(RuntimeError::new(format!(
+                "field assignment not supported: {}",
+                target.kind()
+            )))
+            .into()),
+        }
+    }
+
+    fn eval_struct_statement(&mut self, node: &StructStatement) -> Rc<Object> {
+        let definition: Rc<Object> = Object::StructDefinition(StructDefinition::new(
+            node.name.value.to_owned(),
+            node.fields.iter().map(|f| f.value.to_owned()).collect(),
+        ))
+        .into();
+        match self.bind(&node.name.value, Rc::clone(&definition), false) {
+            Ok(()) => definition,
+            Err(error) => error,
+        }
+    }
+
+    fn eval_enum_statement(&mut self, node: &EnumStatement) -> Rc<Object> {
+        for variant in node.variants.iter() {
+            let definition = VariantDefinition::new(
+                node.name.value.to_owned(),
+                variant.name.value.to_owned(),
+                variant.fields.iter().map(|f| f.value.to_owned()).collect(),
+            );
+            // Variants without a payload are values rather than constructors.
+            let value = if definition.fields.is_empty() {
+                Object::Variant(Variant::new(definition, vec![]))
+            } else {
+                Object::VariantDefinition(definition)
+            };
+            if let Err(error) = self.bind(&variant.name.value, value.into(), false) {
+                return error;
+            }
+        }
+        Rc::clone(&self.env.borrow().null_object)
+    }
+
+    fn eval_import_statement(&mut self, node: &ImportStatement) -> Rc<Object> {
+        let exports = match self.import(&node.path) {
+            Ok(exports) => exports,
+            Err(message) => return Object::RuntimeError(RuntimeError::new(message)).into(),
+        };
+        match self.bind(&node.name.value, exports, false) {
+            Ok(()) => Rc::clone(&self.env.borrow().null_object),
+            Err(error) => error,
+        }
+    }
+
+    /// Returns a hash of the names the module at `path` exports, running it
+    /// in an enviroment of its own the first time it is imported.
+    fn import(&mut self, path: &str) -> Result<Rc<Object>, String> {
+        let path = self.modules.borrow().resolve(path)?;
+        if let Some(exports) = self.modules.borrow().get(&path) {
+            return Ok(exports);
+        }
+
+        let module = self.modules.borrow_mut().load(path)?;
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let mut evaluator = Evaluator {
+            modules: Rc::clone(&self.modules),
+            ..Evaluator::new(Rc::clone(&env))
+        };
+        let result = evaluator.eval(&module.program);
+        if let Object::RuntimeError(ref error) = *result {
+            self.modules.borrow_mut().finish(&module, None);
+            return Err(format!("{}: {}", module.path.display(), error.message));
+        }
+
+        let mut exports = HashMap::new();
+        for name in module.exports() {
+            let key: Rc<Object> = Object::Str(Str::new(name.to_owned())).into();
+            let value = env
+                .borrow()
+                .get(name)
+                .expect("exported names are bound by the module");
+            exports.insert(key.hash_key().unwrap(), HashPair::new(key, value));
         }
+        let exports: Rc<Object> = Object::Hash(Hash::new(exports)).into();
+        self.modules
+            .borrow_mut()
+            .finish(&module, Some(Rc::clone(&exports)));
+        Ok(exports)
     }
 
     fn eval_return_statement(&mut self, node: &ReturnStatement) -> Rc<Object> {
@@ -84,15 +280,48 @@ impl Evaluator {
         }
     }
 
-    fn quote(&mut self, node: &mut Expression) -> Rc<Object> {
-        self.eval_unquote_calls(node);
-        match *node {
-            Expression::Call(ref call) => {
-                let argument = call.arguments.get(0).unwrap();
-                Object::Quote(Quote::new(argument.to_owned())).into()
+    /// Quotes the first argument of `quote`. The names it binds are renamed,
+    /// apart from those listed in the optional second argument, which the
+    /// quoted code may bind for the code around it to use.
+    fn quote(&mut self, node: &CallExpression) -> Rc<Object> {
+        let captured = match node.arguments[..] {
+            [_] => vec![],
+            [_, Expression::ArrayLiteral(ref names)] => {
+                let names = names.elements.iter().map(|name| match name {
+                    Expression::Identifier(name) => Ok(name.value.to_owned()),
+                    _ => Err(name),
+                });
+                match names.collect::<Result<Vec<_>, _>>() {
+                    Ok(names) => names,
+                    Err(name) => {
+                        return Object::RuntimeError(RuntimeError::new(format!(
+                            "names to capture must be identifiers, got {}",
+                            name
+                        )))
+                        .into()
+                    }
+                }
             }
-            _ => Rc::clone(&self.env.borrow().null_object),
-        }
+            [_, ref names] => {
+                return Object::RuntimeError(RuntimeError::new(format!(
+                    "names to capture must be an array, got {}",
+                    names
+                )))
+                .into()
+            }
+            _ => {
+                return Object::RuntimeError(RuntimeError::new(format!(
+                    "wrong number of arguments. got={}, want=1 or 2",
+                    node.arguments.len()
+                )))
+                .into()
+            }
+        };
+
+        let mut template = node.arguments[0].to_owned();
+        makro::rename_bound_names(&mut template, &captured);
+        self.eval_unquote_calls(&mut template);
+        Object::Quote(Quote::new(template)).into()
     }
 
     fn eval_unquote_calls(&mut self, node: &mut Expression) {
@@ -100,72 +329,375 @@ impl Evaluator {
         node.visit_mut(&visitor);
     }
 
-    fn eval_call_expression(&mut self, parent: &Expression, node: &CallExpression) -> Rc<Object> {
+    /// Evaluates a chain of calls, indexes and slices such as `a?.b.c(d)`. A
+    /// `?.` or `?[` that finds null skips the rest of the chain, which is then
+    /// null as well.
+    fn eval_chain(&mut self, node: &Expression) -> Rc<Object> {
+        self.eval_chain_link(node)
+            .unwrap_or_else(|| Rc::clone(&self.env.borrow().null_object))
+    }
+
+    /// Evaluates a link of a chain, giving `None` once it is cut short.
+    fn eval_chain_link(&mut self, node: &Expression) -> Option<Rc<Object>> {
+        match node {
+            Expression::Call(node) => match self.eval_call(node)? {
+                Tail::Value(value) => Some(value),
+                Tail::Call(func, args) => Some(self.apply_function(func, args)),
+            },
+            Expression::Index(node) => {
+                let left = self.eval_chain_link(&node.left)?;
+                if node.optional && matches!(*left, Object::Null(_)) {
+                    return None;
+                }
+                Some(self.eval_index_expression(left, node))
+            }
+            Expression::Slice(node) => {
+                let left = self.eval_chain_link(&node.left)?;
+                Some(self.eval_slice_expression(left, node))
+            }
+            _ => Some(self.eval_expression(node)),
+        }
+    }
+
+    /// Evaluates a call, except that calls to functions are handed back for
+    /// the caller to make. As a link of a chain it may be cut short.
+    fn eval_call(&mut self, node: &CallExpression) -> Option<Tail> {
         if let Expression::Identifier(ref callee) = *node.callee {
             if callee.value == "quote" {
-                if node.arguments.len() == 1 {
-                    return self.quote(&mut parent.to_owned());
-                } else {
-                    return Object::RuntimeError(RuntimeError::new(format!(
-                        "wrong number of arguments. got={}, want=1",
-                        node.arguments.len()
-                    )))
-                    .into();
-                }
+                return Some(Tail::Value(self.quote(node)));
             }
         }
 
-        let func = self.eval_expression(&node.callee);
+        let func = self.eval_chain_link(&node.callee)?;
+        Some(self.eval_call_arguments(func, node))
+    }
 
+    fn eval_call_arguments(&mut self, func: Rc<Object>, node: &CallExpression) -> Tail {
         if self.is_error(&func) {
-            return func;
+            return Tail::Value(func);
         }
 
-        match *func {
-            Object::Function(ref func) => {
-                let args = self.eval_expressions(&node.arguments);
-                if args.len() == 1 && self.is_error(&args[0]) {
-                    return args[0].clone();
-                }
-                self.apply_function(func, args)
-            }
-            Object::BuiltinFunction(ref func) => {
-                let args = self.eval_expressions(&node.arguments);
-                if args.len() == 1 && self.is_error(&args[0]) {
-                    return args[0].clone();
-                }
-                if let Some(result) = self.builtin.apply_function(&func.name, args) {
-                    result
-                } else {
+        let args = match *func {
+            Object::Function(_)
+            | Object::BuiltinFunction(_)
+            | Object::StructDefinition(_)
+            | Object::VariantDefinition(_) => self.eval_expressions(&node.arguments),
+            _ => {
+                return Tail::Value(
                     Object::RuntimeError(RuntimeError::new(format!(
                         "Not a function: {}",
-                        func.name,
+                        func.kind(),
                     )))
-                    .into()
-                }
+                    .into(),
+                )
             }
-            _ => Object::RuntimeError(RuntimeError::new(format!(
-                "Not a function: {}",
-                func.kind(),
-            )))
-            .into(),
+        };
+        if args.len() == 1 && self.is_error(&args[0]) {
+            return Tail::Value(args[0].clone());
+        }
+
+        match *func {
+            Object::Function(_) => Tail::Call(Rc::clone(&func), args),
+            Object::BuiltinFunction(ref func) => Tail::Value(self.apply_builtin(func, args)),
+            Object::StructDefinition(ref definition) => {
+                Tail::Value(definition.construct(args).into())
+            }
+            Object::VariantDefinition(ref definition) => {
+                Tail::Value(definition.construct(args).into())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn apply_builtin(&mut self, func: &BuiltinFunction, args: Vec<Rc<Object>>) -> Rc<Object> {
+        if func.name == "next" {
+            return match self.builtin.next_generator(func, args) {
+                Ok(generator) => self.resume_generator(&generator),
+                Err(error) => error,
+            };
+        }
+        if let Some(result) = self.builtin.call(func, args) {
+            result
+        } else {
+            Object::RuntimeError(RuntimeError::new(format!("Not a function: {}", func.name,)))
+                .into()
         }
     }
 
-    fn apply_function(&mut self, func: &Function, args: Vec<Rc<Object>>) -> Rc<Object> {
+    /// Calls `func`, a `Function` object. Calls in tail position are made in
+    /// a loop here rather than nested, so only other calls count against
+    /// `MAX_DEPTH`.
+    fn apply_function(&mut self, mut func: Rc<Object>, mut args: Vec<Rc<Object>>) -> Rc<Object> {
+        if let Err(error) = self.enter_call() {
+            return error;
+        }
         let old_env = Rc::clone(&self.env);
-        self.extend_function_env(func, args);
-        let return_value = self.eval_block_statement(&func.body);
+
+        let result = loop {
+            let function = match *func {
+                Object::Function(ref function) => function,
+                _ => unreachable!(),
+            };
+            let env = self.extend_function_env(function, args);
+            if function.is_generator {
+                break Object::Generator(Generator::new(GeneratorState::Continuation(
+                    Continuation::new(env, &function.body),
+                )))
+                .into();
+            }
+
+            self.env = env;
+            match self.eval_tail_block(&function.body) {
+                Tail::Value(value) => break self.unwrap_return_value(value),
+                Tail::Call(next, next_args) => {
+                    func = next;
+                    args = next_args;
+                }
+            }
+        };
+
         self.env = old_env;
-        self.unwrap_return_value(return_value)
+        self.depth -= 1;
+        result
     }
 
-    fn extend_function_env(&mut self, func: &Function, args: Vec<Rc<Object>>) {
+    /// Counts a function call or generator resumption against `MAX_DEPTH`,
+    /// until the caller takes `depth` back down.
+    fn enter_call(&mut self) -> Result<(), Rc<Object>> {
+        if self.depth == MAX_DEPTH {
+            return Err(Object::RuntimeError(RuntimeError::new(
+                "maximum recursion depth exceeded".into(),
+            ))
+            .into());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn extend_function_env(
+        &mut self,
+        func: &Function,
+        args: Vec<Rc<Object>>,
+    ) -> Rc<RefCell<Enviroment>> {
         let mut env = Enviroment::new(Rc::clone(&func.env));
         for (i, param) in func.parameters.iter().enumerate() {
-            env.set(param.value.to_owned(), args[i].to_owned());
+            env.set(param.value.to_owned(), args[i].to_owned())
+                .expect("a new enviroment has no constants");
+        }
+        Rc::new(RefCell::new(env))
+    }
+
+    /// Runs a generator's body until its next `yield` and returns the yielded
+    /// value, or null once the body has finished. The value of a `return` in
+    /// the body is discarded, as in the vm.
+    fn resume_generator(&mut self, generator: &Generator) -> Rc<Object> {
+        if let Err(error) = self.enter_call() {
+            return error;
+        }
+        let value = self.run_generator(generator);
+        self.depth -= 1;
+        value
+    }
+
+    fn run_generator(&mut self, generator: &Generator) -> Rc<Object> {
+        let mut continuation = match generator.state.replace(GeneratorState::Running) {
+            GeneratorState::Continuation(continuation) => continuation,
+            GeneratorState::Running => {
+                return Object::RuntimeError(RuntimeError::new(
+                    "generator is already running".into(),
+                ))
+                .into()
+            }
+            GeneratorState::Done | GeneratorState::Frame { .. } => {
+                generator.state.replace(GeneratorState::Done);
+                return Rc::clone(&self.env.borrow().null_object);
+            }
+        };
+
+        let old_env = std::mem::replace(&mut self.env, Rc::clone(&continuation.env));
+        let value = self.run_continuation(&mut continuation.frames);
+        // A yield inside a block suspends in that block's enviroment.
+        continuation.env = std::mem::replace(&mut self.env, old_env);
+
+        generator.state.replace(if continuation.frames.is_empty() {
+            GeneratorState::Done
+        } else {
+            GeneratorState::Continuation(continuation)
+        });
+        value
+    }
+
+    /// Works through `frames` until a `yield` suspends them, leaving the rest
+    /// in place, or until the body finishes or fails, leaving them empty.
+    fn run_continuation(&mut self, frames: &mut Vec<ContinuationFrame>) -> Rc<Object> {
+        // The result of the last finished piece of work. A resumed `yield`
+        // evaluates to null.
+        let mut value = Rc::clone(&self.env.borrow().null_object);
+
+        while let Some(frame) = frames.pop() {
+            match frame {
+                ContinuationFrame::Block(block, index) => {
+                    let stmt = match block.statements.get(index) {
+                        Some(stmt) => stmt.to_owned(),
+                        None => continue,
+                    };
+                    frames.push(ContinuationFrame::Block(block, index + 1));
+                    match stmt {
+                        Statement::Expression(stmt) if stmt.expression.contains_yield() => {
+                            frames.push(ContinuationFrame::Expression(stmt.expression));
+                        }
+                        Statement::Let(stmt) if stmt.value.contains_yield() => {
+                            frames.push(ContinuationFrame::Let(stmt.name.value, stmt.is_const));
+                            frames.push(ContinuationFrame::Expression(stmt.value));
+                        }
+                        Statement::Return(stmt) => {
+                            frames.push(ContinuationFrame::Return);
+                            frames.push(ContinuationFrame::Expression(stmt.return_value));
+                        }
+                        stmt => value = self.eval_statement(&stmt),
+                    }
+                }
+                ContinuationFrame::Let(name, is_const) => {
+                    if let Err(error) = self.bind(&name, Rc::clone(&value), is_const) {
+                        value = error;
+                    }
+                }
+                ContinuationFrame::Assign(name, field) => {
+                    value = self.assign(&name, field.as_deref(), value)
+                }
+                ContinuationFrame::Leave(env) => self.env = env,
+                ContinuationFrame::Return => frames.clear(),
+                ContinuationFrame::Expression(Expression::Yield(node)) => {
+                    value = self.eval_expression(&node.value);
+                    if !self.is_error(&value) {
+                        return value;
+                    }
+                }
+                ContinuationFrame::Expression(Expression::Assign(node)) => {
+                    frames.push(ContinuationFrame::Assign(node.name, node.field));
+                    frames.push(ContinuationFrame::Expression(*node.value));
+                }
+                ContinuationFrame::Expression(Expression::If(node)) => {
+                    value = self.eval_expression(&node.condition);
+                    if !self.is_error(&value) {
+                        let block = if self.is_truthy(Rc::clone(&value)) {
+                            Some(node.consequence)
+                        } else {
+                            node.alternative
+                        };
+                        value = Rc::clone(&self.env.borrow().null_object);
+                        if let Some(block) = block {
+                            frames.push(ContinuationFrame::Leave(self.enter_block()));
+                            frames.push(ContinuationFrame::Block(block, 0));
+                        }
+                    }
+                }
+                ContinuationFrame::Expression(Expression::Match(node)) => {
+                    let subject = self.eval_expression(&node.subject);
+                    value = if self.is_error(&subject) {
+                        subject
+                    } else {
+                        frames.push(ContinuationFrame::Leave(self.enter_block()));
+                        match self.select_match_arm(&node, &subject) {
+                            Ok(Some(body)) => {
+                                frames.push(ContinuationFrame::Block(body.to_owned(), 0));
+                                Rc::clone(&self.env.borrow().null_object)
+                            }
+                            Ok(None) => Rc::clone(&self.env.borrow().null_object),
+                            Err(error) => error,
+                        }
+                    };
+                }
+                ContinuationFrame::Expression(Expression::ListComprehension(node)) => {
+                    value = match self.eval_comprehension_items(&node.clause) {
+                        Ok(items) => self.resume_list_comprehension(frames, node, items, vec![]),
+                        Err(error) => error,
+                    };
+                }
+                ContinuationFrame::Expression(Expression::HashComprehension(node)) => {
+                    value = match self.eval_comprehension_items(&node.clause) {
+                        Ok(items) => {
+                            self.resume_hash_comprehension(frames, node, items, HashMap::new())
+                        }
+                        Err(error) => error,
+                    };
+                }
+                ContinuationFrame::Expression(expression) => {
+                    value = self.eval_expression(&expression);
+                }
+                ContinuationFrame::ListComprehension(node, items, mut elements) => {
+                    elements.push(value);
+                    value = self.resume_list_comprehension(frames, node, items, elements);
+                }
+                ContinuationFrame::HashKey(node, items, pairs) => {
+                    if value.hash_key().is_some() {
+                        let value_expression = (*node.value).to_owned();
+                        frames.push(ContinuationFrame::HashValue(node, items, pairs, value));
+                        frames.push(ContinuationFrame::Expression(value_expression));
+                        value = Rc::clone(&self.env.borrow().null_object);
+                    } else {
+                        value = self.unusable_hash_key_error(&value);
+                    }
+                }
+                ContinuationFrame::HashValue(node, items, mut pairs, key) => {
+                    pairs.insert(key.hash_key().unwrap(), HashPair::new(key, value));
+                    value = self.resume_hash_comprehension(frames, node, items, pairs);
+                }
+            }
+
+            match *value {
+                Object::RuntimeError(_) => {
+                    frames.clear();
+                    return value;
+                }
+                // A `return` reached without going through the frames, e.g.
+                // inside an `if` that does not yield.
+                Object::ReturnValue(_) => frames.clear(),
+                _ => {}
+            }
+        }
+
+        Rc::clone(&self.env.borrow().null_object)
+    }
+
+    /// Binds the next item of a suspended list comprehension and schedules
+    /// its element, or returns the finished array.
+    fn resume_list_comprehension(
+        &mut self,
+        frames: &mut Vec<ContinuationFrame>,
+        node: ListComprehension,
+        mut items: ComprehensionItems,
+        elements: Vec<Rc<Object>>,
+    ) -> Rc<Object> {
+        match self.bind_next_item(&node.clause, &mut items) {
+            Ok(true) => {
+                let element = (*node.element).to_owned();
+                frames.push(ContinuationFrame::ListComprehension(node, items, elements));
+                frames.push(ContinuationFrame::Expression(element));
+                Rc::clone(&self.env.borrow().null_object)
+            }
+            Ok(false) => Object::Array(Array::new(elements)).into(),
+            Err(error) => error,
+        }
+    }
+
+    fn resume_hash_comprehension(
+        &mut self,
+        frames: &mut Vec<ContinuationFrame>,
+        node: HashComprehension,
+        mut items: ComprehensionItems,
+        pairs: HashMap<HashKey, HashPair>,
+    ) -> Rc<Object> {
+        match self.bind_next_item(&node.clause, &mut items) {
+            Ok(true) => {
+                let key = (*node.key).to_owned();
+                frames.push(ContinuationFrame::HashKey(node, items, pairs));
+                frames.push(ContinuationFrame::Expression(key));
+                Rc::clone(&self.env.borrow().null_object)
+            }
+            Ok(false) => Object::Hash(Hash::new(pairs)).into(),
+            Err(error) => error,
         }
-        self.env = Rc::new(RefCell::new(env));
     }
 
     fn unwrap_return_value(&self, obj: Rc<Object>) -> Rc<Object> {
@@ -196,6 +728,25 @@ impl Evaluator {
         let mut result: Vec<_> = vec![];
 
         for exp in exps.iter() {
+            if let Expression::Spread(ref spread) = *exp {
+                let evaluated = self.eval_expression(&spread.value);
+                if self.is_error(&evaluated) {
+                    return vec![evaluated];
+                }
+                match *evaluated {
+                    Object::Array(ref array) => result.extend(array.elements.iter().cloned()),
+                    Object::Range(_) => result.extend(evaluated.iter_items().unwrap()),
+                    _ => {
+                        return vec![Object::RuntimeError(RuntimeError::new(format!(
+                            "spread operator requires ARRAY, got {}",
+                            evaluated.kind()
+                        )))
+                        .into()]
+                    }
+                }
+                continue;
+            }
+
             let evaluated = self.eval_expression(exp);
             if self.is_error(&evaluated) {
                 return vec![evaluated];
@@ -222,8 +773,71 @@ impl Evaluator {
         result
     }
 
-    fn eval_index_expression(&mut self, node: &IndexExpression) -> Rc<Object> {
-        let left = self.eval_expression(&node.left);
+    /// Evaluates a block that ends a function body, handing back a call in
+    /// its last statement rather than making it.
+    fn eval_tail_block(&mut self, block: &BlockStatement) -> Tail {
+        let (last, init) = match block.statements.split_last() {
+            Some(statements) => statements,
+            None => return Tail::Value(Rc::clone(&self.env.borrow().null_object)),
+        };
+
+        for stmt in init.iter() {
+            let result = self.eval_statement(stmt);
+
+            match *result {
+                Object::ReturnValue(_) => return Tail::Value(result),
+                Object::RuntimeError(_) => return Tail::Value(result),
+                _ => {}
+            }
+        }
+
+        // The function returns whatever the last statement evaluates to, so
+        // a `return` needs no wrapping here.
+        match last {
+            Statement::Expression(node) => self.eval_tail_expression(&node.expression),
+            Statement::Return(node) => self.eval_tail_expression(&node.return_value),
+            _ => Tail::Value(self.eval_statement(last)),
+        }
+    }
+
+    fn eval_tail_expression(&mut self, ast: &Expression) -> Tail {
+        match ast {
+            Expression::If(ie) => {
+                let condition = self.eval_expression(&ie.condition);
+
+                if self.is_error(&condition) {
+                    return Tail::Value(condition);
+                }
+
+                if self.is_truthy(condition) {
+                    return self.in_block(|this| this.eval_tail_block(&ie.consequence));
+                } else if let Some(ref alternative) = ie.alternative {
+                    return self.in_block(|this| this.eval_tail_block(alternative));
+                }
+
+                Tail::Value(Rc::clone(&self.env.borrow().null_object))
+            }
+            Expression::Match(node) => {
+                let subject = self.eval_expression(&node.subject);
+
+                if self.is_error(&subject) {
+                    return Tail::Value(subject);
+                }
+
+                self.in_block(|this| match this.select_match_arm(node, &subject) {
+                    Ok(Some(body)) => this.eval_tail_block(body),
+                    Ok(None) => Tail::Value(Rc::clone(&this.env.borrow().null_object)),
+                    Err(error) => Tail::Value(error),
+                })
+            }
+            Expression::Call(node) => self
+                .eval_call(node)
+                .unwrap_or_else(|| Tail::Value(Rc::clone(&self.env.borrow().null_object))),
+            _ => Tail::Value(self.eval_expression(ast)),
+        }
+    }
+
+    fn eval_index_expression(&mut self, left: Rc<Object>, node: &IndexExpression) -> Rc<Object> {
         if self.is_error(&left) {
             return left;
         }
@@ -232,9 +846,18 @@ impl Evaluator {
             return index;
         }
 
+        if let (true, Object::Str(ref name)) = (node.member, &*index) {
+            if let Some(method) = self.builtin.lookup_method(&left, &name.value) {
+                return method;
+            }
+        }
+
         match *left {
             Object::Array(ref array) => self.eval_array_index_expression(array, &index),
+            Object::Str(ref string) => self.eval_string_index_expression(string, &index),
             Object::Hash(ref hash) => self.eval_hash_index_expression(hash, &index),
+            Object::Struct(ref instance) => self.eval_struct_field_expression(instance, &index),
+            Object::Range(ref range) => self.eval_range_index_expression(range, &index),
             _ => Object::RuntimeError(RuntimeError::new(format!(
                 "index operator not supported: {}",
                 left.kind()
@@ -245,13 +868,37 @@ impl Evaluator {
 
     fn eval_array_index_expression(&self, array: &Array, index: &Rc<Object>) -> Rc<Object> {
         match **index {
-            Object::Integer(ref index) => {
-                if index.value < 0 || array.elements.len() < (index.value + 1).try_into().unwrap() {
-                    Rc::clone(&self.env.borrow().null_object)
-                } else {
-                    array.elements[index.value as usize].clone()
-                }
-            }
+            Object::Integer(ref index) => array
+                .get(index.value)
+                .unwrap_or_else(|| Rc::clone(&self.env.borrow().null_object)),
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "index is not a integer: {}",
+                index.kind()
+            )))
+            .into(),
+        }
+    }
+
+    fn eval_range_index_expression(&self, range: &Range, index: &Rc<Object>) -> Rc<Object> {
+        match **index {
+            Object::Integer(ref index) => match range.get(index.value) {
+                Some(value) => Object::Integer(Integer::new(value)).into(),
+                None => Rc::clone(&self.env.borrow().null_object),
+            },
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "index is not a integer: {}",
+                index.kind()
+            )))
+            .into(),
+        }
+    }
+
+    fn eval_string_index_expression(&self, string: &Str, index: &Rc<Object>) -> Rc<Object> {
+        match **index {
+            Object::Integer(ref index) => match string.get(index.value) {
+                Some(ch) => Object::Str(ch).into(),
+                None => Rc::clone(&self.env.borrow().null_object),
+            },
             _ => Object::RuntimeError(RuntimeError::new(format!(
                 "index is not a integer: {}",
                 index.kind()
@@ -260,12 +907,69 @@ impl Evaluator {
         }
     }
 
+    fn eval_slice_expression(&mut self, left: Rc<Object>, node: &SliceExpression) -> Rc<Object> {
+        if self.is_error(&left) {
+            return left;
+        }
+
+        let start = match self.eval_slice_bound(&node.start) {
+            Ok(start) => start,
+            Err(error) => return error,
+        };
+        let end = match self.eval_slice_bound(&node.end) {
+            Ok(end) => end,
+            Err(error) => return error,
+        };
+
+        match *left {
+            Object::Array(ref array) => Object::Array(array.slice(start, end)).into(),
+            Object::Str(ref string) => Object::Str(string.slice(start, end)).into(),
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "slice operator not supported: {}",
+                left.kind()
+            )))
+            .into(),
+        }
+    }
+
+    fn eval_slice_bound(
+        &mut self,
+        bound: &Option<Box<Expression>>,
+    ) -> Result<Option<i64>, Rc<Object>> {
+        let value = match bound {
+            Some(ref bound) => self.eval_expression(bound),
+            None => return Ok(None),
+        };
+
+        match *value {
+            Object::Integer(ref integer) => Ok(Some(integer.value)),
+            Object::Null(_) => Ok(None),
+            Object::RuntimeError(_) => Err(value),
+            _ => Err(Object::RuntimeError(RuntimeError::new(format!(
+                "slice index is not a integer: {}",
+                value.kind()
+            )))
+            .into()),
+        }
+    }
+
+    fn eval_struct_field_expression(&self, instance: &Struct, field: &Rc<Object>) -> Rc<Object> {
+        match **field {
+            Object::Str(ref field) => instance
+                .get(&field.value)
+                .unwrap_or_else(|| instance.unknown_field(&field.value).into()),
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "struct field must be STRING, got {}",
+                field.kind()
+            )))
+            .into(),
+        }
+    }
+
     fn eval_hash_index_expression(&self, hash: &Hash, index: &Rc<Object>) -> Rc<Object> {
-        let key = match **index {
-            Object::Integer(ref o) => o.hash_key(),
-            Object::Str(ref o) => o.hash_key(),
-            Object::Boolean(ref o) => o.hash_key(),
-            _ => {
+        let key = match index.hash_key() {
+            Some(key) => key,
+            None => {
                 return Rc::new(Object::RuntimeError(RuntimeError::new(format!(
                     "unusable as hash key: {}",
                     index.kind()
@@ -274,8 +978,7 @@ impl Evaluator {
         };
 
         Rc::clone(
-            hash.value
-                .get(&key)
+            hash.get(&key)
                 .unwrap_or(&Rc::clone(&self.env.borrow().null_object)),
         )
     }
@@ -305,12 +1008,25 @@ impl Evaluator {
             return left;
         }
 
+        if node.operator == "??" {
+            return match *left {
+                Object::Null(_) => self.eval_expression(&node.right),
+                _ => left,
+            };
+        }
+
         let right = self.eval_expression(&node.right);
 
         if self.is_error(&right) {
             return right;
         }
 
+        match node.operator.as_str() {
+            ".." | "..=" => return self.eval_range_expression(&node.operator, &left, &right),
+            "in" => return self.eval_in_expression(&left, &right),
+            _ => {}
+        }
+
         if left.kind() != right.kind() {
             return Object::RuntimeError(RuntimeError::new(format!(
                 "type mismatch: {} {} {}",
@@ -399,12 +1115,13 @@ impl Evaluator {
     }
 
     fn eval_function_literal(&mut self, node: &FunctionLiteral) -> Rc<Object> {
-        Object::Function(Function::new(
+        let mut func = Function::new(
             node.parameters.to_owned(),
             node.body.to_owned(),
             Rc::clone(&self.env),
-        ))
-        .into()
+        );
+        func.is_generator = node.is_generator;
+        Object::Function(func).into()
     }
 
     fn eval_array_literal(&mut self, node: &ArrayLiteral) -> Rc<Object> {
@@ -419,23 +1136,38 @@ impl Evaluator {
         let mut hash_value = HashMap::new();
 
         for member in node.members.iter() {
+            if let Expression::Spread(ref spread) = member.key {
+                let other = self.eval_expression(&spread.value);
+                if self.is_error(&other) {
+                    return other;
+                }
+                match *other {
+                    Object::Hash(ref other) => hash_value.extend(
+                        other
+                            .value
+                            .iter()
+                            .map(|(k, pair)| (k.to_owned(), pair.to_owned())),
+                    ),
+                    _ => {
+                        return Object::RuntimeError(RuntimeError::new(format!(
+                            "spread operator requires HASH, got {}",
+                            other.kind()
+                        )))
+                        .into()
+                    }
+                }
+                continue;
+            }
+
             let key = self.eval_expression(&member.key);
 
             if self.is_error(&key) {
                 return key;
             }
 
-            let hash_key = match *key {
-                Object::Str(ref o) => o.hash_key(),
-                Object::Integer(ref o) => o.hash_key(),
-                Object::Boolean(ref o) => o.hash_key(),
-                _ => {
-                    return Object::RuntimeError(RuntimeError::new(format!(
-                        "only string, integer and boolean can be hash key, found {}",
-                        key.kind()
-                    )))
-                    .into()
-                }
+            let hash_key = match key.hash_key() {
+                Some(hash_key) => hash_key,
+                None => return self.unusable_hash_key_error(&key),
             };
 
             let value = self.eval_expression(&member.value);
@@ -443,18 +1175,206 @@ impl Evaluator {
             if self.is_error(&value) {
                 return value;
             }
-            hash_value.insert(hash_key, value);
+            hash_value.insert(hash_key, HashPair::new(key, value));
         }
 
         Object::Hash(Hash::new(hash_value)).into()
     }
 
-    fn eval_bang_operator_expression(&self, right: Rc<Object>) -> Rc<Object> {
-        match *right {
-            Object::Boolean(ref object) => {
-                if object.value {
-                    Rc::clone(&self.env.borrow().false_object)
-                } else {
+    fn eval_range_expression(
+        &self,
+        operator: &str,
+        start: &Rc<Object>,
+        end: &Rc<Object>,
+    ) -> Rc<Object> {
+        match (&**start, &**end) {
+            (Object::Integer(start), Object::Integer(end)) => {
+                Object::Range(Range::new(start.value, end.value, operator == "..=")).into()
+            }
+            _ => Object::RuntimeError(RuntimeError::new(format!(
+                "unsupported range bounds: {}..{}",
+                start.kind(),
+                end.kind()
+            )))
+            .into(),
+        }
+    }
+
+    fn eval_in_expression(&self, value: &Rc<Object>, container: &Rc<Object>) -> Rc<Object> {
+        match container.contains(value) {
+            Some(found) => self.native_bool_to_boolean_object(found),
+            None => Object::RuntimeError(RuntimeError::new(format!(
+                "in operator not supported: {}",
+                container.kind()
+            )))
+            .into(),
+        }
+    }
+
+    fn unusable_hash_key_error(&self, key: &Object) -> Rc<Object> {
+        Object::RuntimeError(RuntimeError::new(format!(
+            "only string, integer, boolean and struct can be hash key, found {}",
+            key.kind()
+        )))
+        .into()
+    }
+
+    fn eval_list_comprehension(&mut self, node: &ListComprehension) -> Rc<Object> {
+        let mut elements = vec![];
+
+        let error = self.eval_comprehension_clause(&node.clause, &mut |this| {
+            let element = this.eval_expression(&node.element);
+            if this.is_error(&element) {
+                return Some(element);
+            }
+            elements.push(element);
+            None
+        });
+
+        match error {
+            Some(error) => error,
+            None => Object::Array(Array::new(elements)).into(),
+        }
+    }
+
+    fn eval_hash_comprehension(&mut self, node: &HashComprehension) -> Rc<Object> {
+        let mut hash_value = HashMap::new();
+
+        let error = self.eval_comprehension_clause(&node.clause, &mut |this| {
+            let key = this.eval_expression(&node.key);
+            if this.is_error(&key) {
+                return Some(key);
+            }
+            let hash_key = match key.hash_key() {
+                Some(hash_key) => hash_key,
+                None => return Some(this.unusable_hash_key_error(&key)),
+            };
+            let value = this.eval_expression(&node.value);
+            if this.is_error(&value) {
+                return Some(value);
+            }
+            hash_value.insert(hash_key, HashPair::new(key, value));
+            None
+        });
+
+        match error {
+            Some(error) => error,
+            None => Object::Hash(Hash::new(hash_value)).into(),
+        }
+    }
+
+    /// Binds each item of the clause's iterable and calls `collect` for those
+    /// passing its condition. Returns the first error raised along the way.
+    fn eval_comprehension_clause(
+        &mut self,
+        clause: &ComprehensionClause,
+        collect: &mut dyn FnMut(&mut Self) -> Option<Rc<Object>>,
+    ) -> Option<Rc<Object>> {
+        let mut items = match self.eval_comprehension_items(clause) {
+            Ok(items) => items,
+            Err(error) => return Some(error),
+        };
+
+        let error = loop {
+            match self.bind_next_item(clause, &mut items) {
+                Ok(true) => {
+                    if let Some(error) = collect(self) {
+                        break Some(error);
+                    }
+                }
+                Ok(false) => break None,
+                Err(error) => break Some(error),
+            }
+        };
+        self.env = items.env;
+        error
+    }
+
+    fn eval_comprehension_items(
+        &mut self,
+        clause: &ComprehensionClause,
+    ) -> Result<ComprehensionItems, Rc<Object>> {
+        let iterable = self.eval_expression(&clause.iterable);
+        if self.is_error(&iterable) {
+            return Err(iterable);
+        }
+
+        // Arrays and ranges are indexed in place; anything else iterable is
+        // collected up front.
+        let items = match *iterable {
+            Object::Array(_) | Object::Range(_) => Rc::clone(&iterable),
+            _ => match iterable.iter_items() {
+                Some(items) => Object::Array(Array::new(items.collect())).into(),
+                None => {
+                    return Err(Object::RuntimeError(RuntimeError::new(format!(
+                        "not iterable: {}",
+                        iterable.kind()
+                    )))
+                    .into())
+                }
+            },
+        };
+
+        Ok(ComprehensionItems {
+            items,
+            index: 0,
+            env: Rc::clone(&self.env),
+        })
+    }
+
+    /// Binds the next item passing the clause's condition, returning false
+    /// once the items are exhausted. Each item is bound in its own enviroment
+    /// enclosed by the comprehension's, which is switched back to at the end.
+    fn bind_next_item(
+        &mut self,
+        clause: &ComprehensionClause,
+        items: &mut ComprehensionItems,
+    ) -> Result<bool, Rc<Object>> {
+        let outer = Rc::clone(&items.env);
+        for item in items {
+            self.env = Rc::new(RefCell::new(Enviroment::new(Rc::clone(&outer))));
+
+            if let [ref binding] = clause.bindings[..] {
+                self.bind(&binding.value, item, false)?;
+            } else {
+                let pair = match *item {
+                    Object::Array(ref pair) if pair.elements.len() == 2 => pair,
+                    _ => {
+                        return Err(Object::RuntimeError(RuntimeError::new(format!(
+                            "cannot destructure {} into 2 bindings",
+                            item.kind()
+                        )))
+                        .into())
+                    }
+                };
+                for (binding, value) in clause.bindings.iter().zip(pair.elements.iter()) {
+                    self.bind(&binding.value, Rc::clone(value), false)?;
+                }
+            }
+
+            if let Some(ref condition) = clause.condition {
+                let condition = self.eval_expression(condition);
+                if self.is_error(&condition) {
+                    return Err(condition);
+                }
+                if !self.is_truthy(condition) {
+                    continue;
+                }
+            }
+
+            return Ok(true);
+        }
+
+        self.env = outer;
+        Ok(false)
+    }
+
+    fn eval_bang_operator_expression(&self, right: Rc<Object>) -> Rc<Object> {
+        match *right {
+            Object::Boolean(ref object) => {
+                if object.value {
+                    Rc::clone(&self.env.borrow().false_object)
+                } else {
                     Rc::clone(&self.env.borrow().true_object)
                 }
             }
@@ -482,14 +1402,103 @@ impl Evaluator {
         }
 
         if self.is_truthy(condition) {
-            return self.eval_block_statement(&ie.consequence);
+            return self.in_block(|this| this.eval_block_statement(&ie.consequence));
         } else if let Some(ref alternative) = ie.alternative {
-            return self.eval_block_statement(alternative);
+            return self.in_block(|this| this.eval_block_statement(alternative));
         }
 
         Rc::clone(&self.env.borrow().null_object)
     }
 
+    fn eval_match_expression(&mut self, node: &MatchExpression) -> Rc<Object> {
+        let subject = self.eval_expression(&node.subject);
+
+        if self.is_error(&subject) {
+            return subject;
+        }
+
+        self.in_block(|this| match this.select_match_arm(node, &subject) {
+            Ok(Some(body)) => this.eval_block_statement(body),
+            Ok(None) => Rc::clone(&this.env.borrow().null_object),
+            Err(error) => error,
+        })
+    }
+
+    /// Runs `eval` in a new enviroment enclosed by the current one, so the
+    /// names a block defines are not visible after it.
+    fn in_block<T>(&mut self, eval: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = self.enter_block();
+        let result = eval(self);
+        self.env = outer;
+        result
+    }
+
+    /// Switches to a new enviroment for a block, returning the one to go back
+    /// to once it ends.
+    fn enter_block(&mut self) -> Rc<RefCell<Enviroment>> {
+        let block_env = Rc::new(RefCell::new(Enviroment::new(Rc::clone(&self.env))));
+        std::mem::replace(&mut self.env, block_env)
+    }
+
+    /// Finds the first arm matching `subject` and binds its payload, returning
+    /// the body to run or `None` when no arm matches.
+    fn select_match_arm<'a>(
+        &mut self,
+        node: &'a MatchExpression,
+        subject: &Rc<Object>,
+    ) -> Result<Option<&'a BlockStatement>, Rc<Object>> {
+        for arm in node.arms.iter() {
+            match arm.pattern {
+                Pattern::Wildcard => return Ok(Some(&arm.body)),
+                Pattern::Variant(ref name, ref bindings) => {
+                    let tag = self.pattern_tag(name)?;
+                    let variant = match **subject {
+                        Object::Variant(ref variant) if variant.tag() == tag => variant,
+                        _ => continue,
+                    };
+
+                    if variant.values.len() != bindings.len() {
+                        return Err(Object::RuntimeError(RuntimeError::new(format!(
+                            "wrong number of bindings for {}: got={}, want={}",
+                            name,
+                            bindings.len(),
+                            variant.values.len()
+                        )))
+                        .into());
+                    }
+
+                    for (binding, value) in bindings.iter().zip(variant.values.iter()) {
+                        self.bind(&binding.value, Rc::clone(value), false)?;
+                    }
+
+                    return Ok(Some(&arm.body));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The tag of the variant a match arm names, found by looking the name
+    /// up like the VM does.
+    fn pattern_tag(&self, name: &Identifier) -> Result<String, Rc<Object>> {
+        let pattern = self.eval_indentifier(name);
+        match *pattern {
+            Object::VariantDefinition(ref definition) => Ok(definition.tag()),
+            Object::Variant(ref variant) => Ok(variant.tag()),
+            Object::RuntimeError(_) => Err(Object::RuntimeError(RuntimeError::new(format!(
+                "unknown variant {}",
+                name.value
+            )))
+            .into()),
+            _ => Err(Object::RuntimeError(RuntimeError::new(format!(
+                "not an enum variant: {}",
+                pattern.inspect()
+            )))
+            .into()),
+        }
+    }
+
     fn eval_indentifier(&self, node: &Identifier) -> Rc<Object> {
         if let Some(value) = self.env.borrow().get(node.value.to_owned()) {
             value
@@ -526,11 +1535,95 @@ impl Evaluator {
     }
 }
 
+/// The items a comprehension walks through, along with its position.
+#[derive(Debug, PartialEq, Clone)]
+struct ComprehensionItems {
+    items: Rc<Object>,
+    index: usize,
+    /// The enviroment the comprehension is evaluated in.
+    env: Rc<RefCell<Enviroment>>,
+}
+
+impl Iterator for ComprehensionItems {
+    type Item = Rc<Object>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match *self.items {
+            Object::Array(ref array) => array.elements.get(self.index).cloned(),
+            Object::Range(ref range) => range
+                .get(self.index as i64)
+                .map(|value| Object::Integer(Integer::new(value)).into()),
+            _ => unreachable!(),
+        };
+        self.index += 1;
+        item
+    }
+}
+
+/// The outcome of evaluating in tail position: either a value, or a call to
+/// a `Function` object that is left to the caller.
+enum Tail {
+    Value(Rc<Object>),
+    Call(Rc<Object>, Vec<Rc<Object>>),
+}
+
+/// The rest of a suspended generator call. The work left to do is kept as an
+/// explicit stack rather than on the Rust stack, so it survives returning
+/// the yielded value to the caller.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Continuation {
+    env: Rc<RefCell<Enviroment>>,
+    frames: Vec<ContinuationFrame>,
+}
+
+impl Continuation {
+    fn new(env: Rc<RefCell<Enviroment>>, body: &BlockStatement) -> Self {
+        Self {
+            env,
+            frames: vec![ContinuationFrame::Block(body.to_owned(), 0)],
+        }
+    }
+}
+
+/// A piece of pending work. Frames that wait on a value receive the result
+/// of the frames pushed above them.
+#[derive(Debug, PartialEq, Clone)]
+enum ContinuationFrame {
+    /// Evaluates an expression in a position a generator can resume from.
+    Expression(Expression),
+    /// Runs the statements of a block from the given index on.
+    Block(BlockStatement, usize),
+    /// Binds the received value, as a constant if the flag is set.
+    Let(String, bool),
+    /// Assigns the received value to an existing binding, or to a field of
+    /// the struct bound.
+    Assign(Identifier, Option<String>),
+    /// Goes back to the enviroment a block was entered from.
+    Leave(Rc<RefCell<Enviroment>>),
+    /// Finishes the generator, discarding the received value.
+    Return,
+    /// Collects the received element and moves on to the next item.
+    ListComprehension(ListComprehension, ComprehensionItems, Vec<Rc<Object>>),
+    /// Receives the key of the current item and schedules its value.
+    HashKey(
+        HashComprehension,
+        ComprehensionItems,
+        HashMap<HashKey, HashPair>,
+    ),
+    /// Collects the received value under the current key and moves on.
+    HashValue(
+        HashComprehension,
+        ComprehensionItems,
+        HashMap<HashKey, HashPair>,
+        Rc<Object>,
+    ),
+}
+
 #[cfg(test)]
 mod tests {
     use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-    use super::Evaluator;
+    use super::{with_stack, Evaluator};
     use crate::{
         enviroment::Enviroment,
         lexer::Lexer,
@@ -574,6 +1667,10 @@ mod tests {
             ("20 + 2 * -10", 0),
             ("50 / 2 * 2 + 10", 60),
             ("2 * (5 + 10)", 30),
+            ("0xFF + 0o17 + 0b101", 275),
+            ("1_000_000 - 0x1_0", 999984),
+            ("-9223372036854775808", i64::MIN),
+            ("-9223372036854775807 - 1", i64::MIN),
             ("3 * 3 * 3 + 10", 37),
             ("3 * (3 * 3) + 10", 37),
             ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
@@ -696,6 +1793,7 @@ mod tests {
                 "unknown operator: BOOLEAN + BOOLEAN",
             ),
             ("foobar", "identifier not found: foobar"),
+            ("foobar = 1", "identifier not found: foobar"),
             ("\"Hello\" - \"World\"", "unknown operator: STRING - STRING"),
             (
                 r#"{"name": "Monkey"}[fn(x) { x }];"#,
@@ -767,6 +1865,69 @@ mod tests {
         test_integer_object(&test_eval(input), 4);
     }
 
+    #[test]
+    fn test_assignments() {
+        let tests = [
+            ("let a = 1; a = a + 1; a", 2),
+            ("let a = 1; let b = a = 5; a + b", 10),
+            (
+                "let makeCounter = fn() { let n = 0; fn() { n = n + 1; n } };
+                let a = makeCounter();
+                let b = makeCounter();
+                a(); a(); b(); a()",
+                3,
+            ),
+            (
+                "let f = fn() { let n = 1; let get = fn() { n }; n = 5; get() }; f()",
+                5,
+            ),
+            ("let x = 1; let f = fn() { x = x + 1 }; f(); f(); x", 3),
+        ];
+
+        for (input, output) in tests.iter() {
+            test_integer_object(&test_eval(input), *output);
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        let tests = [
+            ("const a = 1; a", 1),
+            ("const a = 1; fn() { let a = 2; a = a + 1; a }()", 3),
+            ("const a = 1; if (true) { let a = 2; a = 3 }; a", 1),
+            (
+                "fn* gen() { const a = yield 1; yield 2 } let g = gen(); next(g); next(g)",
+                2,
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            test_integer_object(&test_eval(input), *output);
+        }
+
+        let errors = [
+            ("const a = 1; a = 2; a", "cannot assign to constant a"),
+            ("const a = 1; let a = 2; a", "cannot redefine constant a"),
+            (
+                "let a = 1; const a = 2; a",
+                "cannot redefine a as a constant",
+            ),
+            (
+                "const a = 1; fn() { a = 2 }(); a",
+                "cannot assign to constant a",
+            ),
+            ("const a = 1; struct a { x }", "cannot redefine constant a"),
+            (
+                "fn* gen() { const a = 1; a = yield a } let g = gen(); next(g); next(g)",
+                "cannot assign to constant a",
+            ),
+        ];
+
+        for (input, message) in errors.iter() {
+            test_error_object(&test_eval(input), message);
+        }
+    }
+
     #[test]
     fn test_string_literal() {
         let input = "\"Hello world!\"";
@@ -847,9 +2008,11 @@ mod tests {
                 6,
             ),
             ("let myArray = [1, 2, 3]; let i = myArray[0]; myArray[i]", 2),
+            ("[1, 2, 3][-1]", 3),
+            ("[1, 2, 3][-3]", 1),
         ];
 
-        let null_tests = ["[1, 2, 3][3]", "[1, 2, 3][-1]"];
+        let null_tests = ["[1, 2, 3][3]", "[1, 2, 3][-4]"];
 
         for (input, output) in tests.iter() {
             test_integer_object(&test_eval(input), *output);
@@ -860,6 +2023,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_string_index_expressions() {
+        let tests = [
+            (r#""hello"[0]"#, Some("h")),
+            (r#""hello"[-1]"#, Some("o")),
+            (r#""héllo"[1]"#, Some("é")),
+            (r#""hello"[5]"#, None),
+            (r#""hello"[-6]"#, None),
+        ];
+
+        for (input, output) in tests.iter() {
+            let evaluated = test_eval(input);
+            if let Some(string) = output {
+                test_string_object(&evaluated, string);
+            } else {
+                test_null_object(&evaluated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_slice_expressions() {
+        let tests = [
+            ("[1, 2, 3, 4][1:3]", "[2, 3]"),
+            ("[1, 2, 3, 4][:2]", "[1, 2]"),
+            ("[1, 2, 3, 4][2:]", "[3, 4]"),
+            ("[1, 2, 3, 4][:]", "[1, 2, 3, 4]"),
+            ("[1, 2, 3, 4][-2:]", "[3, 4]"),
+            ("[1, 2, 3, 4][:-1]", "[1, 2, 3]"),
+            ("[1, 2, 3, 4][3:1]", "[]"),
+            ("[1, 2, 3, 4][-10:10]", "[1, 2, 3, 4]"),
+            (r#""monkey"[1:3]"#, "on"),
+            (r#""monkey"[3:]"#, "key"),
+            (r#""monkey"[:-3]"#, "mon"),
+            (r#""héllo"[1:3]"#, "él"),
+            (r#""monkey"[10:]"#, ""),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            (r#"[1, 2][true:]"#, "slice index is not a integer: BOOLEAN"),
+            (r#"{}[1:2]"#, "slice operator not supported: HASH"),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
     #[test]
     fn test_hash_literals() {
         let input = r#"
@@ -902,7 +2117,10 @@ mod tests {
                     Object::Integer(Integer::new(6)).into(),
                 ),
             ]);
-            assert_eq!(hash.value, expected);
+            assert_eq!(hash.value.len(), expected.len());
+            for (key, value) in expected.iter() {
+                assert_eq!(hash.get(key), Some(value));
+            }
         } else {
             panic!("not a hash")
         }
@@ -929,6 +2147,467 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_member_expressions() {
+        let tests = [
+            (r#"let book = {"title": "Monkey"}; book.title"#, "Monkey"),
+            (r#"let book = {"title": "Monkey"}; book.author"#, "null"),
+            (r#"{"a": {"b": 2}}.a.b"#, "2"),
+            (r#"let counter = {"next": fn(x) { x + 1 }}; counter.next(1)"#, "2"),
+            ("[1, 2, 3].push(4)", "[1, 2, 3, 4]"),
+            (r#""monkey".len()"#, "6"),
+            ("let arr = [1, 2, 3]; arr.rest().first()", "2"),
+            ("let f = [1].len; f()", "1"),
+            (r#"let h = {"a": 1, "b": 2}; h.len()"#, "2"),
+            (r#"let h = {"len": 5}; h.len"#, "5"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            ("[1].len(2)", "wrong number of arguments. got=2, want=1"),
+            ("5.first()", "argument to `first` must be ARRAY, got INTEGER"),
+            (r#"[1, 2]["push"]"#, "index is not a integer: STRING"),
+            (r#""abc"["a"]"#, "index is not a integer: STRING"),
+            ("5.gensym()", "index operator not supported: INTEGER"),
+            ("quote(1).ast_kind()", "index operator not supported: QUOTE"),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_structs() {
+        let tests = [
+            ("struct Point { x, y }; Point", "struct Point { x, y }"),
+            ("struct Point { x, y }; Point(1, 2)", "Point { x: 1, y: 2 }"),
+            ("struct P {}; [P, P()]", "[struct P {}, P {}]"),
+            ("struct Point { x, y }; let p = Point(1, 2); p.y", "2"),
+            (r#"struct Point { x, y }; Point(1, 2)["x"]"#, "1"),
+            ("struct Point { x, y }; Point(1, 2) == Point(1, 2)", "true"),
+            ("struct Point { x, y }; Point(1, 2) != Point(2, 1)", "true"),
+            ("struct A { x }; struct B { x }; A(1) == B(1)", "false"),
+            ("struct Size { len }; Size(3).len", "3"),
+            (r#"struct P { x }; let h = {P(1): "one"}; h[P(1)]"#, "one"),
+            (r#"struct P { x }; let h = {P(1): "one"}; h[P("1")]"#, "null"),
+            ("struct P { x }; let h = {P(P(1)): 2}; h[P(P(1))]", "2"),
+            (
+                "let make = fn() { struct Pair { a, b }; Pair(1, [2]) }; make()",
+                "Pair { a: 1, b: [2] }",
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            (
+                "struct Point { x, y }; Point(1)",
+                "wrong number of fields for Point: got=1, want=2",
+            ),
+            (
+                "struct Point { x, y }; Point(1, 2).z",
+                "unknown field z for struct Point",
+            ),
+            (
+                "struct Point { x, y }; Point(1, 2)[0]",
+                "struct field must be STRING, got INTEGER",
+            ),
+            (
+                "struct P { x }; {P([1]): 1}",
+                "only string, integer, boolean and struct can be hash key, found STRUCT",
+            ),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_field_assignment() {
+        let tests = [
+            ("let p = Point(1, 2); p.x = 5; p", "Point { x: 5, y: 2 }"),
+            ("let p = Point(1, 2); p.x = 5", "5"),
+            ("let p = Point(1, 2); let q = p; p.x = 5; q", "Point { x: 1, y: 2 }"),
+            (
+                "let f = fn() { let p = Point(1, 2); p.y = p.y + 1; p }; f()",
+                "Point { x: 1, y: 3 }",
+            ),
+            (
+                "let f = fn() { let p = Point(1, 2); let set = fn(v) { p.x = v }; set(7); p.x }; f()",
+                "7",
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            let input = format!("struct Point {{ x, y }}; {}", input);
+            assert_eq!(test_eval(&input).inspect(), *output, "{}", input);
+        }
+
+        let error_tests = [
+            (
+                "struct Point { x, y }; let p = Point(1, 2); p.z = 1",
+                "unknown field z for struct Point",
+            ),
+            (
+                r#"let h = {"a": 1}; h.a = 2"#,
+                "field assignment not supported: HASH",
+            ),
+            ("q.x = 1", "identifier not found: q"),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_enums() {
+        let shapes = "
+enum Shape { Circle(r), Rect(w, h), Empty }
+let area = fn(shape) {
+    match (shape) {
+        Circle(r) => 3 * r * r,
+        Rect(w, h) => { w * h }
+        Empty => 0
+    }
+};
+";
+        let tests = [
+            ("Circle(2)", "Circle(2)"),
+            ("Empty", "Empty"),
+            ("Rect", "Shape::Rect(w, h)"),
+            ("area(Circle(2))", "12"),
+            ("area(Rect(2, 3))", "6"),
+            ("area(Empty)", "0"),
+            ("Rect(1, 2) == Rect(1, 2)", "true"),
+            ("Rect(1, 2) == Rect(2, 1)", "false"),
+            ("match (5) { Circle(r) => r }", "null"),
+            ("match (5) { Circle(r) => r, _ => 7 }", "7"),
+            ("let f = fn() { match (Circle(1)) { Circle(r) => { return r; } }; 9 }; f()", "1"),
+            (
+                "let c = Circle(1); enum Round { Circle(r) } match (c) { Circle(r) => r, _ => 0 }",
+                "0",
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            let input = format!("{}{}", shapes, input);
+            assert_eq!(test_eval(&input).inspect(), *output);
+        }
+
+        let error_tests = [
+            ("Rect(1)", "wrong number of fields for Rect: got=1, want=2"),
+            (
+                "match (Rect(1, 2)) { Rect(w) => w }",
+                "wrong number of bindings for Rect: got=1, want=2",
+            ),
+            ("match (Empty) { Nothing => 1 }", "unknown variant Nothing"),
+            (
+                "let one = 1; match (Empty) { one => 1 }",
+                "not an enum variant: 1",
+            ),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            let input = format!("{}{}", shapes, input);
+            test_error_object(&test_eval(&input), output);
+        }
+    }
+
+    #[test]
+    fn test_pipe_expressions() {
+        let tests = [
+            ("let double = fn(x) { x * 2 }; 3 |> double", "6"),
+            ("let add = fn(a, b) { a + b }; 1 |> add(2) |> add(3)", "6"),
+            ("[1, 2, 3] |> rest |> push(4)", "[2, 3, 4]"),
+            (r#""monkey" |> len"#, "6"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+    }
+
+    #[test]
+    fn test_null_safe_expressions() {
+        let tests = [
+            (r#"let h = {"a": {"b": 1}}; h?.a?.b"#, "1"),
+            (r#"let h = {"a": {"b": 1}}; h?.x?.b"#, "null"),
+            (r#"let h = {}; h?.x?.y?["z"]"#, "null"),
+            ("let n = if (false) { 1 }; n?[missing]", "null"),
+            (r#"{}["x"] ?? 5"#, "5"),
+            ("false ?? 5", "false"),
+            ("0 ?? 5", "0"),
+            ("1 ?? missing", "1"),
+            (r#"let h = {"port": 0}; h?.port ?? 80"#, "0"),
+            (r#"{}.a?.b ?? "none""#, "none"),
+            (r#"{}.a ?? {}.b ?? 3"#, "3"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+    }
+
+    #[test]
+    fn test_spread_expressions() {
+        let tests = [
+            ("let a = [1, 2]; let b = [3]; [...a, ...b, 4]", "[1, 2, 3, 4]"),
+            ("[...[], 1, ...[]]", "[1]"),
+            ("let add = fn(a, b, c) { a + b + c }; let args = [2, 3]; add(1, ...args)", "6"),
+            ("push(...[[1], 2])", "[1, 2]"),
+            (r#"let d = {"a": 1, "b": 2}; {...d, "b": 3}["b"]"#, "3"),
+            (r#"let d = {"a": 1, "b": 2}; {"b": 3, ...d}["b"]"#, "2"),
+            (r#"let d = {"a": 1}; len([...rest([0, ...[1, 2]])])"#, "2"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            ("[...1]", "spread operator requires ARRAY, got INTEGER"),
+            ("{...[1]}", "spread operator requires HASH, got ARRAY"),
+            ("...[1]", "spread operator is not allowed here"),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_comprehensions() {
+        let tests = [
+            ("[x * 2 for x in [1, 2, 3]]", "[2, 4, 6]"),
+            ("[x * 2 for x in [1, 2, 3] if x > 1]", "[4, 6]"),
+            ("[x for x in []]", "[]"),
+            ("[i * x for i, x in [[1, 2], [3, 4]]]", "[2, 12]"),
+            (
+                "let xs = [1, 2]; [[y * x for y in xs] for x in xs]",
+                "[[1, 2], [2, 4]]",
+            ),
+            (r#"[v for k, v in {"a": 1}]"#, "[1]"),
+            (
+                r#"let h = {"a": 1, "b": 2}; {k: v * 10 for k, v in h}["b"]"#,
+                "20",
+            ),
+            ("{x: x * x for x in [1, 2, 3] if x != 2}[3]", "9"),
+            ("{x: x for x in [1, 1]}", "{1: 1}"),
+            (
+                "let f = fn(xs) { [x + 1 for x in xs] }; f([1, 2])",
+                "[2, 3]",
+            ),
+            ("let x = 1; [x for x in [5]]; x", "1"),
+            (
+                "let f = fn() { let x = 1; {x: 1 for x in [5]}; x }; f()",
+                "1",
+            ),
+            ("let fs = [fn() { x } for x in [1, 2]]; fs[0]()", "1"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            ("[x for x in 1]", "not iterable: INTEGER"),
+            (
+                "[a for a, b in [1]]",
+                "cannot destructure INTEGER into 2 bindings",
+            ),
+            ("[x / y for x in [1]]", "identifier not found: y"),
+            ("[x for x in [1]]; x", "identifier not found: x"),
+            (
+                "{fn() {}: 1 for x in [1]}",
+                "only string, integer, boolean and struct can be hash key, found FUNCTION",
+            ),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_generators() {
+        let tests = [
+            (
+                "fn* gen() { yield 1; yield 2; } let g = gen(); [next(g), g.next(), next(g)]",
+                "[1, 2, null]",
+            ),
+            (
+                "fn* gen() { yield 1; return 2; yield 3 } let g = gen(); [next(g), next(g), next(g)]",
+                "[1, null, null]",
+            ),
+            (
+                "let count = fn*(n) { [yield i for i in 0..n] }; let g = count(2); [next(g), next(g), next(g)]",
+                "[0, 1, null]",
+            ),
+            (
+                "fn* naturals() { [yield n for n in 0..9223372036854775807]; } let g = naturals(); next(g); next(g); next(g)",
+                "2",
+            ),
+            (
+                "fn* gen(x) { if (x > 1) { yield 1; yield x } else { yield 0 }; yield -1 } let g = gen(5); [next(g), next(g), next(g), next(g)]",
+                "[1, 5, -1, null]",
+            ),
+            (
+                "enum E { A(x), B }; fn* gen(e) { match (e) { A(x) => { yield x }, _ => { yield 0 } } }; [next(gen(A(7))), next(gen(B))]",
+                "[7, 0]",
+            ),
+            (
+                "fn* gen() { let x = yield 1; yield x; } let g = gen(); next(g); next(g)",
+                "null",
+            ),
+            (
+                "fn* gen() { let h = {k: yield k for k in [1, 2]}; yield 2 in h } let g = gen(); [next(g), next(g), next(g)]",
+                "[1, 2, true]",
+            ),
+            (
+                "fn* gen() { yield 1; if (true) { return 2 }; yield 3 } let g = gen(); [next(g), next(g), next(g)]",
+                "[1, null, null]",
+            ),
+            (
+                "fn* gen() { yield 1; yield 2 } let g = gen(); let h = g; next(g); [next(h), next(gen())]",
+                "[2, 1]",
+            ),
+            (
+                "fn* gen() { let x = 1; x = yield x; yield [x] } let g = gen(); [next(g), next(g), next(g)]",
+                "[1, [null], null]",
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            (
+                "next(1)",
+                "argument to `next` must be GENERATOR, got INTEGER",
+            ),
+            (
+                "fn* gen() { yield x } next(gen())",
+                "identifier not found: x",
+            ),
+            (
+                "fn* gen() { yield next(g) } let g = gen(); next(g)",
+                "generator is already running",
+            ),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let tests = [
+            (
+                "let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } }; count(100000, 0)",
+                "100000",
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { return true; } odd(n - 1) };
+                let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+                even(10001)",
+                "false",
+            ),
+            (
+                "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } }; sum(1000)",
+                "500500",
+            ),
+        ];
+
+        with_stack(|| {
+            for (input, output) in tests.iter() {
+                assert_eq!(test_eval(input).inspect(), *output);
+            }
+
+            test_error_object(
+                &test_eval(
+                    "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } }; sum(100000)",
+                ),
+                "maximum recursion depth exceeded",
+            );
+            test_error_object(
+                &test_eval("fn* gen(n) { yield n + next(gen(n + 1)) } next(gen(0))"),
+                "maximum recursion depth exceeded",
+            );
+        });
+    }
+
+    #[test]
+    fn test_task_builtins_need_vm() {
+        let tests = [
+            ("channel()", "`channel` is only available in the vm engine"),
+            (
+                "spawn(fn() { 1 })",
+                "`spawn` is only available in the vm engine",
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
+    #[test]
+    fn test_ranges() {
+        let tests = [
+            ("0..3", "0..3"),
+            ("1..=3", "1..=3"),
+            ("len(0..10)", "10"),
+            ("len(1..=10)", "10"),
+            ("len(5..1)", "0"),
+            ("(0..1000000000000)[-1]", "999999999999"),
+            ("(2..=4)[0]", "2"),
+            ("(2..4)[2]", "null"),
+            ("(0..3).len()", "3"),
+            ("len(0..9223372036854775807)", "9223372036854775807"),
+            (
+                "let r = -9223372036854775808..=9223372036854775807; [r[0], r[-1]]",
+                "[-9223372036854775808, 9223372036854775807]",
+            ),
+            ("[...0..3]", "[0, 1, 2]"),
+            ("[x * x for x in 1..=3]", "[1, 4, 9]"),
+            ("5 in 0..1000000000000", "true"),
+            ("3 in 0..3", "false"),
+            ("3 in 0..=3", "true"),
+            (r#""a" in 0..3"#, "false"),
+            ("2 in [1, 2]", "true"),
+            (r#""b" in {"a": 1}"#, "false"),
+            (r#""ell" in "hello""#, "true"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output);
+        }
+
+        let error_tests = [
+            (r#"0.."a""#, "unsupported range bounds: INTEGER..STRING"),
+            ("1 in 2", "in operator not supported: INTEGER"),
+            (r#"(0..2)["a"]"#, "index is not a integer: STRING"),
+            (
+                "len(-9223372036854775808..=9223372036854775807)",
+                "range -9223372036854775808..=9223372036854775807 is too long for `len`",
+            ),
+        ];
+
+        for (input, output) in error_tests.iter() {
+            test_error_object(&test_eval(input), output);
+        }
+    }
+
     #[test]
     fn test_quote() {
         let tests = [
@@ -966,4 +2645,81 @@ mod tests {
             test_quote_object(&test_eval(input), output);
         }
     }
+
+    #[test]
+    fn test_quote_errors() {
+        let tests = [
+            ("quote()", "wrong number of arguments. got=0, want=1 or 2"),
+            (
+                "quote(1, [a], 2)",
+                "wrong number of arguments. got=3, want=1 or 2",
+            ),
+            ("quote(1, a)", "names to capture must be an array, got a"),
+            (
+                "quote(1, [a, 2])",
+                "names to capture must be identifiers, got 2",
+            ),
+        ];
+
+        for (input, message) in tests.iter() {
+            test_error_object(&test_eval(input), message);
+        }
+    }
+
+    #[test]
+    fn test_ast_builtins() {
+        let tests = [
+            ("ast_kind(quote(1 + 2))", "INFIX"),
+            ("ast_kind(quote({ let a = 1; a }))", "BLOCK"),
+            (
+                "ast_children(quote(f(1, x)))",
+                "[QUOTE(f), QUOTE(1), QUOTE(x)]",
+            ),
+            ("ast_children(quote(x))", "[]"),
+            ("ast_call(\"add\", [1, quote(x)])", "QUOTE(add(1, x))"),
+            (
+                "ast_call(quote(f), [[1, true], \"s\"])",
+                "QUOTE(f([1, true], s))",
+            ),
+            ("ast_ident(\"y\")", "QUOTE(y)"),
+            ("ast_let(\"x\", [1])", "QUOTE({ let x = [1] })"),
+            (
+                "ast_let(quote(f), quote(fn() { 1 }))",
+                "QUOTE({ let f = fn f() 1 })",
+            ),
+            ("ast_kind(quote(1))", "INTEGER"),
+        ];
+
+        for (input, output) in tests.iter() {
+            assert_eq!(test_eval(input).inspect(), *output, "{}", input);
+        }
+
+        let tests = [
+            (
+                "ast_kind(1)",
+                "argument to `ast_kind` must be QUOTE, got INTEGER",
+            ),
+            ("ast_children()", "wrong number of arguments. got=0, want=1"),
+            (
+                "ast_call(1, [])",
+                "first argument to `ast_call` must be STRING or QUOTE, got INTEGER",
+            ),
+            (
+                "ast_call(\"f\", 1)",
+                "second argument to `ast_call` must be ARRAY, got INTEGER",
+            ),
+            (
+                "ast_ident(1)",
+                "argument to `ast_ident` must be STRING, got INTEGER",
+            ),
+            (
+                "ast_let(quote(1), 2)",
+                "first argument to `ast_let` must be STRING or quoted identifier, got QUOTE",
+            ),
+        ];
+
+        for (input, message) in tests.iter() {
+            test_error_object(&test_eval(input), message);
+        }
+    }
 }
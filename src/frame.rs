@@ -1,18 +1,39 @@
-use crate::{code::Instructions, object::Closure};
+use crate::{
+    code::Instructions,
+    object::{Closure, Generator},
+};
 
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub closure: Closure,
     pub ip: isize,
     pub base_pointer: usize,
+    /// The generator this frame runs for, if it was resumed by `next`.
+    pub generator: Option<Generator>,
 }
 
 impl Frame {
     pub fn new(closure: Closure, base_pointer: usize) -> Self {
-        Self { closure, ip: -1, base_pointer }
+        Self {
+            closure,
+            ip: -1,
+            base_pointer,
+            generator: None,
+        }
     }
 
     pub fn instructions(&self) -> &Instructions {
         &self.closure.func.instructions
     }
+
+    /// Reads the next operand, `width` bytes wide or twice that after a
+    /// `Wide` prefix, and moves past it.
+    pub fn read_operand(&mut self, width: usize, wide: bool) -> usize {
+        let width = if wide { width * 2 } else { width };
+        let operand = self
+            .instructions()
+            .read_operand(self.ip as usize + 1, width);
+        self.ip += width as isize;
+        operand
+    }
 }
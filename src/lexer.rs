@@ -6,6 +6,11 @@ pub struct Lexer {
     position: usize,
     read_position: usize,
     ch: Option<char>,
+    /// Whether the last token can end an operand, so a minus after it is a
+    /// subtraction rather than a negation.
+    after_operand: bool,
+    after_prefix_minus: bool,
+    pub errors: Vec<String>,
 }
 
 impl Lexer {
@@ -15,6 +20,9 @@ impl Lexer {
             position: 0,
             read_position: 0,
             ch: None,
+            after_operand: false,
+            after_prefix_minus: false,
+            errors: vec![],
         };
         l.read_char();
         l
@@ -24,13 +32,30 @@ impl Lexer {
         if self.read_position >= self.input.len() {
             self.ch = None;
         } else {
-            self.ch = self.input.chars().nth(self.read_position);
+            self.ch = self.input[self.read_position..].chars().next();
         }
         self.position = self.read_position;
-        self.read_position += 1;
+        self.read_position += self.ch.map_or(1, |ch| ch.len_utf8());
     }
 
     pub fn next_token(&mut self) -> Token {
+        let token = self.read_token();
+        self.after_prefix_minus = token.0 == TokenKind::Minus && !self.after_operand;
+        self.after_operand = matches!(
+            token.0,
+            TokenKind::Int
+                | TokenKind::Ident
+                | TokenKind::String
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::Rparen
+                | TokenKind::Rbracket
+                | TokenKind::Rbrace
+        );
+        token
+    }
+
+    fn read_token(&mut self) -> Token {
         self.skip_whitespace();
 
         let token = match self.ch {
@@ -39,6 +64,10 @@ impl Lexer {
                     self.read_char();
                     Token(TokenKind::Eq, "==".into())
                 }
+                Some('>') => {
+                    self.read_char();
+                    Token(TokenKind::FatArrow, "=>".into())
+                }
                 _ => Token(TokenKind::Assign, "=".into()),
             },
             Some('+') => Token(TokenKind::Plus, "+".into()),
@@ -50,6 +79,28 @@ impl Lexer {
                 }
                 _ => Token(TokenKind::Bang, "!".into()),
             },
+            Some('|') => match self.peek_char() {
+                Some('>') => {
+                    self.read_char();
+                    Token(TokenKind::Pipe, "|>".into())
+                }
+                _ => Token(TokenKind::Illegal, "".into()),
+            },
+            Some('?') => match self.peek_char() {
+                Some('.') => {
+                    self.read_char();
+                    Token(TokenKind::QuestionDot, "?.".into())
+                }
+                Some('[') => {
+                    self.read_char();
+                    Token(TokenKind::QuestionBracket, "?[".into())
+                }
+                Some('?') => {
+                    self.read_char();
+                    Token(TokenKind::NullCoalesce, "??".into())
+                }
+                _ => Token(TokenKind::Illegal, "".into()),
+            },
             Some('/') => Token(TokenKind::Slash, "/".into()),
             Some('*') => Token(TokenKind::Asterisk, "*".into()),
             Some('<') => Token(TokenKind::Lt, "<".into()),
@@ -63,13 +114,30 @@ impl Lexer {
             Some('[') => Token(TokenKind::Lbracket, "[".into()),
             Some(']') => Token(TokenKind::Rbracket, "]".into()),
             Some(':') => Token(TokenKind::Colon, ":".into()),
+            Some('.') => {
+                let rest = &self.input[self.read_position..];
+                if rest.starts_with("..") {
+                    self.read_char();
+                    self.read_char();
+                    Token(TokenKind::Ellipsis, "...".into())
+                } else if rest.starts_with(".=") {
+                    self.read_char();
+                    self.read_char();
+                    Token(TokenKind::DotDotEq, "..=".into())
+                } else if rest.starts_with('.') {
+                    self.read_char();
+                    Token(TokenKind::DotDot, "..".into())
+                } else {
+                    Token(TokenKind::Dot, ".".into())
+                }
+            }
             Some('"') => Token(TokenKind::String, self.read_string().into()),
             None => Token(TokenKind::Eof, "\n".into()),
             Some(ch) => {
                 if self.is_letter(Some(ch)) {
                     return Token::from_word(self.read_identifier());
                 } else if self.is_digital(Some(ch)) {
-                    return Token(TokenKind::Int, self.read_number().into());
+                    return self.read_number();
                 } else {
                     Token(TokenKind::Illegal, "".into())
                 }
@@ -102,12 +170,86 @@ impl Lexer {
         self.input[position..self.position].into()
     }
 
-    fn read_number(&mut self) -> &str {
+    /// Reads a decimal, `0x`, `0o` or `0b` literal with optional `_`
+    /// separators between digits. The token holds the value in decimal;
+    /// malformed or out-of-range literals are reported in `errors` and lexed
+    /// as `Illegal`.
+    fn read_number(&mut self) -> Token {
         let position = self.position;
-        while self.is_digital(self.ch) {
+        let radix = match (self.ch, self.peek_char()) {
+            (Some('0'), Some('x' | 'X')) => 16,
+            (Some('0'), Some('o' | 'O')) => 8,
+            (Some('0'), Some('b' | 'B')) => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.read_char();
             self.read_char();
         }
-        self.input[position..self.position].into()
+
+        let digits_position = self.position;
+        while let Some(ch) = self.ch {
+            let in_literal = match radix {
+                10 => self.is_digital(Some(ch)) || ch == '_',
+                _ => ch.is_ascii_alphanumeric() || ch == '_',
+            };
+            if !in_literal {
+                break;
+            }
+            self.read_char();
+        }
+
+        let literal = self.input[position..self.position].to_owned();
+        let separated = &self.input[digits_position..self.position];
+        if separated.starts_with('_') || separated.ends_with('_') || separated.contains("__") {
+            self.error_at(
+                position,
+                format!("misplaced _ in integer literal {}", literal),
+            );
+            return Token(TokenKind::Illegal, literal);
+        }
+        let digits: String = separated.chars().filter(|ch| *ch != '_').collect();
+
+        if let Some(ch) = digits.chars().find(|ch| !ch.is_digit(radix)) {
+            self.error_at(
+                position,
+                format!("invalid digit {} in integer literal {}", ch, literal),
+            );
+            return Token(TokenKind::Illegal, literal);
+        }
+        if digits.is_empty() {
+            self.error_at(
+                position,
+                format!("integer literal {} has no digits", literal),
+            );
+            return Token(TokenKind::Illegal, literal);
+        }
+
+        // `i64::MIN` has no positive counterpart, so its magnitude is only
+        // accepted right after a negating minus; the parser folds the two.
+        match u64::from_str_radix(&digits, radix) {
+            Ok(value)
+                if value <= i64::MAX as u64
+                    || (self.after_prefix_minus && value == i64::MIN.unsigned_abs()) =>
+            {
+                Token(TokenKind::Int, value.to_string())
+            }
+            _ => {
+                self.error_at(
+                    position,
+                    format!("integer literal {} is out of range", literal),
+                );
+                Token(TokenKind::Illegal, literal)
+            }
+        }
+    }
+
+    fn error_at(&mut self, position: usize, message: String) {
+        let before = &self.input[..position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|ch| *ch != '\n').count() + 1;
+        self.errors
+            .push(format!("line {}, column {}: {}", line, column, message));
     }
 
     fn read_string(&mut self) -> &str {
@@ -143,7 +285,7 @@ impl Lexer {
         if self.read_position >= self.input.len() {
             None
         } else {
-            self.input.chars().nth(self.read_position)
+            self.input[self.read_position..].chars().next()
         }
     }
 }
@@ -172,6 +314,258 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dot_tokens() {
+        let input = "book.title; arr.push(4)";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Ident, "book".into()),
+            Token(TokenKind::Dot, ".".into()),
+            Token(TokenKind::Ident, "title".into()),
+            Token(TokenKind::Semicolon, ";".into()),
+            Token(TokenKind::Ident, "arr".into()),
+            Token(TokenKind::Dot, ".".into()),
+            Token(TokenKind::Ident, "push".into()),
+            Token(TokenKind::Lparen, "(".into()),
+            Token(TokenKind::Int, "4".into()),
+            Token(TokenKind::Rparen, ")".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_match_tokens() {
+        let input = "enum E { A(x) } match (e) { A(x) => x }";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Enum, "enum".into()),
+            Token(TokenKind::Ident, "E".into()),
+            Token(TokenKind::Lbrace, "{".into()),
+            Token(TokenKind::Ident, "A".into()),
+            Token(TokenKind::Lparen, "(".into()),
+            Token(TokenKind::Ident, "x".into()),
+            Token(TokenKind::Rparen, ")".into()),
+            Token(TokenKind::Rbrace, "}".into()),
+            Token(TokenKind::Match, "match".into()),
+            Token(TokenKind::Lparen, "(".into()),
+            Token(TokenKind::Ident, "e".into()),
+            Token(TokenKind::Rparen, ")".into()),
+            Token(TokenKind::Lbrace, "{".into()),
+            Token(TokenKind::Ident, "A".into()),
+            Token(TokenKind::Lparen, "(".into()),
+            Token(TokenKind::Ident, "x".into()),
+            Token(TokenKind::Rparen, ")".into()),
+            Token(TokenKind::FatArrow, "=>".into()),
+            Token(TokenKind::Ident, "x".into()),
+            Token(TokenKind::Rbrace, "}".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_comprehension_tokens() {
+        let input = "[x for x in xs]";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Lbracket, "[".into()),
+            Token(TokenKind::Ident, "x".into()),
+            Token(TokenKind::For, "for".into()),
+            Token(TokenKind::Ident, "x".into()),
+            Token(TokenKind::In, "in".into()),
+            Token(TokenKind::Ident, "xs".into()),
+            Token(TokenKind::Rbracket, "]".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_generator_tokens() {
+        let input = "fn* gen() { yield 1; }";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Function, "fn".into()),
+            Token(TokenKind::Asterisk, "*".into()),
+            Token(TokenKind::Ident, "gen".into()),
+            Token(TokenKind::Lparen, "(".into()),
+            Token(TokenKind::Rparen, ")".into()),
+            Token(TokenKind::Lbrace, "{".into()),
+            Token(TokenKind::Yield, "yield".into()),
+            Token(TokenKind::Int, "1".into()),
+            Token(TokenKind::Semicolon, ";".into()),
+            Token(TokenKind::Rbrace, "}".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_range_tokens() {
+        let input = "0..n 1..=2 [...a]";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Int, "0".into()),
+            Token(TokenKind::DotDot, "..".into()),
+            Token(TokenKind::Ident, "n".into()),
+            Token(TokenKind::Int, "1".into()),
+            Token(TokenKind::DotDotEq, "..=".into()),
+            Token(TokenKind::Int, "2".into()),
+            Token(TokenKind::Lbracket, "[".into()),
+            Token(TokenKind::Ellipsis, "...".into()),
+            Token(TokenKind::Ident, "a".into()),
+            Token(TokenKind::Rbracket, "]".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let input = "0xFF 0o755 0b1010 1_000_000 0, -9223372036854775808";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Int, "255".into()),
+            Token(TokenKind::Int, "493".into()),
+            Token(TokenKind::Int, "10".into()),
+            Token(TokenKind::Int, "1000000".into()),
+            Token(TokenKind::Int, "0".into()),
+            Token(TokenKind::Comma, ",".into()),
+            Token(TokenKind::Minus, "-".into()),
+            Token(TokenKind::Int, "9223372036854775808".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+        assert!(lexer.errors.is_empty());
+    }
+
+    #[test]
+    fn test_integer_literal_errors() {
+        let input = "1 +\n  0b102 0x;\n9223372036854775808 0xFFFFFFFFFFFFFFFFF\n0x_10 1__0 1_ 2 -9223372036854775808";
+        let mut lexer = Lexer::new(input);
+        while lexer.next_token().0 != TokenKind::Eof {}
+
+        assert_eq!(
+            lexer.errors,
+            vec![
+                "line 2, column 3: invalid digit 2 in integer literal 0b102",
+                "line 2, column 9: integer literal 0x has no digits",
+                "line 3, column 1: integer literal 9223372036854775808 is out of range",
+                "line 3, column 21: integer literal 0xFFFFFFFFFFFFFFFFF is out of range",
+                "line 4, column 1: misplaced _ in integer literal 0x_10",
+                "line 4, column 7: misplaced _ in integer literal 1__0",
+                "line 4, column 12: misplaced _ in integer literal 1_",
+                "line 4, column 18: integer literal 9223372036854775808 is out of range",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipe_tokens() {
+        let input = "xs |> f | g";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Ident, "xs".into()),
+            Token(TokenKind::Pipe, "|>".into()),
+            Token(TokenKind::Ident, "f".into()),
+            Token(TokenKind::Illegal, "".into()),
+            Token(TokenKind::Ident, "g".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_optional_tokens() {
+        let input = "a?.b?[0] ?? c";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Ident, "a".into()),
+            Token(TokenKind::QuestionDot, "?.".into()),
+            Token(TokenKind::Ident, "b".into()),
+            Token(TokenKind::QuestionBracket, "?[".into()),
+            Token(TokenKind::Int, "0".into()),
+            Token(TokenKind::Rbracket, "]".into()),
+            Token(TokenKind::NullCoalesce, "??".into()),
+            Token(TokenKind::Ident, "c".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_spread_tokens() {
+        let input = "[...a, b.c]";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::Lbracket, "[".into()),
+            Token(TokenKind::Ellipsis, "...".into()),
+            Token(TokenKind::Ident, "a".into()),
+            Token(TokenKind::Comma, ",".into()),
+            Token(TokenKind::Ident, "b".into()),
+            Token(TokenKind::Dot, ".".into()),
+            Token(TokenKind::Ident, "c".into()),
+            Token(TokenKind::Rbracket, "]".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn test_unicode_string() {
+        let input = "\"héllo\"[1]";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            Token(TokenKind::String, "héllo".into()),
+            Token(TokenKind::Lbracket, "[".into()),
+            Token(TokenKind::Int, "1".into()),
+            Token(TokenKind::Rbracket, "]".into()),
+            Token(TokenKind::Eof, "\n".into()),
+        ];
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
     #[test]
     fn test_complex_tokens() {
         let input = "let five = 5;
@@ -11,6 +11,7 @@ pub mod repl;
 mod token;
 mod traverser;
 pub mod makro;
+mod module;
 mod code;
 pub mod compiler;
 mod symbol_table;
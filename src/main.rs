@@ -3,8 +3,16 @@ use std::{cell::RefCell, env::args, fs, path::PathBuf, process, rc::Rc};
 
 use clap::Parser;
 use monkey_lang::{
-    compiler::Compiler, enviroment::Enviroment, evaluator::Evaluator, lexer::Lexer,
-    makro::MacroExpension, parser, repl::Repl, vm::Vm, Engine,
+    ast::Program,
+    compiler::Compiler,
+    enviroment::Enviroment,
+    evaluator::{self, Evaluator},
+    lexer::Lexer,
+    makro::MacroExpension,
+    parser,
+    repl::Repl,
+    vm::{Vm, VmConfig, GLOBAL_SIZE, MAX_FRAMES, STACK_SIZE},
+    Engine,
 };
 
 #[derive(Parser)]
@@ -14,56 +22,117 @@ struct Cli {
 
     #[arg(short, long, value_name="ENGINE", value_enum, default_value_t=Engine::Vm)]
     engine: Engine,
+
+    /// Number of value stack slots for the vm engine
+    #[arg(long, value_name = "SLOTS", default_value_t = STACK_SIZE)]
+    stack_size: usize,
+
+    /// Maximum depth of nested calls for the vm engine
+    #[arg(long, value_name = "FRAMES", default_value_t = MAX_FRAMES)]
+    max_frames: usize,
+
+    /// Number of global bindings for the vm engine
+    #[arg(long, value_name = "SLOTS", default_value_t = GLOBAL_SIZE)]
+    global_size: usize,
+
+    /// Print the program after macro expansion instead of running it
+    #[arg(long, requires = "file")]
+    expand: bool,
+
+    /// With --expand, also print each macro call as it is expanded
+    #[arg(long, requires = "expand")]
+    trace: bool,
 }
 
 fn main() {
     let cli = Cli::parse_from(args());
+    let config = VmConfig {
+        stack_size: cli.stack_size,
+        max_frames: cli.max_frames,
+        global_size: cli.global_size,
+    };
 
-    if let Some(filename) = cli.file {
-        run(&filename, &cli.engine);
-    } else {
-        repl(&cli.engine);
-    }
+    // Macros are evaluated whichever engine runs the program.
+    evaluator::with_stack(|| {
+        if let Some(filename) = cli.file {
+            if cli.expand {
+                expand(&filename, cli.trace);
+            } else {
+                run(&filename, &cli.engine, config);
+            }
+        } else {
+            repl(&cli.engine, config);
+        }
+    });
 }
 
-fn run(file: &PathBuf, engine: &Engine) {
+fn parse(file: &PathBuf) -> Program {
     let script = fs::read_to_string(file).expect("Unable to read file");
     let mut lexer = Lexer::new(&script);
     let mut parser = parser::Parser::new(&mut lexer);
-    let mut program = parser.parse_program();
-    if !parser.errors.is_empty() {
-        for error in parser.errors.iter() {
+    let program = parser.parse_program();
+    exit_on_errors(&parser.errors);
+    program
+}
+
+fn exit_on_errors(errors: &[String]) {
+    if !errors.is_empty() {
+        for error in errors.iter() {
             println!("\t{error}");
         }
         process::exit(1);
     }
+}
+
+fn expand(file: &PathBuf, trace: bool) {
+    let mut program = parse(file);
+    let macros = MacroExpension::new(Rc::new(RefCell::new(Enviroment::default())));
+    let (steps, errors) = macros.expand_traced(&mut program);
+    if trace {
+        for step in steps.iter() {
+            println!("{step}");
+        }
+    }
+    exit_on_errors(&errors);
+    for stmt in program.statements.iter() {
+        println!("{stmt}");
+    }
+}
+
+fn run(file: &PathBuf, engine: &Engine, config: VmConfig) {
+    let mut program = parse(file);
+    // The vm only needs the enviroment for macros, but the evaluator runs the
+    // program in it too.
+    let env = Rc::new(RefCell::new(Enviroment::default()));
+    if let Err(errors) = MacroExpension::new(Rc::clone(&env)).expand(&mut program) {
+        exit_on_errors(&errors);
+    }
     match engine {
         Engine::Vm => {
             let mut compiler = Compiler::new();
+            compiler.set_main_file(file);
             compiler.compile(&program);
-            let mut vm = Vm::new(compiler.bytecode());
+            exit_on_errors(&compiler.errors);
+            let mut vm = Vm::new(compiler.bytecode(), config);
             vm.run();
             let result = vm.last_popped_stack_elem();
             println!("{}", result.inspect());
         }
         Engine::Eval => {
-            let env = Rc::new(RefCell::new(Enviroment::default()));
-            let makro = MacroExpension::new(Rc::clone(&env));
-            makro.define_macros(&mut program);
-            makro.expand_macros(&mut program);
             let mut evaluator = Evaluator::new(env);
+            evaluator.set_main_file(file);
             let result = evaluator.eval(&mut program);
             println!("{}", result.inspect());
         }
     };
 }
 
-fn repl(engine: &Engine) {
+fn repl(engine: &Engine, config: VmConfig) {
     println!(
         "Hello {}! This is the Monkey programming language!",
         whoami::username()
     );
     println!("Feel free to type in commands");
-    let repl = Repl::new(engine);
+    let repl = Repl::new(engine, config);
     repl.start();
 }
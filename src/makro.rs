@@ -1,9 +1,19 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    iter,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crate::{
     ast::{
-        ArrayLiteral, BooleanExpression, CallExpression, Expression, ExpressionStatement,
-        HashLiteral, HashMember, IntegerLiteral, NullLiteral, Program, Statement, StringLiteral,
+        ArrayLiteral, BlockStatement, BooleanExpression, CallExpression, ComprehensionClause,
+        Expression, ExpressionStatement, FunctionLiteral, HashComprehension, HashLiteral,
+        HashMember, Identifier, IndexExpression, InfixExpression, IntegerLiteral,
+        ListComprehension, MacroLiteral, MatchExpression, NullLiteral, Pattern, Program, Statement,
+        StringLiteral,
     },
     enviroment::Enviroment,
     evaluator::Evaluator,
@@ -22,7 +32,17 @@ impl EvalUnqupteCalls {
 }
 
 impl EvalUnqupteCalls {
-    fn convert_object_to_ast_node(object: &Object) -> Expression {
+    /// Renders a value back into code that evaluates to it. Functions lose
+    /// the enviroment they closed over, and values with no literal form,
+    /// such as generators, become null.
+    pub fn convert_object_to_ast_node(object: &Object) -> Expression {
+        let convert_all = |objects: &[Rc<Object>]| {
+            objects
+                .iter()
+                .map(|o| EvalUnqupteCalls::convert_object_to_ast_node(o))
+                .collect()
+        };
+        let ident = |name: &str| Expression::Identifier(Identifier::new(name.to_owned()));
         match *object {
             Object::Integer(ref integer) => {
                 Expression::IntegerLiteral(IntegerLiteral::new(integer.value.to_owned()))
@@ -34,17 +54,13 @@ impl EvalUnqupteCalls {
                 Expression::Boolean(BooleanExpression::new(bool.value.to_owned()))
             }
             Object::Null(_) => Expression::NullLiteral(NullLiteral::default()),
-            Object::Array(ref array) => Expression::ArrayLiteral(ArrayLiteral::new(
-                array
-                    .elements
-                    .iter()
-                    .map(|e| EvalUnqupteCalls::convert_object_to_ast_node(e))
-                    .collect(),
-            )),
+            Object::Array(ref array) => {
+                Expression::ArrayLiteral(ArrayLiteral::new(convert_all(&array.elements)))
+            }
             Object::Hash(ref hash) => Expression::HashLiteral(HashLiteral::new(
                 hash.value
                     .iter()
-                    .map(|(k, v)| {
+                    .map(|(k, pair)| {
                         let key = match k.kind {
                             ObjectKind::Boolean => Expression::Boolean(BooleanExpression::new(
                                 k.name.parse::<bool>().unwrap(),
@@ -57,44 +73,330 @@ impl EvalUnqupteCalls {
                             }
                             _ => Expression::NullLiteral(NullLiteral::default()),
                         };
-                        let value = EvalUnqupteCalls::convert_object_to_ast_node(v);
+                        let value = EvalUnqupteCalls::convert_object_to_ast_node(&pair.value);
                         HashMember::new(key, value)
                     })
                     .collect(),
             )),
+            Object::Range(ref range) => Expression::Infix(InfixExpression::new(
+                if range.inclusive { "..=" } else { ".." }.into(),
+                Expression::IntegerLiteral(IntegerLiteral::new(range.start)),
+                Expression::IntegerLiteral(IntegerLiteral::new(range.end)),
+            )),
+            Object::Function(ref function) => {
+                let mut literal = FunctionLiteral::new(
+                    "".into(),
+                    function.parameters.to_owned(),
+                    function.body.to_owned(),
+                );
+                literal.is_generator = function.is_generator;
+                Expression::FunctionLiteral(literal)
+            }
+            Object::Macro(ref makro) => Expression::MacroLiteral(MacroLiteral::new(
+                makro.parameters.to_owned(),
+                makro.body.to_owned(),
+            )),
+            Object::BuiltinFunction(ref builtin) => match builtin.receiver {
+                Some(ref receiver) => Expression::Index(IndexExpression::member(
+                    EvalUnqupteCalls::convert_object_to_ast_node(receiver),
+                    builtin.name.to_owned(),
+                    false,
+                )),
+                None => ident(&builtin.name),
+            },
+            Object::StructDefinition(ref definition) => ident(&definition.name),
+            Object::Struct(ref instance) => Expression::Call(CallExpression::new(
+                ident(&instance.definition.name),
+                convert_all(&instance.values),
+            )),
+            Object::VariantDefinition(ref definition) => ident(&definition.name),
+            Object::Variant(ref variant) if variant.values.is_empty() => {
+                ident(&variant.definition.name)
+            }
+            Object::Variant(ref variant) => Expression::Call(CallExpression::new(
+                ident(&variant.definition.name),
+                convert_all(&variant.values),
+            )),
+            Object::ReturnValue(ref value) => {
+                EvalUnqupteCalls::convert_object_to_ast_node(&value.value)
+            }
             Object::Quote(ref quote) => quote.node.to_owned(),
             _ => Expression::NullLiteral(NullLiteral::default()),
         }
     }
 }
 
+impl EvalUnqupteCalls {
+    fn eval_argument(&self, call: &CallExpression) -> Rc<Object> {
+        let mut program = Program {
+            statements: vec![Statement::Expression(ExpressionStatement::new(
+                call.arguments[0].to_owned(),
+            ))],
+        };
+        let mut evaluator = Evaluator::new(Rc::clone(&self.env));
+        evaluator.eval(&mut program)
+    }
+
+    /// Evaluates an `unquote_splice` call to the nodes it splices in: the
+    /// elements of an array or a quoted array literal, or else the single
+    /// value.
+    fn splice(&self, call: &CallExpression) -> Vec<Expression> {
+        let object = self.eval_argument(call);
+        match *object {
+            Object::Array(ref array) => array
+                .elements
+                .iter()
+                .map(|e| EvalUnqupteCalls::convert_object_to_ast_node(e))
+                .collect(),
+            Object::Quote(Quote {
+                node: Expression::ArrayLiteral(ref array),
+            }) => array.elements.to_owned(),
+            _ => vec![EvalUnqupteCalls::convert_object_to_ast_node(&object)],
+        }
+    }
+
+    fn splice_expressions(&self, nodes: &mut Vec<Expression>) {
+        *nodes = nodes
+            .drain(..)
+            .flat_map(|node| match node {
+                Expression::Call(ref call) if is_call_to(call, "unquote_splice") => {
+                    self.splice(call)
+                }
+                node => vec![node],
+            })
+            .collect();
+    }
+
+    /// Splices in statements, taking those of quoted blocks as they are.
+    fn splice_statements(&self, statements: &mut Vec<Statement>) {
+        let mut spliced = vec![];
+        for stmt in statements.drain(..) {
+            let call = match stmt {
+                Statement::Expression(ExpressionStatement {
+                    expression: Expression::Call(ref call),
+                }) if is_call_to(call, "unquote_splice") => call,
+                stmt => {
+                    spliced.push(stmt);
+                    continue;
+                }
+            };
+            for node in self.splice(call) {
+                match node {
+                    Expression::Block(block) => spliced.extend(block.statements),
+                    node => spliced.push(Statement::Expression(ExpressionStatement::new(node))),
+                }
+            }
+        }
+        *statements = spliced;
+    }
+}
+
 impl Visitor for EvalUnqupteCalls {
+    // Splices outside a list put in the value as a whole, like `unquote`.
     fn visit_mut_expression(&self, node: &mut Expression) {
         if let Expression::Call(ref call) = *node {
-            if let Expression::Identifier(ref callee) = *call.callee {
-                if callee.value == "unquote" && call.arguments.len() == 1 {
-                    let mut program = Program {
-                        statements: vec![Statement::Expression(ExpressionStatement::new(
-                            call.arguments[0].to_owned(),
-                        ))],
-                    };
-                    let mut evaluator = Evaluator::new(Rc::clone(&self.env));
-                    let object = evaluator.eval(&mut program);
-                    *node = EvalUnqupteCalls::convert_object_to_ast_node(&object);
+            if is_unquote_call(call) {
+                let object = self.eval_argument(call);
+                *node = EvalUnqupteCalls::convert_object_to_ast_node(&object);
+            }
+        }
+    }
+
+    fn visit_mut_call_expression(&self, node: &mut CallExpression) {
+        self.splice_expressions(&mut node.arguments);
+    }
+
+    fn visit_mut_array_literal(&self, node: &mut ArrayLiteral) {
+        self.splice_expressions(&mut node.elements);
+    }
+
+    fn visit_mut_block_statement(&self, node: &mut BlockStatement) {
+        self.splice_statements(&mut node.statements);
+    }
+}
+
+fn is_call_to(call: &CallExpression, name: &str) -> bool {
+    matches!(*call.callee, Expression::Identifier(ref callee) if callee.value == name)
+        && call.arguments.len() == 1
+}
+
+fn is_unquote_call(call: &CallExpression) -> bool {
+    is_call_to(call, "unquote") || is_call_to(call, "unquote_splice")
+}
+
+/// Leaves out the code a template unquotes, which belongs to the macro rather
+/// than the template.
+fn is_template_code(node: &Expression) -> bool {
+    !matches!(*node, Expression::Call(ref call) if is_unquote_call(call))
+}
+
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes a name based on `prefix` that no other name can clash with, as the
+/// lexer never reads an `@` into an identifier.
+pub fn gensym(prefix: &str) -> String {
+    let id = GENSYM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+    format!("{}@{}", prefix, id)
+}
+
+/// Renames the names `template` binds to fresh ones, so the code a macro
+/// expands to cannot capture or clobber the caller's variables. Only uses
+/// within the scope of each binding are renamed, leaving references to the
+/// caller's variables alone. Names in `captured` are left as they are, for
+/// macros that mean to bind them.
+pub fn rename_bound_names(template: &mut Expression, captured: &[String]) {
+    template.visit_mut(&RenameBindings { captured });
+}
+
+struct RenameBindings<'a> {
+    captured: &'a [String],
+}
+
+impl RenameBindings<'_> {
+    /// Picks fresh names for `names`. Names an enclosing binding has already
+    /// renamed are skipped, as every use of them in its scope was renamed too.
+    fn renames<'n>(
+        &self,
+        names: impl Iterator<Item = &'n Identifier>,
+    ) -> Option<RenameIdentifiers> {
+        let renames: HashMap<_, _> = names
+            .filter(|name| !name.value.contains('@') && !self.captured.contains(&name.value))
+            .map(|name| (name.value.to_owned(), gensym(&name.value)))
+            .collect();
+        (!renames.is_empty()).then_some(RenameIdentifiers { renames })
+    }
+
+    /// Renames a comprehension's bindings where they are in scope, which
+    /// leaves out the iterable.
+    fn rename_clause(clause: &mut ComprehensionClause, rename: &RenameIdentifiers) {
+        clause.bindings.iter_mut().for_each(|b| b.visit_mut(rename));
+        if let Some(ref mut condition) = clause.condition {
+            condition.visit_mut(rename);
+        }
+    }
+}
+
+impl Visitor for RenameBindings<'_> {
+    fn descend_into_expression(&self, node: &Expression) -> bool {
+        is_template_code(node)
+    }
+
+    // A `let` is in scope from its own value, so functions can call
+    // themselves, to the end of the block.
+    fn visit_mut_block_statement(&self, node: &mut BlockStatement) {
+        for i in 0..node.statements.len() {
+            if let Statement::Let(ref statement) = node.statements[i] {
+                if let Some(rename) = self.renames(iter::once(&statement.name)) {
+                    node.statements[i..]
+                        .iter_mut()
+                        .for_each(|s| s.visit_mut(&rename));
                 }
             }
         }
     }
+
+    fn visit_mut_function_literal(&self, node: &mut FunctionLiteral) {
+        if let Some(rename) = self.renames(node.parameters.iter()) {
+            node.parameters
+                .iter_mut()
+                .for_each(|p| p.visit_mut(&rename));
+            node.body.visit_mut(&rename);
+        }
+    }
+
+    fn visit_mut_match_expression(&self, node: &mut MatchExpression) {
+        for arm in node.arms.iter_mut() {
+            if let Pattern::Variant(_, ref mut bindings) = arm.pattern {
+                if let Some(rename) = self.renames(bindings.iter()) {
+                    bindings.iter_mut().for_each(|b| b.visit_mut(&rename));
+                    arm.body.visit_mut(&rename);
+                }
+            }
+        }
+    }
+
+    fn visit_mut_list_comprehension(&self, node: &mut ListComprehension) {
+        if let Some(rename) = self.renames(node.clause.bindings.iter()) {
+            node.element.visit_mut(&rename);
+            Self::rename_clause(&mut node.clause, &rename);
+        }
+    }
+
+    fn visit_mut_hash_comprehension(&self, node: &mut HashComprehension) {
+        if let Some(rename) = self.renames(node.clause.bindings.iter()) {
+            node.key.visit_mut(&rename);
+            node.value.visit_mut(&rename);
+            Self::rename_clause(&mut node.clause, &rename);
+        }
+    }
+}
+
+struct RenameIdentifiers {
+    renames: HashMap<String, String>,
+}
+
+impl Visitor for RenameIdentifiers {
+    fn descend_into_expression(&self, node: &Expression) -> bool {
+        is_template_code(node)
+    }
+
+    fn visit_mut_identifer(&self, node: &mut Identifier) {
+        if let Some(fresh) = self.renames.get(&node.value) {
+            node.value = fresh.to_owned();
+        }
+    }
+
+    // Named functions refer to themselves by the name they are bound to.
+    fn visit_mut_function_literal(&self, node: &mut FunctionLiteral) {
+        if let Some(fresh) = self.renames.get(&node.name) {
+            node.name = fresh.to_owned();
+        }
+    }
+}
+
+/// One macro call replaced during expansion.
+#[derive(Debug, Clone)]
+pub struct ExpansionStep {
+    pub call: CallExpression,
+    /// Each parameter with the quoted argument it was bound to.
+    pub args: Vec<(String, Rc<Object>)>,
+    pub result: Expression,
+}
+
+impl Display for ExpansionStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "expand {}", self.call)?;
+        for (name, arg) in self.args.iter() {
+            writeln!(f, "  {} = {}", name, arg.inspect())?;
+        }
+        write!(f, "  => {}", self.result)
+    }
 }
 
 #[derive(Debug)]
 struct ExpandMacro {
     env: Rc<RefCell<Enviroment>>,
+    /// The steps taken so far, when tracing.
+    trace: Option<RefCell<Vec<ExpansionStep>>>,
+    /// Why each call that could not be expanded failed.
+    errors: RefCell<Vec<String>>,
 }
 
 impl ExpandMacro {
     fn new(env: Rc<RefCell<Enviroment>>) -> Self {
-        Self { env }
+        Self {
+            env,
+            trace: None,
+            errors: RefCell::new(vec![]),
+        }
+    }
+
+    fn traced(env: Rc<RefCell<Enviroment>>) -> Self {
+        Self {
+            env,
+            trace: Some(RefCell::new(vec![])),
+            errors: RefCell::new(vec![]),
+        }
     }
 
     fn get_macro(&self, call: &CallExpression) -> Option<Rc<Object>> {
@@ -124,33 +426,122 @@ impl ExpandMacro {
 
         for (i, parameter) in macro_obj.parameters.iter().enumerate() {
             env.borrow_mut()
-                .set(parameter.value.to_owned(), Rc::clone(&args[i]));
+                .set(parameter.value.to_owned(), Rc::clone(&args[i]))
+                .expect("a new enviroment has no constants");
         }
 
         env
     }
 }
 
+impl ExpandMacro {
+    /// Returns the node a call expands to, if it calls a macro.
+    fn expand_call(&self, call: &CallExpression) -> Option<Expression> {
+        let object = self.get_macro(call)?;
+        if let Object::Macro(ref macro_obj) = *object {
+            let args = self.quote_args(call);
+            let env = self.extend_macro_env(macro_obj, args.to_owned());
+            let mut evaluator = Evaluator::new(env);
+            let mut program = Program {
+                statements: macro_obj.body.statements.to_owned(),
+            };
+            let object = evaluator.eval(&mut program);
+            if let Object::Quote(ref quote) = *object {
+                if let Some(ref trace) = self.trace {
+                    trace.borrow_mut().push(ExpansionStep {
+                        call: call.to_owned(),
+                        args: macro_obj
+                            .parameters
+                            .iter()
+                            .map(|p| p.value.to_owned())
+                            .zip(args)
+                            .collect(),
+                        result: quote.node.to_owned(),
+                    });
+                }
+                Some(quote.node.to_owned())
+            } else {
+                let error = match *object {
+                    Object::RuntimeError(ref error) => {
+                        format!("macro {}: {}", call.callee, error.message)
+                    }
+                    _ => format!(
+                        "macro {} must return a quote, got {}",
+                        call.callee,
+                        object.inspect()
+                    ),
+                };
+                self.errors.borrow_mut().push(error);
+                // Taken out so it is not expanded again.
+                Some(Expression::NullLiteral(NullLiteral::default()))
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Expands macro calls standing as statements. Those expanding to a
+    /// quoted block put its statements in place of the call.
+    fn expand_statements(&self, statements: &mut Vec<Statement>) {
+        let mut expanded = vec![];
+        for stmt in statements.drain(..) {
+            let node = match stmt {
+                Statement::Expression(ExpressionStatement {
+                    expression: Expression::Call(ref call),
+                }) => self.expand_call(call),
+                _ => None,
+            };
+            match node {
+                Some(Expression::Block(block)) => expanded.extend(block.statements),
+                Some(node) => expanded.push(Statement::Expression(ExpressionStatement::new(node))),
+                None => expanded.push(stmt),
+            }
+        }
+        *statements = expanded;
+    }
+}
+
 impl Visitor for ExpandMacro {
+    fn visit_mut_program(&self, node: &mut Program) {
+        self.expand_statements(&mut node.statements);
+    }
+
+    fn visit_mut_block_statement(&self, node: &mut BlockStatement) {
+        self.expand_statements(&mut node.statements);
+    }
+
     fn visit_mut_expression(&self, node: &mut Expression) {
         if let Expression::Call(ref call) = *node {
-            if let Some(object) = self.get_macro(call) {
-                if let Object::Macro(ref macro_obj) = *object {
-                    let args = self.quote_args(call);
-                    let env = self.extend_macro_env(macro_obj, args);
-                    let mut evaluator = Evaluator::new(env);
-                    let mut program = Program {
-                        statements: macro_obj.body.statements.to_owned(),
-                    };
-                    let object = evaluator.eval(&mut program);
-                    if let Object::Quote(ref quote) = *object {
-                        *node = quote.node.to_owned();
-                    } else {
-                        panic!("we only support returning AST-nodes from macros");
+            if let Some(expanded) = self.expand_call(call) {
+                *node = expanded;
+            }
+        }
+    }
+}
+
+/// Finds the macros still in a program once its calls are expanded, which
+/// neither engine can run.
+struct LeftoverMacros {
+    env: Rc<RefCell<Enviroment>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl Visitor for LeftoverMacros {
+    fn visit_mut_expression(&self, node: &mut Expression) {
+        let error = match *node {
+            Expression::MacroLiteral(_) => "macros can only be defined by a top-level let".into(),
+            Expression::Identifier(ref name) => {
+                match self.env.borrow().get(name.value.to_owned()) {
+                    Some(object) if matches!(*object, Object::Macro(_)) => {
+                        format!("macro {} can only be called", name)
                     }
+                    _ => return,
                 }
             }
-        }
+            _ => return,
+        };
+        self.errors.borrow_mut().push(error);
+        *node = Expression::NullLiteral(NullLiteral::default());
     }
 }
 
@@ -164,14 +555,37 @@ impl MacroExpension {
         Self { env }
     }
 
+    /// Defines the macros in `program` and expands their calls, leaving a
+    /// program either engine can run. Definitions are kept in the
+    /// enviroment, so later programs can call them too. Fails with a message
+    /// for each call whose macro did not return a quote, and for each macro
+    /// left that is not called.
+    pub fn expand(&self, program: &mut Program) -> Result<(), Vec<String>> {
+        self.define_macros(program);
+        self.expand_macros(program)
+    }
+
+    /// Like `expand`, but also returns every expansion in the order it
+    /// happened, along with the messages for the calls that failed.
+    pub fn expand_traced(&self, program: &mut Program) -> (Vec<ExpansionStep>, Vec<String>) {
+        self.define_macros(program);
+        let visitor = ExpandMacro::traced(Rc::clone(&self.env));
+        program.visit_mut(&visitor);
+        let steps = visitor.trace.map(RefCell::into_inner).unwrap_or_default();
+        let mut errors = visitor.errors.into_inner();
+        errors.extend(self.find_leftover_macros(program));
+        (steps, errors)
+    }
+
     pub fn define_macros(&self, program: &mut Program) {
         let mut definitions: Vec<_> = vec![];
         let mut i = 0;
 
         program.statements.retain(|stmt| {
-            let is_macro = self.is_macro_definition(stmt);
+            // A definition clashing with a constant is left for the engine
+            // to report.
+            let is_macro = self.is_macro_definition(stmt) && self.add_macros(stmt);
             if is_macro {
-                self.add_macros(stmt);
                 definitions.push((i, stmt.to_owned()));
             }
             i += 1;
@@ -179,9 +593,34 @@ impl MacroExpension {
         });
     }
 
-    pub fn expand_macros(&self, program: &mut Program) {
+    pub fn expand_macros(&self, program: &mut Program) -> Result<(), Vec<String>> {
         let visitor = ExpandMacro::new(Rc::clone(&self.env));
         program.visit_mut(&visitor);
+        let mut errors = visitor.errors.into_inner();
+        errors.extend(self.find_leftover_macros(program));
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Reports the macros an expanded program still refers to, as well as
+    /// macro literals anywhere but a top-level `let`, replacing them with
+    /// null.
+    fn find_leftover_macros(&self, program: &mut Program) -> Vec<String> {
+        let visitor = LeftoverMacros {
+            env: Rc::clone(&self.env),
+            errors: RefCell::new(vec![]),
+        };
+        for stmt in program.statements.iter_mut() {
+            // A definition clashing with a constant is left for the engine
+            // to report.
+            if !self.is_macro_definition(stmt) {
+                stmt.visit_mut(&visitor);
+            }
+        }
+        visitor.errors.into_inner()
     }
 
     fn is_macro_definition(&self, stmt: &Statement) -> bool {
@@ -193,7 +632,8 @@ impl MacroExpension {
         false
     }
 
-    fn add_macros(&self, stmt: &Statement) {
+    /// Defines the macro `stmt` declares, returning whether that succeeded.
+    fn add_macros(&self, stmt: &Statement) -> bool {
         if let Statement::Let(exp) = stmt {
             if let Expression::MacroLiteral(ref macro_liter) = exp.value {
                 let macro_object = Object::Macro(object::Macro::new(
@@ -201,11 +641,17 @@ impl MacroExpension {
                     macro_liter.body.to_owned(),
                     Rc::clone(&self.env),
                 ));
-                self.env
-                    .borrow_mut()
-                    .set(exp.name.value.to_owned(), macro_object.into());
+                let name = exp.name.value.to_owned();
+                let mut env = self.env.borrow_mut();
+                let result = if exp.is_const {
+                    env.set_const(name, macro_object.into())
+                } else {
+                    env.set(name, macro_object.into())
+                };
+                return result.is_ok();
             }
         }
+        false
     }
 }
 
@@ -242,6 +688,26 @@ mod tests {
         assert!(env.borrow().get("mymacro".into()).is_some());
     }
 
+    #[test]
+    fn test_define_const_macros() {
+        let input = "
+            const mymacro = macro() { quote(1); };
+            let mymacro = macro() { quote(2); };
+";
+
+        let mut program = test_parse_program(input);
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let makro = MacroExpension::new(Rc::clone(&env));
+        makro.define_macros(&mut program);
+
+        // The clashing definition is kept for the engine to reject.
+        assert_eq!(program.statements.len(), 1);
+        assert_eq!(
+            program.statements[0].to_string(),
+            "let mymacro = fn() quote(2)"
+        );
+    }
+
     #[test]
     fn test_expand_macros() {
         let tests = [
@@ -278,8 +744,331 @@ mod tests {
             let env = Rc::new(RefCell::new(Enviroment::default()));
             let makro = MacroExpension::new(Rc::clone(&env));
             makro.define_macros(&mut program);
-            makro.expand_macros(&mut program);
+            makro.expand_macros(&mut program).unwrap();
             assert_eq!(format!("{}", program), format!("{}", expected));
         }
     }
+
+    #[test]
+    fn test_expand_across_programs() {
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let makro = MacroExpension::new(Rc::clone(&env));
+
+        let mut program = test_parse_program("let double = macro(x) { quote(unquote(x) * 2) };");
+        makro.expand(&mut program).unwrap();
+        assert!(program.statements.is_empty());
+
+        let mut program = test_parse_program("double(1 + 2)");
+        makro.expand(&mut program).unwrap();
+        assert_eq!(program.to_string(), "((1 + 2) * 2)");
+    }
+
+    fn test_expand_and_eval(input: &str) -> (Program, Rc<Object>) {
+        let mut program = test_parse_program(input);
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        MacroExpension::new(Rc::clone(&env))
+            .expand(&mut program)
+            .unwrap();
+        let result = Evaluator::new(env).eval(&program);
+        (program, result)
+    }
+
+    #[test]
+    fn test_hygiene() {
+        // Without renaming, `swap(x, tmp)` would expand to `[tmp, tmp]`.
+        let input = "
+            let swap = macro(a, b) {
+                quote(if (true) { let tmp = unquote(a); [unquote(b), tmp] })
+            };
+            let tmp = 1;
+            let x = 2;
+            swap(x, tmp)
+";
+        let (program, result) = test_expand_and_eval(input);
+        assert_eq!(result.inspect(), "[1, 2]");
+        let expanded = program.statements[2].to_string();
+        assert!(expanded.contains("let tmp@"), "{}", expanded);
+        assert!(expanded.contains("[tmp, tmp@"), "{}", expanded);
+
+        // Functions the template defines keep calling themselves.
+        let input = "
+            let countdown = macro(n) {
+                quote(if (true) {
+                    let go = fn(n) { if (n == 0) { 0 } else { go(n - 1) } };
+                    go(unquote(n))
+                })
+            };
+            let go = 7;
+            [countdown(3), go]
+";
+        assert_eq!(test_expand_and_eval(input).1.inspect(), "[0, 7]");
+
+        // Only uses inside a binding's scope are renamed.
+        let tests = [
+            (
+                "let x = 10; let m = macro() { quote([fn(x) { x }(1), x]) }; m()",
+                "[1, 10]",
+            ),
+            (
+                "let x = 10; let m = macro() { quote([[x * 2 for x in [1, 2]], x]) }; m()",
+                "[[2, 4], 10]",
+            ),
+            (
+                "let x = 10;
+                let m = macro() { quote(if (true) { let y = x; let x = 1; [y, x] }) };
+                m()",
+                "[10, 1]",
+            ),
+        ];
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                test_expand_and_eval(input).1.inspect(),
+                *expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_intentional_capture() {
+        let tests = [
+            (
+                "let aif = macro(cond, then) {
+                    quote(if (true) { let it = unquote(cond); if (it) { unquote(then) } }, [it])
+                };
+                aif(5 - 3, it * 10)",
+                "20",
+            ),
+            (
+                "let aif = macro(cond, then) {
+                    quote(if (true) { let it = unquote(cond); if (it) { unquote(then) } })
+                };
+                aif(5 - 3, it * 10)",
+                "Error: identifier not found: it",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(test_expand_and_eval(input).1.inspect(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_gensym() {
+        let input = "
+            let fresh = macro() { quote(unquote(gensym(\"t\"))) };
+            fresh();
+            fresh();
+";
+        let mut program = test_parse_program(input);
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        MacroExpension::new(env).expand(&mut program).unwrap();
+
+        let first = program.statements[0].to_string();
+        let second = program.statements[1].to_string();
+        assert!(first.starts_with("t@"), "{}", first);
+        assert!(second.starts_with("t@"), "{}", second);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_statement_macros() {
+        let input = "
+            let defcounter = macro(start) {
+                quote({
+                    let count = unquote(start);
+                    let bump = fn() { count = count + 1 };
+                }, [count, bump])
+            };
+            let assert = macro(cond) {
+                quote({ if (!(unquote(cond))) { return \"assertion failed\" } })
+            };
+            defcounter(5);
+            bump();
+            bump();
+            assert(count == 7);
+            count
+";
+        let (program, result) = test_expand_and_eval(input);
+        assert_eq!(result.inspect(), "7");
+        assert_eq!(program.statements[0].to_string(), "let count = 5");
+        assert_eq!(program.statements.len(), 6);
+
+        let input = "
+            let assert = macro(cond) {
+                quote({ if (!(unquote(cond))) { return \"assertion failed\" } })
+            };
+            assert(1 > 2);
+            3
+";
+        assert_eq!(test_expand_and_eval(input).1.inspect(), "assertion failed");
+
+        // In expression position, the statements run in place.
+        let input = "
+            let twice = macro(x) { quote({ let y = unquote(x); y + y }) };
+            let y = 1;
+            [twice(y + 2), y]
+";
+        assert_eq!(test_expand_and_eval(input).1.inspect(), "[6, 1]");
+    }
+
+    #[test]
+    fn test_unquote_splice() {
+        let tests = [
+            (
+                "let apply = macro(f, args) { quote(unquote(f)(unquote_splice(args))) };
+                apply(fn(a, b) { a - b }, [10, 3])",
+                "7",
+            ),
+            (
+                "let wrap = macro(xs) { quote([0, unquote_splice(xs), 4]) }; wrap([1, 2, 3])",
+                "[0, 1, 2, 3, 4]",
+            ),
+            (
+                "let twice = macro(e) { quote({ unquote_splice([e, e]) }) };
+                let n = 0;
+                twice(n = n + 1);
+                n",
+                "2",
+            ),
+            (
+                "let second = macro(xs) { quote(unquote_splice(xs)[1]) }; second([1, 2])",
+                "2",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                test_expand_and_eval(input).1.inspect(),
+                *expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_ast_builtins() {
+        let tests = [
+            (
+                "let flip = macro(call) {
+                    let parts = ast_children(call);
+                    quote(unquote(ast_call(parts[0], [parts[2], parts[1]])))
+                };
+                let sub = fn(a, b) { a - b };
+                flip(sub(1, 10))",
+                "9",
+            ),
+            (
+                "let getter = macro() { quote(unquote(ast_ident(\"val\" + \"ue\"))) };
+                let value = 5;
+                getter()",
+                "5",
+            ),
+            (
+                "let defn = macro(name, f) { ast_let(name, f) };
+                defn(fact, fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } });
+                fact(5)",
+                "120",
+            ),
+            (
+                "let describe = macro(e) { quote(unquote(ast_kind(e))) }; describe(a[1])",
+                "INDEX",
+            ),
+            (
+                "let double = macro() { quote(unquote(fn(x) { x * 2 })(4)) }; double()",
+                "8",
+            ),
+            (
+                "let inline = macro() { quote(unquote(len)(unquote(1..4))) }; inline()",
+                "3",
+            ),
+            (
+                "let pusher = macro() { quote(unquote([1].push)(2)) }; pusher()",
+                "[1, 2]",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                test_expand_and_eval(input).1.inspect(),
+                *expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let input = "
+            let one = macro() { 1 };
+            let bad = macro() { 1 + true };
+            let inc = macro(x) { quote(unquote(x) + 1) };
+            one();
+            [bad(), inc(2)];
+";
+        let mut program = test_parse_program(input);
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let (steps, errors) = MacroExpension::new(env).expand_traced(&mut program);
+
+        assert_eq!(
+            errors,
+            vec![
+                "macro one must return a quote, got 1",
+                "macro bad: type mismatch: INTEGER + BOOLEAN",
+            ]
+        );
+        assert_eq!(program.to_string(), "null[null, (2 + 1)]");
+        assert_eq!(steps.len(), 1);
+    }
+
+    #[test]
+    fn test_leftover_macros() {
+        let input = "
+            let m = macro() { quote(1) };
+            let f = fn() { let inner = macro() { quote(2) }; inner };
+            [m, m()];
+            puts(m);
+";
+        let mut program = test_parse_program(input);
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let errors = MacroExpension::new(env).expand(&mut program).unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "macros can only be defined by a top-level let",
+                "macro m can only be called",
+                "macro m can only be called",
+            ]
+        );
+        assert_eq!(
+            program.to_string(),
+            "let f = fn f() let inner = nullinner[null, 1]puts(null)"
+        );
+    }
+
+    #[test]
+    fn test_expand_traced() {
+        let input = "
+            let inc = macro(x) { quote(unquote(x) + 1) };
+            let twice = macro(e) { quote(unquote(e) * 2) };
+            twice(inc(y));
+";
+        let mut program = test_parse_program(input);
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let (steps, errors) = MacroExpension::new(env).expand_traced(&mut program);
+
+        assert_eq!(program.to_string(), "((y + 1) * 2)");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].call.to_string(), "twice(inc(y))");
+        assert_eq!(steps[0].result.to_string(), "(inc(y) * 2)");
+        assert_eq!(
+            steps[1].to_string(),
+            "expand inc(y)\n  x = QUOTE(y)\n  => (y + 1)"
+        );
+    }
 }
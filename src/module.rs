@@ -0,0 +1,233 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{Program, Statement},
+    enviroment::Enviroment,
+    lexer::Lexer,
+    makro::MacroExpension,
+    parser::Parser,
+};
+
+/// Lists the directories searched for imports not found next to the
+/// importing file, separated like `PATH`.
+pub const SEARCH_PATH_VAR: &str = "MONKEY_PATH";
+
+/// A parsed and macro expanded module, ready for an engine to run.
+#[derive(Debug)]
+pub struct Module {
+    /// Numbers modules from 1, as the main program is 0.
+    pub id: usize,
+    pub path: PathBuf,
+    pub program: Program,
+}
+
+impl Module {
+    /// The names bound by the module's `export` statements, in order.
+    pub fn exports(&self) -> Vec<String> {
+        self.program
+            .statements
+            .iter()
+            .flat_map(|stmt| match stmt {
+                Statement::Export(export) => export.names(),
+                _ => vec![],
+            })
+            .collect()
+    }
+}
+
+/// Finds and loads the files named by `import` statements. Each file is
+/// loaded once, and what an engine made of it is kept as a `T` for later
+/// imports.
+#[derive(Debug)]
+pub struct Modules<T> {
+    loaded: HashMap<PathBuf, T>,
+    /// The files being loaded, each imported by the one before it.
+    loading: Vec<PathBuf>,
+    search_path: Vec<PathBuf>,
+    next_id: usize,
+}
+
+impl<T: Clone> Default for Modules<T> {
+    fn default() -> Self {
+        let search_path = env::var_os(SEARCH_PATH_VAR)
+            .map(|paths| env::split_paths(&paths).collect())
+            .unwrap_or_default();
+        Self::new(search_path)
+    }
+}
+
+impl<T: Clone> Modules<T> {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            loaded: HashMap::new(),
+            loading: vec![],
+            search_path,
+            next_id: 0,
+        }
+    }
+
+    /// Sets the file the main program was read from. Its imports resolve
+    /// relative to it rather than to the current directory.
+    pub fn set_main_file(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        self.loading = vec![path];
+    }
+
+    /// Finds `path` next to the importing file, then in each directory of
+    /// the search path.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let base = self
+            .loading
+            .last()
+            .and_then(|file| file.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        std::iter::once(base)
+            .chain(self.search_path.iter().cloned())
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+            .and_then(|candidate| fs::canonicalize(candidate).ok())
+            .ok_or_else(|| format!("module not found: {}", path))
+    }
+
+    pub fn get(&self, path: &Path) -> Option<T> {
+        self.loaded.get(path).cloned()
+    }
+
+    /// Reads the module at a path `resolve` returned, which counts as loading
+    /// until `finish` is called. Importing it again before then is a cycle.
+    pub fn load(&mut self, path: PathBuf) -> Result<Module, String> {
+        if let Some(start) = self.loading.iter().position(|file| *file == path) {
+            let cycle: Vec<_> = self.loading[start..]
+                .iter()
+                .chain([&path])
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(format!("import cycle: {}", cycle.join(" -> ")));
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+        let mut lexer = Lexer::new(&source);
+        let mut parser = Parser::new(&mut lexer);
+        let mut program = parser.parse_module();
+        if !parser.errors.is_empty() {
+            return Err(format!("{}: {}", path.display(), parser.errors.join(", ")));
+        }
+        // The macros a module defines are only expanded within it.
+        MacroExpension::new(Rc::new(RefCell::new(Enviroment::default())))
+            .expand(&mut program)
+            .map_err(|errors| format!("{}: {}", path.display(), errors.join(", ")))?;
+
+        self.loading.push(path.to_owned());
+        self.next_id += 1;
+        Ok(Module {
+            id: self.next_id,
+            path,
+            program,
+        })
+    }
+
+    /// Ends loading `module`, keeping `value` for later imports. A module
+    /// that failed is finished with `None`, so importing it tries again.
+    pub fn finish(&mut self, module: &Module, value: Option<T>) {
+        self.loading.pop();
+        if let Some(value) = value {
+            self.loaded.insert(module.path.to_owned(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Modules;
+    use crate::test_helper::write_modules;
+
+    #[test]
+    fn test_resolve() {
+        let dir = write_modules(
+            "resolve",
+            &[
+                ("main.mk", ""),
+                ("lib/strings.mk", ""),
+                ("vendor/math.mk", ""),
+            ],
+        );
+        let mut modules: Modules<()> = Modules::new(vec![dir.join("vendor")]);
+        modules.set_main_file(&dir.join("main.mk"));
+
+        let strings = modules.resolve("lib/strings.mk").unwrap();
+        assert!(strings.ends_with("lib/strings.mk"), "{:?}", strings);
+        let math = modules.resolve("math.mk").unwrap();
+        assert!(math.ends_with("vendor/math.mk"), "{:?}", math);
+        assert_eq!(
+            modules.resolve("missing.mk"),
+            Err("module not found: missing.mk".into())
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = write_modules(
+            "load",
+            &[
+                ("main.mk", ""),
+                (
+                    "a.mk",
+                    "let twice = macro(x) { quote(unquote(x) * 2) };
+                    export let a = twice(2);
+                    export enum Shape { Circle(r), Dot }
+                    let hidden = 1;",
+                ),
+                ("broken.mk", "import strings"),
+                (
+                    "returns.mk",
+                    "let f = fn() { return 1 };
+                    if (true) { return 2 }
+                    export let x = 3;",
+                ),
+            ],
+        );
+        let mut modules: Modules<usize> = Modules::new(vec![]);
+        modules.set_main_file(&dir.join("main.mk"));
+
+        let path = modules.resolve("a.mk").unwrap();
+        let module = modules.load(path.to_owned()).unwrap();
+        assert_eq!(module.id, 1);
+        assert_eq!(module.exports(), vec!["a", "Circle", "Dot"]);
+        assert_eq!(
+            module.program.statements[0].to_string(),
+            "export let a = (2 * 2)"
+        );
+
+        let cycle = modules.load(path.to_owned()).unwrap_err();
+        assert!(cycle.starts_with("import cycle: "), "{}", cycle);
+        let expected = format!("a.mk -> {}", path.display());
+        assert!(cycle.ends_with(&expected), "{}", cycle);
+
+        modules.finish(&module, Some(7));
+        assert_eq!(modules.get(&path), Some(7));
+
+        let broken = modules.resolve("broken.mk").unwrap();
+        let error = modules.load(broken).unwrap_err();
+        assert!(
+            error.ends_with("broken.mk: expected next token to be String, got Ident instead"),
+            "{}",
+            error
+        );
+
+        let returns = modules.resolve("returns.mk").unwrap();
+        let error = modules.load(returns).unwrap_err();
+        assert!(
+            error.ends_with("returns.mk: return outside of function in a module"),
+            "{}",
+            error
+        );
+    }
+}
@@ -1,4 +1,10 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, hash::Hasher, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    hash::Hasher,
+    rc::Rc,
+};
 
 use fxhash::FxHasher64;
 
@@ -6,6 +12,7 @@ use crate::{
     ast::{BlockStatement, Expression, Identifier},
     code::Instructions,
     enviroment::Enviroment,
+    evaluator::Continuation,
 };
 
 #[derive(Debug, Eq, Hash, PartialEq, Clone)]
@@ -20,9 +27,17 @@ pub enum ObjectKind {
     BuiltinFunction,
     Array,
     Hash,
+    Range,
     Quote,
     CompileFunction,
     Closure,
+    StructDefinition,
+    Struct,
+    VariantDefinition,
+    Variant,
+    Generator,
+    Channel,
+    Cell,
 }
 
 impl Display for ObjectKind {
@@ -38,9 +53,17 @@ impl Display for ObjectKind {
             Self::BuiltinFunction => write!(f, "BUILTIN"),
             Self::Array => write!(f, "ARRAY"),
             Self::Hash => write!(f, "HASH"),
+            Self::Range => write!(f, "RANGE"),
             Self::Quote => write!(f, "QUOTE"),
             Self::CompileFunction => write!(f, "COMPILED_FUNCTION"),
             Self::Closure => write!(f, "CLOSURE"),
+            Self::StructDefinition => write!(f, "STRUCT_DEFINITION"),
+            Self::Struct => write!(f, "STRUCT"),
+            Self::VariantDefinition => write!(f, "VARIANT_DEFINITION"),
+            Self::Variant => write!(f, "VARIANT"),
+            Self::Generator => write!(f, "GENERATOR"),
+            Self::Channel => write!(f, "CHANNEL"),
+            Self::Cell => write!(f, "CELL"),
         }
     }
 }
@@ -52,6 +75,7 @@ pub enum Object {
     Boolean(Boolean),
     Array(Array),
     Hash(Hash),
+    Range(Range),
     Null(Null),
     ReturnValue(ReturnValue),
     RuntimeError(RuntimeError),
@@ -61,6 +85,13 @@ pub enum Object {
     Quote(Quote),
     Macro(Macro),
     Closure(Closure),
+    StructDefinition(StructDefinition),
+    Struct(Struct),
+    VariantDefinition(VariantDefinition),
+    Variant(Variant),
+    Generator(Generator),
+    Channel(Channel),
+    Cell(Cell),
 }
 
 impl Object {
@@ -71,6 +102,7 @@ impl Object {
             Self::Boolean(o) => o.kind(),
             Self::Array(o) => o.kind(),
             Self::Hash(o) => o.kind(),
+            Self::Range(o) => o.kind(),
             Self::Null(o) => o.kind(),
             Self::ReturnValue(o) => o.kind(),
             Self::RuntimeError(o) => o.kind(),
@@ -80,6 +112,13 @@ impl Object {
             Self::Quote(o) => o.kind(),
             Self::Macro(o) => o.kind(),
             Self::Closure(o) => o.kind(),
+            Self::StructDefinition(o) => o.kind(),
+            Self::Struct(o) => o.kind(),
+            Self::VariantDefinition(o) => o.kind(),
+            Self::Variant(o) => o.kind(),
+            Self::Generator(o) => o.kind(),
+            Self::Channel(o) => o.kind(),
+            Self::Cell(o) => o.kind(),
         }
     }
 
@@ -90,6 +129,7 @@ impl Object {
             Self::Boolean(o) => o.inspect(),
             Self::Array(o) => o.inspect(),
             Self::Hash(o) => o.inspect(),
+            Self::Range(o) => o.inspect(),
             Self::Null(o) => o.inspect(),
             Self::ReturnValue(o) => o.inspect(),
             Self::RuntimeError(o) => o.inspect(),
@@ -99,10 +139,84 @@ impl Object {
             Self::Quote(o) => o.inspect(),
             Self::Macro(o) => o.inspect(),
             Self::Closure(o) => o.inspect(),
+            Self::StructDefinition(o) => o.inspect(),
+            Self::Struct(o) => o.inspect(),
+            Self::VariantDefinition(o) => o.inspect(),
+            Self::Variant(o) => o.inspect(),
+            Self::Generator(o) => o.inspect(),
+            Self::Channel(o) => o.inspect(),
+            Self::Cell(o) => o.inspect(),
+        }
+    }
+
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Self::Str(o) => Some(o.hash_key()),
+            Self::Integer(o) => Some(o.hash_key()),
+            Self::Boolean(o) => Some(o.hash_key()),
+            Self::Struct(o) => o.hash_key(),
+            _ => None,
+        }
+    }
+
+    /// Returns the items a comprehension iterates over, or `None` if the object
+    /// is not iterable. Hashes yield their entries as `[key, value]` pairs.
+    pub fn iter_items(&self) -> Option<Box<dyn Iterator<Item = Rc<Object>> + '_>> {
+        match self {
+            Self::Array(o) => Some(Box::new(o.elements.iter().cloned())),
+            Self::Range(o) => Some(Box::new(
+                (o.start as i128..o.end_exclusive())
+                    .map(|value| Object::Integer(Integer::new(value as i64)).into()),
+            )),
+            Self::Hash(o) => Some(Box::new(o.value.values().map(|pair| {
+                Object::Array(Array::new(vec![
+                    Rc::clone(&pair.key),
+                    Rc::clone(&pair.value),
+                ]))
+                .into()
+            }))),
+            _ => None,
+        }
+    }
+
+    /// Tests whether `value` is in this object for the `in` operator, or
+    /// returns `None` if the object cannot hold values.
+    pub fn contains(&self, value: &Object) -> Option<bool> {
+        match self {
+            Self::Range(o) => Some(matches!(value, Object::Integer(i) if o.contains(i.value))),
+            Self::Array(o) => Some(o.elements.iter().any(|e| **e == *value)),
+            Self::Hash(o) => Some(matches!(value.hash_key(), Some(k) if o.value.contains_key(&k))),
+            Self::Str(o) => Some(matches!(value, Object::Str(s) if o.value.contains(&s.value))),
+            _ => None,
         }
     }
 }
 
+/// Resolves a possibly negative index against a sequence of `len` items,
+/// returning `None` when it falls outside the sequence.
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let len = len as i64;
+    let index = if index < 0 { index + len } else { index };
+    if index < 0 || index >= len {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+/// Resolves optional slice bounds against a sequence of `len` items. Negative
+/// bounds count from the end and out-of-range bounds are clamped, so slicing
+/// never fails and yields an empty sequence when `start` passes `end`.
+fn slice_range(start: Option<i64>, end: Option<i64>, len: usize) -> (usize, usize) {
+    let clamp = |bound: i64| {
+        let bound = if bound < 0 { bound + len as i64 } else { bound };
+        bound.clamp(0, len as i64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    (start, end.max(start))
+}
+
 pub trait Inspector {
     fn kind(&self) -> ObjectKind;
     fn inspect(&self) -> String;
@@ -148,6 +262,21 @@ impl Str {
     pub fn new(value: String) -> Self {
         Self { value }
     }
+
+    pub fn char_count(&self) -> usize {
+        self.value.chars().count()
+    }
+
+    pub fn get(&self, index: i64) -> Option<Self> {
+        normalize_index(index, self.char_count())
+            .and_then(|i| self.value.chars().nth(i))
+            .map(|ch| Self::new(ch.to_string()))
+    }
+
+    pub fn slice(&self, start: Option<i64>, end: Option<i64>) -> Self {
+        let (start, end) = slice_range(start, end, self.char_count());
+        Self::new(self.value.chars().skip(start).take(end - start).collect())
+    }
 }
 
 impl Inspector for Str {
@@ -263,6 +392,7 @@ pub struct Function {
     pub parameters: Vec<Identifier>,
     pub body: BlockStatement,
     pub env: Rc<RefCell<Enviroment>>,
+    pub is_generator: bool,
 }
 
 impl Function {
@@ -275,6 +405,7 @@ impl Function {
             parameters,
             body,
             env,
+            is_generator: false,
         }
     }
 }
@@ -339,11 +470,24 @@ impl Inspector for Macro {
 #[derive(Debug, PartialEq, Clone)]
 pub struct BuiltinFunction {
     pub name: String,
+    /// The value a builtin was looked up on with method syntax, e.g. `arr`
+    /// in `arr.push(4)`. It is passed as the first argument when called.
+    pub receiver: Option<Rc<Object>>,
 }
 
 impl BuiltinFunction {
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self {
+            name,
+            receiver: None,
+        }
+    }
+
+    pub fn with_receiver(name: String, receiver: Rc<Object>) -> Self {
+        Self {
+            name,
+            receiver: Some(receiver),
+        }
     }
 }
 
@@ -366,6 +510,15 @@ impl Array {
     pub fn new(elements: Vec<Rc<Object>>) -> Self {
         Self { elements }
     }
+
+    pub fn get(&self, index: i64) -> Option<Rc<Object>> {
+        normalize_index(index, self.elements.len()).map(|i| Rc::clone(&self.elements[i]))
+    }
+
+    pub fn slice(&self, start: Option<i64>, end: Option<i64>) -> Self {
+        let (start, end) = slice_range(start, end, self.elements.len());
+        Self::new(self.elements[start..end].to_vec())
+    }
 }
 
 impl Inspector for Array {
@@ -385,15 +538,76 @@ impl Inspector for Array {
     }
 }
 
+/// An integer range that computes its items on demand instead of storing them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    pub fn new(start: i64, end: i64, inclusive: bool) -> Self {
+        Self {
+            start,
+            end,
+            inclusive,
+        }
+    }
+
+    /// The number of values in the range, or `None` if that does not fit a
+    /// `usize`, as for the whole of `i64`.
+    pub fn len(&self) -> Option<usize> {
+        usize::try_from((self.end_exclusive() - self.start as i128).max(0)).ok()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end_exclusive() <= self.start as i128
+    }
+
+    /// Gets the value at `index`, counting from the end if it is negative.
+    pub fn get(&self, index: i64) -> Option<i64> {
+        let (start, end) = (self.start as i128, self.end_exclusive());
+        let value = if index < 0 { end } else { start } + index as i128;
+        (start..end).contains(&value).then_some(value as i64)
+    }
+
+    fn end_exclusive(&self) -> i128 {
+        self.end as i128 + self.inclusive as i128
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        value >= self.start && (value < self.end || self.inclusive && value == self.end)
+    }
+}
+
+impl Inspector for Range {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Range
+    }
+
+    fn inspect(&self) -> String {
+        if self.inclusive {
+            format!("{}..={}", self.start, self.end)
+        } else {
+            format!("{}..{}", self.start, self.end)
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Hash {
-    pub value: HashMap<HashKey, Rc<Object>>,
+    pub value: HashMap<HashKey, HashPair>,
 }
 
 impl Hash {
-    pub fn new(value: HashMap<HashKey, Rc<Object>>) -> Self {
+    pub fn new(value: HashMap<HashKey, HashPair>) -> Self {
         Self { value }
     }
+
+    pub fn get(&self, key: &HashKey) -> Option<&Rc<Object>> {
+        self.value.get(key).map(|pair| &pair.value)
+    }
 }
 
 impl Inspector for Hash {
@@ -406,13 +620,26 @@ impl Inspector for Hash {
             "{{{}}}",
             self.value
                 .iter()
-                .map(|(k, v)| format!("{}: {}", k.name, v.inspect()))
+                .map(|(k, pair)| format!("{}: {}", k.name, pair.value.inspect()))
                 .collect::<Vec<String>>()
                 .join(", ")
         )
     }
 }
 
+/// Keeps the original key object next to its value so hashes can be iterated.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HashPair {
+    pub key: Rc<Object>,
+    pub value: Rc<Object>,
+}
+
+impl HashPair {
+    pub fn new(key: Rc<Object>, value: Rc<Object>) -> Self {
+        Self { key, value }
+    }
+}
+
 #[derive(Debug, Eq, Hash, PartialEq, Clone)]
 pub struct HashKey {
     pub kind: ObjectKind,
@@ -451,15 +678,21 @@ impl Inspector for Quote {
 pub struct CompiledFunction {
     pub instructions: Instructions,
     pub num_locals: u16,
-    pub num_parameters: u8,
+    pub num_parameters: u16,
+    pub is_generator: bool,
+    /// The module whose globals the function reads and writes, 0 being the
+    /// main program.
+    pub module: usize,
 }
 
 impl CompiledFunction {
-    pub fn new(instructions: Instructions, num_locals: u16, num_parameters: u8) -> Self {
+    pub fn new(instructions: Instructions, num_locals: u16, num_parameters: u16) -> Self {
         Self {
             instructions,
             num_locals,
             num_parameters,
+            is_generator: false,
+            module: 0,
         }
     }
 }
@@ -484,6 +717,14 @@ impl Closure {
     pub fn new(func: CompiledFunction, free: Vec<Rc<Object>>) -> Self {
         Self { func, free }
     }
+
+    /// Returns the cell holding the free variable at `index`.
+    pub fn free_cell(&self, index: usize) -> &Cell {
+        match *self.free[index] {
+            Object::Cell(ref cell) => cell,
+            ref other => panic!("free variable is not a cell: {}", other.kind()),
+        }
+    }
 }
 
 impl Inspector for Closure {
@@ -495,3 +736,320 @@ impl Inspector for Closure {
         format!("Closure[{:p}]", self)
     }
 }
+
+/// A local variable captured by a closure. The stack slot it was boxed in and
+/// every closure capturing it share the cell, so an assignment through any
+/// of them is seen by all.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cell {
+    value: RefCell<Rc<Object>>,
+}
+
+impl Cell {
+    pub fn new(value: Rc<Object>) -> Self {
+        Self {
+            value: RefCell::new(value),
+        }
+    }
+
+    pub fn get(&self) -> Rc<Object> {
+        Rc::clone(&self.value.borrow())
+    }
+
+    pub fn set(&self, value: Rc<Object>) {
+        self.value.replace(value);
+    }
+}
+
+impl Inspector for Cell {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Cell
+    }
+
+    fn inspect(&self) -> String {
+        self.value.borrow().inspect()
+    }
+}
+
+/// The result of calling a `fn*`. The state is shared, so every copy of a
+/// generator advances the same suspended call.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Generator {
+    pub state: Rc<RefCell<GeneratorState>>,
+}
+
+impl Generator {
+    pub fn new(state: GeneratorState) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+}
+
+impl Inspector for Generator {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Generator
+    }
+
+    fn inspect(&self) -> String {
+        format!("Generator[{:p}]", Rc::as_ptr(&self.state))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum GeneratorState {
+    /// A VM frame waiting to run from `ip`, with the stack slots it owned
+    /// starting at its base pointer.
+    Frame {
+        closure: Closure,
+        ip: isize,
+        stack: Vec<Rc<Object>>,
+    },
+    /// An evaluator call waiting to run the rest of its body.
+    Continuation(Continuation),
+    /// Currently executing, so it cannot be resumed again until it yields.
+    Running,
+    Done,
+}
+
+/// An unbuffered channel between VM tasks: a send waits for a matching
+/// receive and the other way round.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Channel {
+    pub id: usize,
+    pub state: Rc<RefCell<ChannelState>>,
+}
+
+impl Channel {
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            state: Rc::new(RefCell::new(ChannelState::default())),
+        }
+    }
+}
+
+impl Inspector for Channel {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Channel
+    }
+
+    fn inspect(&self) -> String {
+        format!("Channel[{}]", self.id)
+    }
+}
+
+/// The tasks blocked on a channel, by task id, in the order they arrived.
+#[derive(Debug, PartialEq, Default)]
+pub struct ChannelState {
+    pub senders: VecDeque<(usize, Rc<Object>)>,
+    pub receivers: VecDeque<usize>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StructDefinition {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl StructDefinition {
+    pub fn new(name: String, fields: Vec<String>) -> Self {
+        Self { name, fields }
+    }
+
+    /// Builds an instance from positional constructor arguments, one per
+    /// declared field.
+    pub fn construct(&self, values: Vec<Rc<Object>>) -> Object {
+        if values.len() != self.fields.len() {
+            return Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of fields for {}: got={}, want={}",
+                self.name,
+                values.len(),
+                self.fields.len()
+            )));
+        }
+
+        Object::Struct(Struct::new(self.to_owned(), values))
+    }
+}
+
+impl Inspector for StructDefinition {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::StructDefinition
+    }
+
+    fn inspect(&self) -> String {
+        braced(&format!("struct {}", self.name), &self.fields)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Struct {
+    pub definition: StructDefinition,
+    pub values: Vec<Rc<Object>>,
+}
+
+impl Struct {
+    pub fn new(definition: StructDefinition, values: Vec<Rc<Object>>) -> Self {
+        Self { definition, values }
+    }
+
+    pub fn get(&self, field: &str) -> Option<Rc<Object>> {
+        self.definition
+            .fields
+            .iter()
+            .position(|f| f == field)
+            .map(|i| Rc::clone(&self.values[i]))
+    }
+
+    /// Copies the struct with `field` set to `value`, if it has that field.
+    pub fn with_field(&self, field: &str, value: Rc<Object>) -> Option<Self> {
+        let i = self.definition.fields.iter().position(|f| f == field)?;
+        let mut values = self.values.to_owned();
+        values[i] = value;
+        Some(Self::new(self.definition.to_owned(), values))
+    }
+
+    /// A struct can be a hash key when all of its values can.
+    pub fn hash_key(&self) -> Option<HashKey> {
+        let fields = self
+            .definition
+            .fields
+            .iter()
+            .zip(self.values.iter())
+            .map(|(f, v)| {
+                v.hash_key()
+                    .map(|key| format!("{}: {} {:?}", f, key.kind, key.name))
+            })
+            .collect::<Option<Vec<String>>>()?;
+        let name = format!("{} {{ {} }}", self.definition.name, fields.join(", "));
+        let mut hasher = FxHasher64::default();
+        hasher.write(name.as_bytes());
+        let value = hasher.finish() as i64;
+        Some(HashKey::new(ObjectKind::Struct, name, value))
+    }
+
+    pub fn unknown_field(&self, field: &str) -> Object {
+        Object::RuntimeError(RuntimeError::new(format!(
+            "unknown field {} for struct {}",
+            field, self.definition.name
+        )))
+    }
+}
+
+impl Inspector for Struct {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Struct
+    }
+
+    fn inspect(&self) -> String {
+        let fields: Vec<String> = self
+            .definition
+            .fields
+            .iter()
+            .zip(self.values.iter())
+            .map(|(f, v)| format!("{}: {}", f, v.inspect()))
+            .collect();
+        braced(&self.definition.name, &fields)
+    }
+}
+
+/// Renders `name { a, b }`, or `name {}` when there is nothing inside.
+fn braced(name: &str, items: &[String]) -> String {
+    if items.is_empty() {
+        format!("{} {{}}", name)
+    } else {
+        format!("{} {{ {} }}", name, items.join(", "))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VariantDefinition {
+    pub enum_name: String,
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl VariantDefinition {
+    pub fn new(enum_name: String, name: String, fields: Vec<String>) -> Self {
+        Self {
+            enum_name,
+            name,
+            fields,
+        }
+    }
+
+    /// Builds a tagged value from positional constructor arguments, one per
+    /// declared payload field.
+    pub fn construct(&self, values: Vec<Rc<Object>>) -> Object {
+        if values.len() != self.fields.len() {
+            return Object::RuntimeError(RuntimeError::new(format!(
+                "wrong number of fields for {}: got={}, want={}",
+                self.name,
+                values.len(),
+                self.fields.len()
+            )));
+        }
+
+        Object::Variant(Variant::new(self.to_owned(), values))
+    }
+
+    /// Names the variant along with its enum, so that variants of different
+    /// enums never match each other.
+    pub fn tag(&self) -> String {
+        format!("{}::{}", self.enum_name, self.name)
+    }
+}
+
+impl Inspector for VariantDefinition {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::VariantDefinition
+    }
+
+    fn inspect(&self) -> String {
+        format!(
+            "{}::{}({})",
+            self.enum_name,
+            self.name,
+            self.fields.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variant {
+    pub definition: VariantDefinition,
+    pub values: Vec<Rc<Object>>,
+}
+
+impl Variant {
+    pub fn new(definition: VariantDefinition, values: Vec<Rc<Object>>) -> Self {
+        Self { definition, values }
+    }
+
+    pub fn tag(&self) -> String {
+        self.definition.tag()
+    }
+}
+
+impl Inspector for Variant {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Variant
+    }
+
+    fn inspect(&self) -> String {
+        if self.values.is_empty() {
+            self.definition.name.to_owned()
+        } else {
+            format!(
+                "{}({})",
+                self.definition.name,
+                self.values
+                    .iter()
+                    .map(|v| v.inspect())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
+    }
+}
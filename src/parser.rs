@@ -2,23 +2,30 @@ use std::mem::swap;
 
 use crate::{
     ast::{
-        ArrayLiteral, BlockStatement, BooleanExpression, CallExpression, Expression,
-        ExpressionStatement, FunctionLiteral, HashLiteral, HashMember, Identifier, IfExpression,
-        IndexExpression, InfixExpression, IntegerLiteral, LetStatement, MacroLiteral,
-        PrefixExpression, Program, ReturnStatement, Statement, StringLiteral,
+        ArrayLiteral, AssignExpression, BlockStatement, BooleanExpression, CallExpression,
+        ComprehensionClause, EnumStatement, EnumVariant, ExportStatement, Expression,
+        ExpressionStatement, FunctionLiteral, GroupedExpression, HashComprehension, HashLiteral,
+        HashMember, Identifier, IfExpression, ImportStatement, IndexExpression, InfixExpression,
+        IntegerLiteral, LetStatement, ListComprehension, MacroLiteral, MatchArm, MatchExpression,
+        NullLiteral, Pattern, PrefixExpression, Program, ReturnStatement, SliceExpression,
+        SpreadExpression, Statement, StringLiteral, StructStatement, YieldExpression,
     },
     lexer::Lexer,
     token::{Token, TokenKind},
 };
 
 const LOWEST: i8 = 1;
-const EQUALS: i8 = 2;
-const LESSGREATER: i8 = 3;
-const SUM: i8 = 4;
-const PRODUCT: i8 = 5;
-const PREFIX: i8 = 6;
-const CALL: i8 = 7;
-const INDEX: i8 = 8;
+const ASSIGN: i8 = 2;
+const PIPE: i8 = 3;
+const COALESCE: i8 = 4;
+const EQUALS: i8 = 5;
+const LESSGREATER: i8 = 6;
+const RANGE: i8 = 7;
+const SUM: i8 = 8;
+const PRODUCT: i8 = 9;
+const PREFIX: i8 = 10;
+const CALL: i8 = 11;
+const INDEX: i8 = 12;
 
 #[derive(Debug)]
 pub struct Parser<'a> {
@@ -28,23 +35,35 @@ pub struct Parser<'a> {
     peek_token: Token,
 
     pub errors: Vec<String>,
+
+    /// Whether the function being parsed is a `fn*`, where `yield` is allowed.
+    in_generator: bool,
+
+    /// Whether `return` is allowed here. Only function and macro bodies allow
+    /// it in a module, as the module's exports are taken after its last
+    /// statement.
+    return_allowed: bool,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: &'a mut Lexer) -> Self {
         let cur_token = lexer.next_token();
         let peek_token = lexer.next_token();
+        let errors = lexer.errors.drain(..).collect();
         Self {
             lexer,
             cur_token,
             peek_token,
-            errors: vec![],
+            errors,
+            in_generator: false,
+            return_allowed: true,
         }
     }
 
     fn next_token(&mut self) {
         swap(&mut self.cur_token, &mut self.peek_token);
         self.peek_token = self.lexer.next_token();
+        self.errors.append(&mut self.lexer.errors);
     }
 
     pub fn parse_program(&mut self) -> Program {
@@ -60,17 +79,34 @@ impl<'a> Parser<'a> {
         program
     }
 
+    /// Parses the program of an imported module.
+    pub fn parse_module(&mut self) -> Program {
+        self.return_allowed = false;
+        self.parse_program()
+    }
+
     fn parse_statement(&mut self) -> Option<Statement> {
         match self.cur_token {
-            Token(TokenKind::Let, _) => self.parse_let_statement(),
+            Token(TokenKind::Let, _) => self.parse_let_statement(false),
+            Token(TokenKind::Const, _) => self.parse_let_statement(true),
             Token(TokenKind::Return, _) => self.parse_return_statement(),
+            Token(TokenKind::Struct, _) => self.parse_struct_statement(),
+            Token(TokenKind::Enum, _) => self.parse_enum_statement(),
+            Token(TokenKind::Import, _) => self.parse_import_statement(),
+            Token(TokenKind::Export, _) => self.parse_export_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
     fn parse_expression_statement(&mut self) -> Option<Statement> {
         if let Some(expression) = self.parse_expression(LOWEST) {
-            let stmt = Statement::Expression(ExpressionStatement::new(expression));
+            // `fn* gen() { ... }` on its own declares `gen`.
+            let stmt = match expression {
+                Expression::FunctionLiteral(ref func) if !func.name.is_empty() => Statement::Let(
+                    LetStatement::new(Identifier::new(func.name.to_owned()), expression),
+                ),
+                _ => Statement::Expression(ExpressionStatement::new(expression)),
+            };
 
             if self.peek_token_is(&TokenKind::Semicolon) {
                 self.next_token();
@@ -84,6 +120,14 @@ impl<'a> Parser<'a> {
     fn parse_expression(&mut self, precendence: i8) -> Option<Expression> {
         let mut left_exp = self.prefix_parse();
         if left_exp.is_none() {
+            // Malformed integer literals have already been reported, either by
+            // the lexer as an `Illegal` token with text or by
+            // `parse_integer_literal`.
+            match self.cur_token {
+                Token(TokenKind::Int, _) => return None,
+                Token(TokenKind::Illegal, ref text) if !text.is_empty() => return None,
+                _ => {}
+            }
             self.errors.push(format!(
                 "no prefix parse function for {:?} found",
                 self.cur_token.0
@@ -94,7 +138,7 @@ impl<'a> Parser<'a> {
         while !self.peek_token_is(&TokenKind::Semicolon) && precendence < self.peek_precedence() {
             self.next_token();
 
-            left_exp = self.infix_parse(left_exp.unwrap());
+            left_exp = self.infix_parse(left_exp?);
         }
 
         left_exp
@@ -107,9 +151,16 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_integer_literal(&mut self) -> Option<Expression> {
-        Some(Expression::IntegerLiteral(IntegerLiteral::new(
-            self.cur_token.1.parse::<i64>().unwrap(),
-        )))
+        match self.cur_token.1.parse::<i64>() {
+            Ok(value) => Some(Expression::IntegerLiteral(IntegerLiteral::new(value))),
+            Err(_) => {
+                self.errors.push(format!(
+                    "integer literal {} is out of range",
+                    self.cur_token.1
+                ));
+                None
+            }
+        }
     }
 
     fn parse_string_literal(&mut self) -> Option<Expression> {
@@ -119,9 +170,86 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_array_literal(&mut self) -> Option<Expression> {
-        Some(Expression::ArrayLiteral(ArrayLiteral::new(
-            self.parse_expression_list(TokenKind::Rbracket),
-        )))
+        if self.peek_token_is(&TokenKind::Rbracket) {
+            self.next_token();
+            return Some(Expression::ArrayLiteral(ArrayLiteral::new(vec![])));
+        }
+
+        self.next_token();
+        let first = self.parse_element()?;
+
+        if self.peek_token_is(&TokenKind::For) {
+            if let Expression::Spread(_) = first {
+                self.errors
+                    .push("spread operator is not allowed here".into());
+            }
+            let clause = self.parse_comprehension_clause()?;
+
+            if !self.expect_peek(TokenKind::Rbracket) {
+                return None;
+            }
+
+            return Some(Expression::ListComprehension(ListComprehension::new(
+                first, clause,
+            )));
+        }
+
+        let mut elements = vec![first];
+
+        while self.peek_token_is(&TokenKind::Comma) {
+            self.next_token();
+            self.next_token();
+
+            elements.push(self.parse_element()?);
+        }
+
+        if !self.expect_peek(TokenKind::Rbracket) {
+            return Some(Expression::ArrayLiteral(ArrayLiteral::new(vec![])));
+        }
+
+        Some(Expression::ArrayLiteral(ArrayLiteral::new(elements)))
+    }
+
+    fn parse_comprehension_clause(&mut self) -> Option<ComprehensionClause> {
+        self.next_token();
+
+        let mut bindings = vec![];
+        loop {
+            if !self.expect_peek(TokenKind::Ident) {
+                return None;
+            }
+            bindings.push(Identifier::new(self.cur_token.1.to_owned()));
+
+            if !self.peek_token_is(&TokenKind::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+
+        if bindings.len() > 2 {
+            self.errors.push(format!(
+                "comprehension takes one or two bindings, got {}",
+                bindings.len()
+            ));
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::In) {
+            return None;
+        }
+
+        self.next_token();
+        let iterable = self.parse_expression(LOWEST)?;
+
+        let condition = if self.peek_token_is(&TokenKind::If) {
+            self.next_token();
+            self.next_token();
+            Some(self.parse_expression(LOWEST)?)
+        } else {
+            None
+        };
+
+        Some(ComprehensionClause::new(bindings, iterable, condition))
     }
 
     fn parse_hash_literal(&mut self) -> Option<Expression> {
@@ -130,7 +258,16 @@ impl<'a> Parser<'a> {
         while !self.peek_token_is(&TokenKind::Rbrace) {
             self.next_token();
 
-            let key = self.parse_expression(LOWEST).unwrap();
+            let key = self.parse_element().unwrap();
+
+            if let Expression::Spread(spread) = key {
+                members.push(HashMember::spread(*spread.value));
+
+                if !self.peek_token_is(&TokenKind::Rbrace) && !self.expect_peek(TokenKind::Comma) {
+                    return None;
+                }
+                continue;
+            }
 
             if !self.expect_peek(TokenKind::Colon) {
                 continue;
@@ -140,6 +277,18 @@ impl<'a> Parser<'a> {
 
             let value = self.parse_expression(LOWEST).unwrap();
 
+            if members.is_empty() && self.peek_token_is(&TokenKind::For) {
+                let clause = self.parse_comprehension_clause()?;
+
+                if !self.expect_peek(TokenKind::Rbrace) {
+                    return None;
+                }
+
+                return Some(Expression::HashComprehension(HashComprehension::new(
+                    key, value, clause,
+                )));
+            }
+
             members.push(HashMember::new(key, value));
 
             if !self.peek_token_is(&TokenKind::Rbrace) && !self.expect_peek(TokenKind::Comma) {
@@ -163,13 +312,13 @@ impl<'a> Parser<'a> {
         }
 
         self.next_token();
-        list.push(self.parse_expression(LOWEST).unwrap());
+        list.push(self.parse_element().unwrap());
 
         while self.peek_token_is(&TokenKind::Comma) {
             self.next_token();
             self.next_token();
 
-            list.push(self.parse_expression(LOWEST).unwrap());
+            list.push(self.parse_element().unwrap());
         }
 
         if !self.expect_peek(end_token) {
@@ -190,7 +339,11 @@ impl<'a> Parser<'a> {
             return None;
         }
 
-        if let Some(body) = self.parse_block_statement() {
+        let return_allowed = std::mem::replace(&mut self.return_allowed, true);
+        let body = self.parse_block_statement();
+        self.return_allowed = return_allowed;
+
+        if let Some(body) = body {
             return Some(Expression::MacroLiteral(MacroLiteral { parameters, body }));
         }
 
@@ -198,6 +351,18 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_function_literal(&mut self) -> Option<Expression> {
+        let is_generator = self.peek_token_is(&TokenKind::Asterisk);
+        if is_generator {
+            self.next_token();
+        }
+
+        let name = if self.peek_token_is(&TokenKind::Ident) {
+            self.next_token();
+            self.cur_token.1.to_owned()
+        } else {
+            "".into()
+        };
+
         if !self.expect_peek(TokenKind::Lparen) {
             return None;
         }
@@ -208,17 +373,95 @@ impl<'a> Parser<'a> {
             return None;
         }
 
-        if let Some(body) = self.parse_block_statement() {
-            return Some(Expression::FunctionLiteral(FunctionLiteral::new(
-                "".into(),
-                parameters,
-                body,
-            )));
+        let in_generator = std::mem::replace(&mut self.in_generator, is_generator);
+        let return_allowed = std::mem::replace(&mut self.return_allowed, true);
+        let body = self.parse_block_statement();
+        self.in_generator = in_generator;
+        self.return_allowed = return_allowed;
+
+        if let Some(body) = body {
+            if is_generator {
+                self.check_yield_positions(&body);
+            }
+            let mut func = FunctionLiteral::new(name, parameters, body);
+            func.is_generator = is_generator;
+            return Some(Expression::FunctionLiteral(func));
         }
 
         None
     }
 
+    /// A suspended generator can only resume from a statement, a `let` or
+    /// `return` value, an `if` or `match` branch or a comprehension body, so
+    /// a `yield` nested anywhere else is rejected.
+    fn check_yield_positions(&mut self, block: &BlockStatement) {
+        for stmt in block.statements.iter() {
+            match stmt {
+                Statement::Expression(stmt) => self.check_resumable(&stmt.expression),
+                Statement::Let(stmt) => self.check_resumable(&stmt.value),
+                Statement::Return(stmt) => self.check_resumable(&stmt.return_value),
+                Statement::Struct(_)
+                | Statement::Enum(_)
+                | Statement::Import(_)
+                | Statement::Export(_) => {}
+            }
+        }
+    }
+
+    fn check_resumable(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Yield(node) => self.check_no_yield(node.value.contains_yield()),
+            Expression::If(node) => {
+                self.check_no_yield(node.condition.contains_yield());
+                self.check_yield_positions(&node.consequence);
+                if let Some(ref alternative) = node.alternative {
+                    self.check_yield_positions(alternative);
+                }
+            }
+            Expression::Match(node) => {
+                self.check_no_yield(node.subject.contains_yield());
+                for arm in node.arms.iter() {
+                    self.check_yield_positions(&arm.body);
+                }
+            }
+            Expression::ListComprehension(node) => {
+                self.check_no_yield(node.clause.contains_yield());
+                self.check_resumable(&node.element);
+            }
+            Expression::HashComprehension(node) => {
+                self.check_no_yield(node.clause.contains_yield());
+                self.check_resumable(&node.key);
+                self.check_resumable(&node.value);
+            }
+            _ => self.check_no_yield(expression.contains_yield()),
+        }
+    }
+
+    fn check_no_yield(&mut self, contains_yield: bool) {
+        if contains_yield {
+            self.errors
+                .push("yield is not allowed in this position".into());
+        }
+    }
+
+    fn parse_yield_expression(&mut self) -> Option<Expression> {
+        if !self.in_generator {
+            self.errors
+                .push("yield outside of generator function".into());
+        }
+
+        if self.peek_token_is(&TokenKind::Semicolon) || self.peek_token_is(&TokenKind::Rbrace) {
+            return Some(Expression::Yield(YieldExpression::new(
+                Expression::NullLiteral(NullLiteral::default()),
+            )));
+        }
+
+        self.next_token();
+
+        let value = self.parse_expression(LOWEST)?;
+        Some(Expression::Yield(YieldExpression::new(value)))
+    }
+
     fn parse_function_parameters(&mut self) -> Vec<Identifier> {
         let mut identifiers: Vec<_> = vec![];
 
@@ -262,13 +505,46 @@ impl<'a> Parser<'a> {
     fn parse_prefix_expression(&mut self) -> Option<Expression> {
         let operator = self.cur_token.1.to_owned();
 
+        if operator == "-"
+            && self.peek_token_is(&TokenKind::Int)
+            && self.peek_token.1 == i64::MIN.unsigned_abs().to_string()
+        {
+            self.next_token();
+            return Some(Expression::IntegerLiteral(IntegerLiteral::new(i64::MIN)));
+        }
+
         self.next_token();
 
-        let right = self.parse_expression(PREFIX).unwrap();
+        let right = self.parse_expression(PREFIX)?;
 
         Some(Expression::Prefix(PrefixExpression::new(operator, right)))
     }
 
+    /// Parses an array element, a call argument or a hash member, the only
+    /// places a spread is allowed.
+    fn parse_element(&mut self) -> Option<Expression> {
+        if self.cur_token_is(&TokenKind::Ellipsis) {
+            self.parse_spread_expression()
+        } else {
+            self.parse_expression(LOWEST)
+        }
+    }
+
+    /// Reports a spread anywhere else, but parses its value to carry on.
+    fn parse_misplaced_spread(&mut self) -> Option<Expression> {
+        self.errors
+            .push("spread operator is not allowed here".into());
+        self.parse_spread_expression()
+    }
+
+    fn parse_spread_expression(&mut self) -> Option<Expression> {
+        self.next_token();
+
+        let value = self.parse_expression(LOWEST)?;
+
+        Some(Expression::Spread(SpreadExpression::new(value)))
+    }
+
     fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
         let operator = self.cur_token.1.to_owned();
         let precendence = self.cur_precendence();
@@ -285,17 +561,113 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Desugars `x |> f(a)` into `f(x, a)` and `x |> f` into `f(x)`.
+    fn parse_pipe_expression(&mut self, left: Expression) -> Option<Expression> {
+        let precendence = self.cur_precendence();
+
+        self.next_token();
+
+        match self.parse_expression(precendence) {
+            Some(Expression::Call(mut call)) => {
+                call.arguments.insert(0, left);
+                Some(Expression::Call(call))
+            }
+            Some(callee) => Some(Expression::Call(CallExpression::new(callee, vec![left]))),
+            None => {
+                self.errors.push("Parse error |>".into());
+                None
+            }
+        }
+    }
+
+    /// Parses `x = value`. Assignment is right-associative, so `a = b = 1`
+    /// assigns to `b` first.
+    fn parse_assign_expression(&mut self, left: Expression) -> Option<Expression> {
+        let (name, field) = match Self::assign_target(&left) {
+            Some(target) => target,
+            None => {
+                self.errors
+                    .push(format!("invalid assignment target: {}", left));
+                return None;
+            }
+        };
+
+        self.next_token();
+
+        match self.parse_expression(ASSIGN - 1) {
+            Some(value) => Some(Expression::Assign(match field {
+                Some(field) => AssignExpression::field(name, field, value),
+                None => AssignExpression::new(name, value),
+            })),
+            None => {
+                self.errors.push("Parse error =".into());
+                None
+            }
+        }
+    }
+
+    /// Splits an assignment target into the variable assigned and, for
+    /// `name.field`, the field.
+    fn assign_target(left: &Expression) -> Option<(Identifier, Option<String>)> {
+        match left {
+            Expression::Identifier(name) => Some((name.to_owned(), None)),
+            Expression::Index(index) if index.member && !index.optional => {
+                match (&*index.left, &*index.index) {
+                    (Expression::Identifier(name), Expression::StringLiteral(field)) => {
+                        Some((name.to_owned(), Some(field.value.to_owned())))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
     fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+        let is_quote = matches!(function, Expression::Identifier(ref f) if f.value == "quote");
+        if is_quote && self.peek_token_is(&TokenKind::Lbrace) {
+            return self.parse_quoted_block(function);
+        }
+
         Some(Expression::Call(CallExpression::new(
             function,
             self.parse_expression_list(TokenKind::Rparen),
         )))
     }
 
+    /// Parses `quote({ ... })`, where the braces hold statements rather than
+    /// a hash literal. A hash literal can still be quoted in parentheses.
+    fn parse_quoted_block(&mut self, function: Expression) -> Option<Expression> {
+        self.next_token();
+        let block = self.parse_block_statement()?;
+        let mut arguments = vec![Expression::Block(block)];
+
+        while self.peek_token_is(&TokenKind::Comma) {
+            self.next_token();
+            self.next_token();
+            arguments.push(self.parse_expression(LOWEST)?);
+        }
+
+        if !self.expect_peek(TokenKind::Rparen) {
+            return None;
+        }
+
+        Some(Expression::Call(CallExpression::new(function, arguments)))
+    }
+
     fn parse_index_expression(&mut self, left: Expression) -> Option<Expression> {
         self.next_token();
 
-        let index = self.parse_expression(LOWEST).unwrap();
+        if self.cur_token_is(&TokenKind::Colon) {
+            return self.parse_slice_expression(left, None);
+        }
+
+        let index = self.parse_expression(LOWEST)?;
+
+        if self.peek_token_is(&TokenKind::Colon) {
+            self.next_token();
+            return self.parse_slice_expression(left, Some(index));
+        }
 
         if !self.expect_peek(TokenKind::Rbracket) {
             return None;
@@ -304,15 +676,65 @@ impl<'a> Parser<'a> {
         Some(Expression::Index(IndexExpression::new(left, index)))
     }
 
+    fn parse_member_expression(&mut self, left: Expression) -> Option<Expression> {
+        let optional = self.cur_token_is(&TokenKind::QuestionDot);
+
+        if !self.expect_peek(TokenKind::Ident) {
+            return None;
+        }
+
+        Some(Expression::Index(IndexExpression::member(
+            left,
+            self.cur_token.1.to_owned(),
+            optional,
+        )))
+    }
+
+    fn parse_optional_index_expression(&mut self, left: Expression) -> Option<Expression> {
+        self.next_token();
+
+        let index = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(TokenKind::Rbracket) {
+            return None;
+        }
+
+        Some(Expression::Index(IndexExpression::optional(left, index)))
+    }
+
+    fn parse_slice_expression(
+        &mut self,
+        left: Expression,
+        start: Option<Expression>,
+    ) -> Option<Expression> {
+        let end = if self.peek_token_is(&TokenKind::Rbracket) {
+            None
+        } else {
+            self.next_token();
+            Some(self.parse_expression(LOWEST)?)
+        };
+
+        if !self.expect_peek(TokenKind::Rbracket) {
+            return None;
+        }
+
+        Some(Expression::Slice(SliceExpression::new(left, start, end)))
+    }
+
     fn parse_grouped_expression(&mut self) -> Option<Expression> {
         self.next_token();
 
         let exp = self.parse_expression(LOWEST);
 
         if !self.expect_peek(TokenKind::Rparen) {
-            None
-        } else {
-            exp
+            return None;
+        }
+        // Parentheses end a `?.` chain, so they are kept for those.
+        match exp {
+            Some(exp) if exp.is_optional_chain() => {
+                Some(Expression::Group(GroupedExpression::new(exp)))
+            }
+            exp => exp,
         }
     }
 
@@ -354,14 +776,88 @@ impl<'a> Parser<'a> {
         Some(Expression::If(exp))
     }
 
+    fn parse_match_expression(&mut self) -> Option<Expression> {
+        if !self.expect_peek(TokenKind::Lparen) {
+            return None;
+        }
+
+        self.next_token();
+
+        let subject = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(TokenKind::Rparen) {
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::Lbrace) {
+            return None;
+        }
+
+        let mut arms: Vec<MatchArm> = vec![];
+
+        while !self.peek_token_is(&TokenKind::Rbrace) {
+            if !self.expect_peek(TokenKind::Ident) {
+                return None;
+            }
+
+            let pattern = if self.cur_token.1 == "_" {
+                Pattern::Wildcard
+            } else {
+                let name = Identifier::new(self.cur_token.1.to_owned());
+                let bindings = if self.peek_token_is(&TokenKind::Lparen) {
+                    self.next_token();
+                    self.parse_function_parameters()
+                } else {
+                    vec![]
+                };
+                Pattern::Variant(name, bindings)
+            };
+
+            if !self.expect_peek(TokenKind::FatArrow) {
+                return None;
+            }
+
+            // As in `if`, a block body needs no trailing comma before the next arm.
+            if self.peek_token_is(&TokenKind::Lbrace) {
+                self.next_token();
+                arms.push(MatchArm::new(pattern, self.parse_block_statement()?));
+
+                if self.peek_token_is(&TokenKind::Comma) {
+                    self.next_token();
+                }
+            } else {
+                self.next_token();
+                let value = self.parse_expression(LOWEST)?;
+                let body =
+                    BlockStatement::new(vec![Statement::Expression(ExpressionStatement::new(value))]);
+                arms.push(MatchArm::new(pattern, body));
+
+                if !self.peek_token_is(&TokenKind::Rbrace) && !self.expect_peek(TokenKind::Comma) {
+                    return None;
+                }
+            }
+        }
+
+        self.next_token();
+
+        Some(Expression::Match(MatchExpression::new(subject, arms)))
+    }
+
     fn parse_block_statement(&mut self) -> Option<BlockStatement> {
         let mut block = BlockStatement { statements: vec![] };
 
         self.next_token();
 
         while !self.cur_token_is(&TokenKind::Rbrace) && !self.cur_token_is(&TokenKind::Eof) {
-            if let Some(stmt) = self.parse_statement() {
-                block.statements.push(stmt);
+            match self.parse_statement() {
+                Some(Statement::Import(_)) => self
+                    .errors
+                    .push("import is only allowed at the top level".into()),
+                Some(Statement::Export(_)) => self
+                    .errors
+                    .push("export is only allowed at the top level".into()),
+                Some(stmt) => block.statements.push(stmt),
+                None => {}
             }
             self.next_token();
         }
@@ -369,7 +865,48 @@ impl<'a> Parser<'a> {
         Some(block)
     }
 
+    fn parse_import_statement(&mut self) -> Option<Statement> {
+        if !self.expect_peek(TokenKind::String) {
+            return None;
+        }
+
+        let path = self.cur_token.1.to_owned();
+
+        if !self.expect_peek(TokenKind::As) || !self.expect_peek(TokenKind::Ident) {
+            return None;
+        }
+
+        let name = Identifier::new(self.cur_token.1.to_owned());
+
+        if self.peek_token_is(&TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Import(ImportStatement::new(path, name)))
+    }
+
+    fn parse_export_statement(&mut self) -> Option<Statement> {
+        self.next_token();
+
+        match self.parse_statement()? {
+            stmt @ (Statement::Let(_) | Statement::Struct(_) | Statement::Enum(_)) => {
+                Some(Statement::Export(ExportStatement::new(stmt)))
+            }
+            stmt => {
+                self.errors.push(format!(
+                    "expected a let, const, struct or enum after export, got {}",
+                    stmt
+                ));
+                None
+            }
+        }
+    }
+
     fn parse_return_statement(&mut self) -> Option<Statement> {
+        if !self.return_allowed {
+            self.errors
+                .push("return outside of function in a module".into());
+        }
         self.next_token();
 
         if let Some(return_value) = self.parse_expression(LOWEST) {
@@ -382,7 +919,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_let_statement(&mut self) -> Option<Statement> {
+    fn parse_let_statement(&mut self, is_const: bool) -> Option<Statement> {
         if !self.expect_peek(TokenKind::Ident) {
             return None;
         }
@@ -404,10 +941,108 @@ impl<'a> Parser<'a> {
                 func.name = name.value.to_owned();
             }
 
-            Some(Statement::Let(LetStatement::new(name, value.to_owned())))
-        } else {
-            None
+            let value = value.to_owned();
+            Some(Statement::Let(if is_const {
+                LetStatement::constant(name, value)
+            } else {
+                LetStatement::new(name, value)
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn parse_struct_statement(&mut self) -> Option<Statement> {
+        if !self.expect_peek(TokenKind::Ident) {
+            return None;
+        }
+
+        let name = Identifier::new(self.cur_token.1.to_owned());
+
+        if !self.expect_peek(TokenKind::Lbrace) {
+            return None;
+        }
+
+        let mut fields: Vec<Identifier> = vec![];
+
+        while !self.peek_token_is(&TokenKind::Rbrace) {
+            if !self.expect_peek(TokenKind::Ident) {
+                return None;
+            }
+
+            let field = Identifier::new(self.cur_token.1.to_owned());
+
+            if fields.contains(&field) {
+                self.errors.push(format!(
+                    "duplicate field {} in struct {}",
+                    field, name
+                ));
+                return None;
+            }
+
+            fields.push(field);
+
+            if !self.peek_token_is(&TokenKind::Rbrace) && !self.expect_peek(TokenKind::Comma) {
+                return None;
+            }
+        }
+
+        self.next_token();
+
+        if self.peek_token_is(&TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Struct(StructStatement::new(name, fields)))
+    }
+
+    fn parse_enum_statement(&mut self) -> Option<Statement> {
+        if !self.expect_peek(TokenKind::Ident) {
+            return None;
+        }
+
+        let name = Identifier::new(self.cur_token.1.to_owned());
+
+        if !self.expect_peek(TokenKind::Lbrace) {
+            return None;
+        }
+
+        let mut variants: Vec<EnumVariant> = vec![];
+
+        while !self.peek_token_is(&TokenKind::Rbrace) {
+            if !self.expect_peek(TokenKind::Ident) {
+                return None;
+            }
+
+            let variant = Identifier::new(self.cur_token.1.to_owned());
+
+            if variants.iter().any(|v| v.name == variant) {
+                self.errors
+                    .push(format!("duplicate variant {} in enum {}", variant, name));
+                return None;
+            }
+
+            let fields = if self.peek_token_is(&TokenKind::Lparen) {
+                self.next_token();
+                self.parse_function_parameters()
+            } else {
+                vec![]
+            };
+
+            variants.push(EnumVariant::new(variant, fields));
+
+            if !self.peek_token_is(&TokenKind::Rbrace) && !self.expect_peek(TokenKind::Comma) {
+                return None;
+            }
+        }
+
+        self.next_token();
+
+        if self.peek_token_is(&TokenKind::Semicolon) {
+            self.next_token();
         }
+
+        Some(Statement::Enum(EnumStatement::new(name, variants)))
     }
 
     fn cur_token_is(&self, kind: &TokenKind) -> bool {
@@ -446,8 +1081,11 @@ impl<'a> Parser<'a> {
             TokenKind::Lbracket => self.parse_array_literal(),
             TokenKind::Lbrace => self.parse_hash_literal(),
             TokenKind::If => self.parse_if_expression(),
+            TokenKind::Match => self.parse_match_expression(),
             TokenKind::True | TokenKind::False => self.parse_boolean(),
             TokenKind::Bang | TokenKind::Minus => self.parse_prefix_expression(),
+            TokenKind::Ellipsis => self.parse_misplaced_spread(),
+            TokenKind::Yield => self.parse_yield_expression(),
             _ => None,
         }
     }
@@ -461,9 +1099,17 @@ impl<'a> Parser<'a> {
             | TokenKind::Eq
             | TokenKind::NotEq
             | TokenKind::Lt
-            | TokenKind::Gt => self.parse_infix_expression(left),
+            | TokenKind::Gt
+            | TokenKind::In
+            | TokenKind::DotDot
+            | TokenKind::DotDotEq
+            | TokenKind::NullCoalesce => self.parse_infix_expression(left),
             TokenKind::Lparen => self.parse_call_expression(left),
             TokenKind::Lbracket => self.parse_index_expression(left),
+            TokenKind::Dot | TokenKind::QuestionDot => self.parse_member_expression(left),
+            TokenKind::QuestionBracket => self.parse_optional_index_expression(left),
+            TokenKind::Pipe => self.parse_pipe_expression(left),
+            TokenKind::Assign => self.parse_assign_expression(left),
             _ => None,
         }
     }
@@ -478,16 +1124,25 @@ impl<'a> Parser<'a> {
 
     fn get_precedence(&self, token: &Token) -> i8 {
         match token.0 {
+            TokenKind::Assign => ASSIGN,
+            TokenKind::Pipe => PIPE,
+            TokenKind::NullCoalesce => COALESCE,
             TokenKind::Eq => EQUALS,
             TokenKind::NotEq => EQUALS,
             TokenKind::Lt => LESSGREATER,
             TokenKind::Gt => LESSGREATER,
+            TokenKind::In => LESSGREATER,
+            TokenKind::DotDot => RANGE,
+            TokenKind::DotDotEq => RANGE,
             TokenKind::Plus => SUM,
             TokenKind::Minus => SUM,
             TokenKind::Slash => PRODUCT,
             TokenKind::Asterisk => PRODUCT,
             TokenKind::Lparen => CALL,
             TokenKind::Lbracket => INDEX,
+            TokenKind::Dot => INDEX,
+            TokenKind::QuestionDot => INDEX,
+            TokenKind::QuestionBracket => INDEX,
             _ => LOWEST,
         }
     }
@@ -497,10 +1152,12 @@ impl<'a> Parser<'a> {
 mod tests {
     use crate::{
         ast::{
-            ArrayLiteral, BlockStatement, BooleanExpression, CallExpression, Expression,
-            ExpressionStatement, FunctionLiteral, HashLiteral, HashMember, Identifier,
-            IfExpression, IndexExpression, InfixExpression, IntegerLiteral, LetStatement,
-            MacroLiteral, PrefixExpression, Program, ReturnStatement, Statement, StringLiteral,
+            ArrayLiteral, BlockStatement, BooleanExpression, CallExpression, EnumStatement,
+            EnumVariant, Expression, ExpressionStatement, FunctionLiteral, HashLiteral, HashMember,
+            Identifier, IfExpression, ImportStatement, IndexExpression, InfixExpression,
+            IntegerLiteral, LetStatement, MacroLiteral, MatchArm, MatchExpression, Pattern,
+            PrefixExpression, Program, ReturnStatement, SliceExpression, Statement, StringLiteral,
+            StructStatement,
         },
         lexer::Lexer,
     };
@@ -548,6 +1205,117 @@ let foobar = y;
         );
     }
 
+    #[test]
+    fn test_quoted_blocks() {
+        let tests = [
+            ("quote({ let x = 1; x }, [x])", "quote({ let x = 1x }, [x])"),
+            ("quote({})", "quote({  })"),
+            (r#"quote(({"a": 1}))"#, "quote({a: 1})"),
+            ("unquote({ 1: 2 })", "unquote({1: 2})"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            let program = parser.parse_program();
+
+            assert_eq!(parser.errors, Vec::<String>::new(), "{}", input);
+            assert_eq!(program.to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_const_statements() {
+        let input = "const x = 5; const f = fn() { x };";
+        let mut lexer = Lexer::new(input);
+        let mut parser = Parser::new(&mut lexer);
+
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors.len(), 0);
+        assert_eq!(
+            program.statements[0],
+            Statement::Let(LetStatement::constant(
+                Identifier::new("x".into()),
+                Expression::IntegerLiteral(IntegerLiteral::new(5))
+            ))
+        );
+        assert_eq!(program.statements[1].to_string(), "const f = fn f() x");
+    }
+
+    #[test]
+    fn test_import_export_statements() {
+        let input = "
+import \"lib/strings.mk\" as strings;
+export let trim = fn(s) { s };
+export const limit = 10;
+export fn shout(s) { s }
+export struct Point { x, y }
+export enum Shape { Circle(r), Dot }
+";
+        let mut lexer = Lexer::new(input);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors, Vec::<String>::new());
+        assert_eq!(
+            program.statements[0],
+            Statement::Import(ImportStatement::new(
+                "lib/strings.mk".into(),
+                Identifier::new("strings".into())
+            ))
+        );
+        assert_eq!(
+            program.statements[0].to_string(),
+            "import \"lib/strings.mk\" as strings"
+        );
+
+        let exports: Vec<_> = program.statements[1..]
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Export(export) => export.names(),
+                _ => panic!("not an export: {}", stmt),
+            })
+            .collect();
+        assert_eq!(
+            exports,
+            vec![
+                vec!["trim"],
+                vec!["limit"],
+                vec!["shout"],
+                vec!["Point"],
+                vec!["Circle", "Dot"]
+            ]
+        );
+        assert_eq!(program.statements[2].to_string(), "export const limit = 10");
+
+        let tests = [
+            (
+                "import strings",
+                "expected next token to be String, got Ident instead",
+            ),
+            (
+                "export 1 + 2",
+                "expected a let, const, struct or enum after export, got (1 + 2)",
+            ),
+            (
+                "fn() { import \"a.mk\" as a }",
+                "import is only allowed at the top level",
+            ),
+            (
+                "if (true) { export let x = 1 }",
+                "export is only allowed at the top level",
+            ),
+        ];
+
+        for (input, error) in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            parser.parse_program();
+            assert_eq!(parser.errors, vec![error.to_string()], "{}", input);
+        }
+    }
+
     #[test]
     fn test_return_statements() {
         let input = "
@@ -613,6 +1381,43 @@ return y;
         );
     }
 
+    #[test]
+    fn test_integer_literal_errors() {
+        let tests = [
+            (
+                "let x = 0b2;",
+                "line 1, column 9: invalid digit 2 in integer literal 0b2",
+            ),
+            (
+                "-0xFFFFFFFFFFFFFFFFF",
+                "line 1, column 2: integer literal 0xFFFFFFFFFFFFFFFFF is out of range",
+            ),
+            (
+                "1 - 9223372036854775808",
+                "line 1, column 5: integer literal 9223372036854775808 is out of range",
+            ),
+            (
+                "let x = 1__000;",
+                "line 1, column 9: misplaced _ in integer literal 1__000",
+            ),
+        ];
+
+        for (input, error) in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            parser.parse_program();
+
+            assert_eq!(parser.errors[0], *error);
+        }
+
+        let mut lexer = Lexer::new("-9223372036854775808 * 1");
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors.len(), 0);
+        assert_eq!(program.to_string(), "(-9223372036854775808 * 1)");
+    }
+
     #[test]
     fn test_prefix_expression() {
         let tests = [("!5", "!", 5), ("-15", "-", 15)];
@@ -711,6 +1516,38 @@ return y;
                 "add(a * b[2], b[1], 2 * [1, 2][1])",
                 "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))",
             ),
+            ("a * b[1:2] * c", "((a * (b[1:2])) * c)"),
+            ("a[-1]", "(a[(-1)])"),
+            ("a[:b + 1]", "(a[:(b + 1)])"),
+            ("a[1:][:2]", "((a[1:])[:2])"),
+            ("a.b.c", "((a.b).c)"),
+            ("-a.b", "(-(a.b))"),
+            ("a.b(1) * c", "((a.b)(1) * c)"),
+            ("a.b[0].c()", "(((a.b)[0]).c)()"),
+            ("xs |> f", "f(xs)"),
+            ("xs |> filter(p) |> map(f)", "map(filter(xs, p), f)"),
+            ("a + b |> f(c)", "f((a + b), c)"),
+            ("a == b |> f", "f((a == b))"),
+            ("xs |> a.b(1)", "(a.b)(xs, 1)"),
+            ("a?.b?[c].d", "(((a?.b)?[c]).d)"),
+            ("(a?.b).c", "(((a?.b)).c)"),
+            ("(a.b).c", "((a.b).c)"),
+            ("a ?? b ?? c", "((a ?? b) ?? c)"),
+            ("a == b ?? c", "((a == b) ?? c)"),
+            ("a ?? b |> f", "f((a ?? b))"),
+            ("a?.b ?? -1", "((a?.b) ?? (-1))"),
+            ("[...a, ...b + c, 3]", "[...a, ...(b + c), 3]"),
+            ("f(...args, 1)", "f(...args, 1)"),
+            (r#"{...a, "k": 1, ...b.c}"#, "{...a, k: 1, ...(b.c)}"),
+            ("0..n + 1", "(0 .. (n + 1))"),
+            ("-1..=a * 2", "((-1) ..= (a * 2))"),
+            ("x in 0..5 == true", "((x in (0 .. 5)) == true)"),
+            ("a..b[0]", "(a .. (b[0]))"),
+            ("[...0..n]", "[...(0 .. n)]"),
+            ("x = y + 1", "(x = (y + 1))"),
+            ("a = b = c ?? d", "(a = (b = (c ?? d)))"),
+            ("f(x = 1)", "f((x = 1))"),
+            ("p.x = p.y + 1", "(p.x = ((p.y) + 1))"),
         ];
 
         for (input, output) in tests.iter() {
@@ -722,6 +1559,48 @@ return y;
         }
     }
 
+    #[test]
+    fn test_assign_expression_errors() {
+        let tests = ["a.b.c = 1", "a?.b = 1", "a[b] = 1", "1 = 2", "f() = 3"];
+
+        for input in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            parser.parse_program();
+
+            assert!(
+                parser.errors[0].starts_with("invalid assignment target"),
+                "{}",
+                parser.errors[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_spread_errors() {
+        let tests = [
+            "...a",
+            "let b = ...a;",
+            "f(...a + ...b)",
+            r#"{"k": ...a}"#,
+            "[...a for a in b]",
+            "a[...b]",
+        ];
+
+        for input in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            parser.parse_program();
+
+            assert_eq!(
+                parser.errors,
+                vec!["spread operator is not allowed here"],
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_if_expression() {
         let input = "if (x < y) { x } else { y }";
@@ -794,7 +1673,8 @@ return y;
                                     })),
                                 })
                             })]
-                        }
+                        },
+                        is_generator: false,
                     })
                 })]
             }
@@ -907,6 +1787,268 @@ return y;
         );
     }
 
+    #[test]
+    fn test_parsing_member_expressions() {
+        let input = "book.title";
+
+        let mut lexer = Lexer::new(input);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors.len(), 0);
+        assert_eq!(
+            program,
+            Program {
+                statements: vec![Statement::Expression(ExpressionStatement::new(
+                    Expression::Index(IndexExpression::member(
+                        Expression::Identifier(Identifier::new("book".into())),
+                        "title".into(),
+                        false,
+                    ))
+                ))]
+            }
+        );
+
+        let mut lexer = Lexer::new("book.(title + 1");
+        let mut parser = Parser::new(&mut lexer);
+        parser.parse_program();
+
+        assert_eq!(
+            parser.errors[0],
+            "expected next token to be Ident, got Lparen instead"
+        );
+    }
+
+    #[test]
+    fn test_struct_statements() {
+        let input = "struct Point { x, y }; struct Empty {}";
+
+        let mut lexer = Lexer::new(input);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors.len(), 0);
+        assert_eq!(
+            program.statements,
+            vec![
+                Statement::Struct(StructStatement::new(
+                    Identifier::new("Point".into()),
+                    vec![Identifier::new("x".into()), Identifier::new("y".into())],
+                )),
+                Statement::Struct(StructStatement::new(Identifier::new("Empty".into()), vec![])),
+            ]
+        );
+        assert_eq!(program.statements[0].to_string(), "struct Point { x, y }");
+
+        let mut lexer = Lexer::new("struct Point { x, x }");
+        let mut parser = Parser::new(&mut lexer);
+        parser.parse_program();
+
+        assert_eq!(parser.errors[0], "duplicate field x in struct Point");
+    }
+
+    #[test]
+    fn test_comprehension_expressions() {
+        let tests = [
+            ("[x * 2 for x in xs]", "[(x * 2) for x in xs]"),
+            ("[x for x in xs if x > 1]", "[x for x in xs if (x > 1)]"),
+            (
+                "[i + x for i, x in f(xs) if !i]",
+                "[(i + x) for i, x in f(xs) if (!i)]",
+            ),
+            ("{k: v * v for k, v in h}", "{k: (v * v) for k, v in h}"),
+            (
+                "[[y for y in x] for x in xs |> g]",
+                "[[y for y in x] for x in g(xs)]",
+            ),
+        ];
+
+        for (input, output) in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            let program = parser.parse_program();
+
+            assert_eq!(parser.errors.len(), 0, "{:?}", parser.errors);
+            assert_eq!(format!("{}", program), output.to_string());
+        }
+
+        let mut lexer = Lexer::new("[a for a, b, c in xs]");
+        let mut parser = Parser::new(&mut lexer);
+        parser.parse_program();
+
+        assert_eq!(
+            parser.errors[0],
+            "comprehension takes one or two bindings, got 3"
+        );
+    }
+
+    #[test]
+    fn test_generator_literals() {
+        let tests = [
+            (
+                "fn* gen() { yield 1; yield; }",
+                "let gen = fn* gen() yield 1yield null",
+            ),
+            (
+                "let g = fn*(x) { let y = yield x; }",
+                "let g = fn* g(x) let y = yield x",
+            ),
+            (
+                "fn*() { if (x) { yield 1 } else { [yield i for i in xs] } }",
+                "fn* () if x yield 1 else [yield i for i in xs]",
+            ),
+            ("fn add(a, b) { a + b }", "let add = fn add(a, b) (a + b)"),
+        ];
+
+        for (input, output) in tests.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            let program = parser.parse_program();
+
+            assert_eq!(parser.errors.len(), 0, "{:?}", parser.errors);
+            assert_eq!(format!("{}", program), output.to_string());
+        }
+
+        let errors = [
+            ("yield 1", "yield outside of generator function"),
+            (
+                "fn*() { fn() { yield 1 } }",
+                "yield outside of generator function",
+            ),
+            (
+                "fn*() { f(yield 1) }",
+                "yield is not allowed in this position",
+            ),
+            (
+                "fn*() { [x for x in yield xs] }",
+                "yield is not allowed in this position",
+            ),
+        ];
+
+        for (input, error) in errors.iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            parser.parse_program();
+
+            assert_eq!(parser.errors[0], error.to_string());
+        }
+    }
+
+    #[test]
+    fn test_enum_statements() {
+        let input = "enum Shape { Circle(r), Rect(w, h), Empty }";
+
+        let mut lexer = Lexer::new(input);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse_program();
+
+        let ident = |name: &str| Identifier::new(name.into());
+
+        assert_eq!(parser.errors.len(), 0);
+        assert_eq!(
+            program.statements,
+            vec![Statement::Enum(EnumStatement::new(
+                ident("Shape"),
+                vec![
+                    EnumVariant::new(ident("Circle"), vec![ident("r")]),
+                    EnumVariant::new(ident("Rect"), vec![ident("w"), ident("h")]),
+                    EnumVariant::new(ident("Empty"), vec![]),
+                ],
+            ))]
+        );
+        assert_eq!(program.to_string(), input);
+
+        let mut lexer = Lexer::new("enum E { A, A(x) }");
+        let mut parser = Parser::new(&mut lexer);
+        parser.parse_program();
+
+        assert_eq!(parser.errors[0], "duplicate variant A in enum E");
+    }
+
+    #[test]
+    fn test_match_expressions() {
+        let input = "match (shape) { Circle(r) => r, Empty => { 0 } _ => 1 }";
+
+        let mut lexer = Lexer::new(input);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse_program();
+
+        let ident = |name: &str| Identifier::new(name.into());
+        let block = |exp: Expression| {
+            BlockStatement::new(vec![Statement::Expression(ExpressionStatement::new(exp))])
+        };
+
+        assert_eq!(parser.errors.len(), 0);
+        assert_eq!(
+            program.statements,
+            vec![Statement::Expression(ExpressionStatement::new(
+                Expression::Match(MatchExpression::new(
+                    Expression::Identifier(ident("shape")),
+                    vec![
+                        MatchArm::new(
+                            Pattern::Variant(ident("Circle"), vec![ident("r")]),
+                            block(Expression::Identifier(ident("r"))),
+                        ),
+                        MatchArm::new(
+                            Pattern::Variant(ident("Empty"), vec![]),
+                            block(Expression::IntegerLiteral(IntegerLiteral::new(0))),
+                        ),
+                        MatchArm::new(
+                            Pattern::Wildcard,
+                            block(Expression::IntegerLiteral(IntegerLiteral::new(1))),
+                        ),
+                    ],
+                ))
+            ))]
+        );
+        assert_eq!(
+            program.to_string(),
+            "match shape { Circle(r) => r, Empty => 0, _ => 1 }"
+        );
+    }
+
+    #[test]
+    fn test_parsing_slice_expressions() {
+        let one = || Expression::IntegerLiteral(IntegerLiteral::new(1));
+        let three = || Expression::IntegerLiteral(IntegerLiteral::new(3));
+        let array = || Expression::Identifier(Identifier::new("arr".into()));
+
+        let tests = [
+            ("arr[1:3]", SliceExpression::new(array(), Some(one()), Some(three()))),
+            ("arr[:3]", SliceExpression::new(array(), None, Some(three()))),
+            ("arr[1:]", SliceExpression::new(array(), Some(one()), None)),
+            ("arr[:]", SliceExpression::new(array(), None, None)),
+        ];
+
+        for (input, expected) in tests.into_iter() {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            let program = parser.parse_program();
+
+            assert_eq!(parser.errors.len(), 0);
+            assert_eq!(
+                program,
+                Program {
+                    statements: vec![Statement::Expression(ExpressionStatement::new(
+                        Expression::Slice(expected)
+                    ))]
+                }
+            );
+        }
+
+        for input in ["arr[1:)]", "arr[)]", "arr[:)]"] {
+            let mut lexer = Lexer::new(input);
+            let mut parser = Parser::new(&mut lexer);
+            parser.parse_program();
+
+            assert_eq!(
+                parser.errors[0], "no prefix parse function for Rparen found",
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_parsing_hash_literals_string_keys() {
         let input = r#"{"one": 1, "two": 2, "three": 3}"#;
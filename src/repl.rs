@@ -5,8 +5,14 @@ use std::{
 };
 
 use crate::{
-    compiler::Compiler, enviroment::Enviroment, evaluator::Evaluator, lexer::Lexer, parser::Parser,
-    vm::Vm, Engine, makro::MacroExpension,
+    compiler::Compiler,
+    enviroment::Enviroment,
+    evaluator::Evaluator,
+    lexer::Lexer,
+    makro::MacroExpension,
+    parser::Parser,
+    vm::{Vm, VmConfig},
+    Engine,
 };
 
 const MONKEY_FACE: &str = r#"            __,__
@@ -24,11 +30,12 @@ const MONKEY_FACE: &str = r#"            __,__
 
 pub struct Repl<'a> {
     engine: &'a Engine,
+    config: VmConfig,
 }
 
 impl<'a> Repl<'a> {
-    pub fn new(engine: &'a Engine) -> Self {
-        Self { engine }
+    pub fn new(engine: &'a Engine, config: VmConfig) -> Self {
+        Self { engine, config }
     }
 
     fn prompt(&self) {
@@ -36,15 +43,51 @@ impl<'a> Repl<'a> {
         std::io::stdout().flush().unwrap();
     }
 
-    fn print_errors(&self, errors: Vec<String>) {
+    fn print_errors(&self, stage: &str, errors: Vec<String>) {
         println!("{}", MONKEY_FACE);
         println!("Woops! We ran into some monkey business here!");
-        println!("  parser errors:");
+        println!("  {} errors:", stage);
         for error in errors.iter() {
             println!("\t{}", error);
         }
     }
 
+    /// Handles `:expand <code>` and `:trace <code>`, which print `code` after
+    /// macro expansion instead of running it. `:trace` first prints each
+    /// expansion step. Returns whether `line` was one of them.
+    fn run_command(&self, macros: &MacroExpension, line: &str) -> bool {
+        let (trace, code) = if let Some(code) = line.strip_prefix(":expand ") {
+            (false, code)
+        } else if let Some(code) = line.strip_prefix(":trace ") {
+            (true, code)
+        } else {
+            return false;
+        };
+
+        let mut lexer = Lexer::new(code);
+        let mut parser = Parser::new(&mut lexer);
+        let mut program = parser.parse_program();
+        if !parser.errors.is_empty() {
+            self.print_errors("parser", parser.errors);
+            return true;
+        }
+
+        let (steps, errors) = macros.expand_traced(&mut program);
+        if trace {
+            for step in steps.iter() {
+                println!("{}", step);
+            }
+        }
+        if !errors.is_empty() {
+            self.print_errors("macro", errors);
+            return true;
+        }
+        for stmt in program.statements.iter() {
+            println!("{}", stmt);
+        }
+        true
+    }
+
     pub fn start(&self) {
         match self.engine {
             Engine::Vm => self.start_with_vm(),
@@ -53,23 +96,40 @@ impl<'a> Repl<'a> {
     }
 
     fn start_with_vm(&self) {
+        // Holds the macros defined so far. Everything else lives in the vm.
+        let macros = MacroExpension::new(Rc::new(RefCell::new(Enviroment::default())));
         let mut compiler = Compiler::new();
-        let mut vm = Vm::new(compiler.bytecode());
+        let mut vm = Vm::new(compiler.bytecode(), self.config);
         self.prompt();
         let stdin = io::stdin();
         for line in stdin.lock().lines() {
-            let mut lexer = Lexer::new(&line.unwrap());
+            let line = line.unwrap();
+            if self.run_command(&macros, &line) {
+                self.prompt();
+                continue;
+            }
+            let mut lexer = Lexer::new(&line);
             let mut parser = Parser::new(&mut lexer);
-            let program = parser.parse_program();
+            let mut program = parser.parse_program();
 
             if !parser.errors.is_empty() {
-                self.print_errors(parser.errors);
+                self.print_errors("parser", parser.errors);
                 self.prompt();
                 continue;
             }
 
+            if let Err(errors) = macros.expand(&mut program) {
+                self.print_errors("macro", errors);
+                self.prompt();
+                continue;
+            }
             compiler = Compiler::from(compiler);
             compiler.compile(&program);
+            if !compiler.errors.is_empty() {
+                self.print_errors("compiler", std::mem::take(&mut compiler.errors));
+                self.prompt();
+                continue;
+            }
             let code = compiler.bytecode();
             vm = Vm::from(code, vm);
             vm.run();
@@ -84,24 +144,32 @@ impl<'a> Repl<'a> {
     fn start_with_eval(&self) {
         self.prompt();
         let env = Rc::new(RefCell::new(Enviroment::default()));
+        let macros = MacroExpension::new(Rc::clone(&env));
+        // Kept across lines, so each module is only loaded once.
+        let mut evaluator = Evaluator::new(Rc::clone(&env));
         let stdin = io::stdin();
         for line in stdin.lock().lines() {
-            let mut lexer = Lexer::new(&line.unwrap());
+            let line = line.unwrap();
+            if self.run_command(&macros, &line) {
+                self.prompt();
+                continue;
+            }
+            let mut lexer = Lexer::new(&line);
             let mut parser = Parser::new(&mut lexer);
             let mut program = parser.parse_program();
 
             if !parser.errors.is_empty() {
-                self.print_errors(parser.errors);
+                self.print_errors("parser", parser.errors);
                 self.prompt();
                 continue;
             }
 
-            let makro = MacroExpension::new(Rc::clone(&env));
-            makro.define_macros(&mut program);
-            makro.expand_macros(&mut program);
-
+            if let Err(errors) = macros.expand(&mut program) {
+                self.print_errors("macro", errors);
+                self.prompt();
+                continue;
+            }
 
-            let mut evaluator = Evaluator::new(Rc::clone(&env));
             let result = evaluator.eval(&mut program);
             println!("{}", result.inspect());
 
@@ -3,6 +3,9 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc};
 #[derive(Debug, PartialEq, Clone)]
 pub enum SymbolScope {
     Global,
+    /// A global slot defined inside a block at the top level. Each run of the
+    /// block starts a new binding, so closures capture it like a local.
+    BlockGlobal,
     Local,
     Builtin,
     Free,
@@ -14,6 +17,15 @@ pub struct Symbol {
     pub name: String,
     pub scope: SymbolScope,
     pub index: u16,
+    pub is_const: bool,
+}
+
+/// The names declared in a block, along with the symbols they shadowed, so
+/// leaving the block can put those back.
+#[derive(Debug, Default, PartialEq, Clone)]
+struct BlockScope {
+    num_definitions: u16,
+    declared: Vec<(String, Option<Rc<Symbol>>)>,
 }
 
 #[derive(Debug, Default, PartialEq, Clone)]
@@ -21,7 +33,11 @@ pub struct SymbolTable {
     pub outer: Option<Rc<RefCell<SymbolTable>>>,
     store: HashMap<String, Rc<Symbol>>,
     pub num_definitions: u16,
+    /// The most definitions in use at once. Blocks hand their slots back when
+    /// they end, so this is how many locals a function needs.
+    pub max_definitions: u16,
     pub free_symbols: Vec<Rc<Symbol>>,
+    blocks: Vec<BlockScope>,
 }
 
 impl SymbolTable {
@@ -32,21 +48,78 @@ impl SymbolTable {
         }
     }
 
+    /// Defines `name` in the innermost scope. Defining a name again in the
+    /// same scope rebinds it, keeping its slot.
     pub fn define(&mut self, name: &str) -> Rc<Symbol> {
+        self.define_symbol(name, false)
+    }
+
+    /// Defines `name` as a constant, which may not be assigned to. It is up to
+    /// the caller to reject redefining an existing name this way.
+    pub fn define_const(&mut self, name: &str) -> Rc<Symbol> {
+        self.define_symbol(name, true)
+    }
+
+    fn define_symbol(&mut self, name: &str, is_const: bool) -> Rc<Symbol> {
+        if let Some(symbol) = self.defined_in_scope(name) {
+            return symbol;
+        }
+
         let scope = match self.outer {
             Some(_) => SymbolScope::Local,
+            None if !self.blocks.is_empty() => SymbolScope::BlockGlobal,
             None => SymbolScope::Global,
         };
-        self.store.insert(
-            name.into(),
-            Rc::new(Symbol {
-                name: name.into(),
-                scope,
-                index: self.num_definitions,
-            }),
-        );
+        let symbol = Rc::new(Symbol {
+            name: name.into(),
+            scope,
+            index: self.num_definitions,
+            is_const,
+        });
+        let shadowed = self.store.insert(name.into(), Rc::clone(&symbol));
+        if let Some(block) = self.blocks.last_mut() {
+            block.declared.push((name.into(), shadowed));
+        }
         self.num_definitions += 1;
-        self.resolve(name).unwrap()
+        self.max_definitions = self.max_definitions.max(self.num_definitions);
+        symbol
+    }
+
+    /// Looks up `name` if it was defined in the innermost scope, rather than
+    /// in an enclosing block or function.
+    pub fn defined_in_scope(&self, name: &str) -> Option<Rc<Symbol>> {
+        let symbol = self.store.get(name)?;
+        let in_scope = match self.blocks.last() {
+            Some(block) => block.declared.iter().any(|(declared, _)| declared == name),
+            None => matches!(symbol.scope, SymbolScope::Global | SymbolScope::Local),
+        };
+        in_scope.then(|| Rc::clone(symbol))
+    }
+
+    /// Opens a block scope. Names defined until the matching `leave_block`
+    /// shadow those outside it.
+    pub fn enter_block(&mut self) {
+        self.blocks.push(BlockScope {
+            num_definitions: self.num_definitions,
+            declared: vec![],
+        });
+    }
+
+    /// Closes the innermost block scope, hiding the names defined in it again.
+    pub fn leave_block(&mut self) {
+        let block = self.blocks.pop().expect("no block to leave");
+        for (name, shadowed) in block.declared.into_iter().rev() {
+            match shadowed {
+                Some(symbol) => self.store.insert(name, symbol),
+                None => self.store.remove(&name),
+            };
+        }
+        // Local slots are reused by later blocks, as closures capture the
+        // locals they need. Functions read globals by slot, so those are
+        // kept.
+        if self.outer.is_some() {
+            self.num_definitions = block.num_definitions;
+        }
     }
 
     pub fn define_free(&mut self, original: Rc<Symbol>) -> Rc<Symbol> {
@@ -54,6 +127,7 @@ impl SymbolTable {
             name: original.name.to_owned(),
             index: self.free_symbols.len() as u16,
             scope: SymbolScope::Free,
+            is_const: original.is_const,
         });
 
         self.store.insert(original.name.to_owned(), Rc::clone(&symbol));
@@ -68,6 +142,7 @@ impl SymbolTable {
             name: name.into(),
             index: 0,
             scope: SymbolScope::Function,
+            is_const: false,
         });
 
         self.store.insert(name.into(), symbol);
@@ -80,6 +155,7 @@ impl SymbolTable {
             name: name.into(),
             scope: SymbolScope::Builtin,
             index,
+            is_const: false,
         };
         self.store.insert(name.into(), Rc::new(symbol));
         self.resolve(name).unwrap()
@@ -122,6 +198,7 @@ mod tests {
                     name: "a".into(),
                     scope: SymbolScope::Global,
                     index: 0,
+                    is_const: false,
                 },
             ),
             (
@@ -130,6 +207,7 @@ mod tests {
                     name: "b".into(),
                     scope: SymbolScope::Global,
                     index: 1,
+                    is_const: false,
                 },
             ),
             (
@@ -138,6 +216,7 @@ mod tests {
                     name: "c".into(),
                     scope: SymbolScope::Local,
                     index: 0,
+                    is_const: false,
                 },
             ),
             (
@@ -146,6 +225,7 @@ mod tests {
                     name: "d".into(),
                     scope: SymbolScope::Local,
                     index: 1,
+                    is_const: false,
                 },
             ),
             (
@@ -154,6 +234,7 @@ mod tests {
                     name: "e".into(),
                     scope: SymbolScope::Local,
                     index: 0,
+                    is_const: false,
                 },
             ),
             (
@@ -162,6 +243,7 @@ mod tests {
                     name: "f".into(),
                     scope: SymbolScope::Local,
                     index: 1,
+                    is_const: false,
                 },
             ),
         ]);
@@ -217,11 +299,13 @@ mod tests {
                 name: "a".into(),
                 scope: SymbolScope::Global,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "b".into(),
                 scope: SymbolScope::Global,
                 index: 1,
+                is_const: false,
             },
         ];
 
@@ -245,21 +329,25 @@ mod tests {
                 name: "a".into(),
                 scope: SymbolScope::Global,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "b".into(),
                 scope: SymbolScope::Global,
                 index: 1,
+                is_const: false,
             },
             Symbol {
                 name: "c".into(),
                 scope: SymbolScope::Local,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "d".into(),
                 scope: SymbolScope::Local,
                 index: 1,
+                is_const: false,
             },
         ];
 
@@ -292,21 +380,25 @@ mod tests {
                         name: "a".into(),
                         scope: SymbolScope::Global,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "b".into(),
                         scope: SymbolScope::Global,
                         index: 1,
+                        is_const: false,
                     },
                     Symbol {
                         name: "c".into(),
                         scope: SymbolScope::Local,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "d".into(),
                         scope: SymbolScope::Local,
                         index: 1,
+                        is_const: false,
                     },
                 ],
             ),
@@ -317,31 +409,37 @@ mod tests {
                         name: "a".into(),
                         scope: SymbolScope::Global,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "b".into(),
                         scope: SymbolScope::Global,
                         index: 1,
+                        is_const: false,
                     },
                     Symbol {
                         name: "c".into(),
                         scope: SymbolScope::Free,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "d".into(),
                         scope: SymbolScope::Free,
                         index: 1,
+                        is_const: false,
                     },
                     Symbol {
                         name: "e".into(),
                         scope: SymbolScope::Local,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "f".into(),
                         scope: SymbolScope::Local,
                         index: 1,
+                        is_const: false,
                     },
                 ],
             ),
@@ -367,21 +465,25 @@ mod tests {
                 name: "a".into(),
                 scope: SymbolScope::Builtin,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "c".into(),
                 scope: SymbolScope::Builtin,
                 index: 1,
+                is_const: false,
             },
             Symbol {
                 name: "e".into(),
                 scope: SymbolScope::Builtin,
                 index: 2,
+                is_const: false,
             },
             Symbol {
                 name: "f".into(),
                 scope: SymbolScope::Builtin,
                 index: 3,
+                is_const: false,
             },
         ];
 
@@ -420,21 +522,25 @@ mod tests {
                         name: "a".into(),
                         scope: SymbolScope::Global,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "b".into(),
                         scope: SymbolScope::Global,
                         index: 1,
+                        is_const: false,
                     },
                     Symbol {
                         name: "c".into(),
                         scope: SymbolScope::Local,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "d".into(),
                         scope: SymbolScope::Local,
                         index: 1,
+                        is_const: false,
                     },
                 ],
                 vec![],
@@ -446,31 +552,37 @@ mod tests {
                         name: "a".into(),
                         scope: SymbolScope::Global,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "b".into(),
                         scope: SymbolScope::Global,
                         index: 1,
+                        is_const: false,
                     },
                     Symbol {
                         scope: SymbolScope::Free,
                         name: "c".into(),
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "d".into(),
                         scope: SymbolScope::Free,
                         index: 1,
+                        is_const: false,
                     },
                     Symbol {
                         name: "e".into(),
                         scope: SymbolScope::Local,
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "f".into(),
                         scope: SymbolScope::Local,
                         index: 1,
+                        is_const: false,
                     },
                 ],
                 vec![
@@ -478,11 +590,13 @@ mod tests {
                         scope: SymbolScope::Local,
                         name: "c".into(),
                         index: 0,
+                        is_const: false,
                     },
                     Symbol {
                         name: "d".into(),
                         scope: SymbolScope::Local,
                         index: 1,
+                        is_const: false,
                     },
                 ],
             ),
@@ -518,21 +632,25 @@ mod tests {
                 name: "a".into(),
                 scope: SymbolScope::Global,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "c".into(),
                 scope: SymbolScope::Free,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "e".into(),
                 scope: SymbolScope::Local,
                 index: 0,
+                is_const: false,
             },
             Symbol {
                 name: "f".into(),
                 scope: SymbolScope::Local,
                 index: 1,
+                is_const: false,
             },
         ];
 
@@ -552,7 +670,12 @@ mod tests {
         let global = Rc::new(RefCell::new(SymbolTable::default()));
         global.borrow_mut().define_function_name("a");
 
-        let expected = Symbol { name: "a".into(), scope: SymbolScope::Function, index: 0};
+        let expected = Symbol {
+            name: "a".into(),
+            scope: SymbolScope::Function,
+            index: 0,
+            is_const: false,
+        };
 
         assert_eq!(*global.borrow_mut().resolve("a").unwrap(), expected);
     }
@@ -563,8 +686,66 @@ mod tests {
         global.borrow_mut().define_function_name("a");
         global.borrow_mut().define("a");
 
-        let expected = Symbol { name: "a".into(), scope: SymbolScope::Global, index: 0};
+        let expected = Symbol {
+            name: "a".into(),
+            scope: SymbolScope::Global,
+            index: 0,
+            is_const: false,
+        };
 
         assert_eq!(*global.borrow_mut().resolve("a").unwrap(), expected);
     }
+
+    #[test]
+    fn test_block_scopes() {
+        let global = Rc::new(RefCell::new(SymbolTable::default()));
+        let mut local = SymbolTable::new_enclosed(Rc::clone(&global));
+        local.define("a");
+
+        local.enter_block();
+        let inner = local.define("a");
+        local.define("b");
+        assert_eq!(inner.index, 1);
+        assert_eq!(local.resolve("a").unwrap().index, 1);
+        assert_eq!(local.define("b").index, 2);
+        local.leave_block();
+
+        assert_eq!(local.resolve("a").unwrap().index, 0);
+        assert!(local.resolve("b").is_none());
+
+        local.enter_block();
+        assert_eq!(local.define("c").index, 1);
+        local.leave_block();
+
+        assert_eq!(local.num_definitions, 1);
+        assert_eq!(local.max_definitions, 3);
+        assert_eq!(local.define("a").index, 0);
+
+        let mut global = global.borrow_mut();
+        global.enter_block();
+        assert_eq!(global.define("a").scope, SymbolScope::BlockGlobal);
+        global.leave_block();
+        assert!(global.resolve("a").is_none());
+        assert_eq!(global.define("b").index, 1);
+    }
+
+    #[test]
+    fn test_define_const() {
+        let global = Rc::new(RefCell::new(SymbolTable::default()));
+        global.borrow_mut().define_const("a");
+        global.borrow_mut().define("b");
+
+        let mut local = SymbolTable::new_enclosed(Rc::clone(&global));
+        local.define_const("c");
+        let mut nested = SymbolTable::new_enclosed(Rc::new(RefCell::new(local)));
+
+        assert!(nested.resolve("a").unwrap().is_const);
+        assert!(!nested.resolve("b").unwrap().is_const);
+        let free = nested.resolve("c").unwrap();
+        assert_eq!(free.scope, SymbolScope::Free);
+        assert!(free.is_const);
+
+        assert!(global.borrow().defined_in_scope("a").unwrap().is_const);
+        assert!(nested.defined_in_scope("a").is_none());
+    }
 }
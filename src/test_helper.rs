@@ -1,3 +1,5 @@
+use std::{fs, path::PathBuf};
+
 use crate::{
     ast::Program,
     code::Instructions,
@@ -18,6 +20,20 @@ pub enum ExpectedValue<'a> {
     Null,
 }
 
+/// Writes `files` into a fresh directory for `test` and returns its path.
+pub fn write_modules(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("monkey-modules-{}", std::process::id()))
+        .join(test);
+    let _ = fs::remove_dir_all(&dir);
+    for (name, source) in files.iter() {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
+
 pub fn parse(input: &str) -> Program {
     let mut lexer = Lexer::new(input);
     let mut parser = Parser::new(&mut lexer);
@@ -69,7 +85,7 @@ pub fn test_hash_object(object: &Object, expected: &[(i64, i64)]) {
 
         for (key, value) in expected.iter() {
             let key_obj = Integer::new(*key);
-            test_integer_object(hash.value.get(&key_obj.hash_key()).unwrap(), *value);
+            test_integer_object(hash.get(&key_obj.hash_key()).unwrap(), *value);
         }
     } else {
         panic!("not a hash")
@@ -18,10 +18,19 @@ pub enum TokenKind {
     Gt,
     Eq,
     NotEq,
+    FatArrow,
+    Pipe,
+    NullCoalesce,
 
     Comma,
     Semicolon,
     Colon,
+    Dot,
+    QuestionDot,
+    QuestionBracket,
+    Ellipsis,
+    DotDot,
+    DotDotEq,
 
     Lparen,
     Rparen,
@@ -33,12 +42,22 @@ pub enum TokenKind {
     // Keyword
     Function,
     Let,
+    Const,
     True,
     False,
     If,
     Else,
     Return,
     Macro,
+    Struct,
+    Enum,
+    Match,
+    For,
+    In,
+    Yield,
+    Import,
+    Export,
+    As,
 }
 
 #[derive(Debug, PartialEq)]
@@ -49,12 +68,22 @@ impl Token {
         match value {
             "fn" => Token(TokenKind::Function, value.into()),
             "let" => Token(TokenKind::Let, value.into()),
+            "const" => Token(TokenKind::Const, value.into()),
             "true" => Token(TokenKind::True, value.into()),
             "false" => Token(TokenKind::False, value.into()),
             "if" => Token(TokenKind::If, value.into()),
             "else" => Token(TokenKind::Else, value.into()),
             "return" => Token(TokenKind::Return, value.into()),
             "macro" => Token(TokenKind::Macro, value.into()),
+            "struct" => Token(TokenKind::Struct, value.into()),
+            "enum" => Token(TokenKind::Enum, value.into()),
+            "match" => Token(TokenKind::Match, value.into()),
+            "for" => Token(TokenKind::For, value.into()),
+            "in" => Token(TokenKind::In, value.into()),
+            "yield" => Token(TokenKind::Yield, value.into()),
+            "import" => Token(TokenKind::Import, value.into()),
+            "export" => Token(TokenKind::Export, value.into()),
+            "as" => Token(TokenKind::As, value.into()),
             _ => Token(TokenKind::Ident, value.into()),
         }
     }
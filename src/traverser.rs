@@ -1,8 +1,10 @@
 use crate::ast::{
-    ArrayLiteral, BlockStatement, BooleanExpression, CallExpression, Expression,
-    ExpressionStatement, FunctionLiteral, HashLiteral, Identifier, IfExpression, IndexExpression,
-    InfixExpression, IntegerLiteral, LetStatement, MacroLiteral, NullLiteral, PrefixExpression,
-    Program, ReturnStatement, Statement, StringLiteral,
+    ArrayLiteral, AssignExpression, BlockStatement, BooleanExpression, CallExpression,
+    ComprehensionClause, EnumStatement, Expression, ExpressionStatement, FunctionLiteral,
+    GroupedExpression, HashComprehension, HashLiteral, Identifier, IfExpression, IndexExpression,
+    InfixExpression, IntegerLiteral, LetStatement, ListComprehension, MacroLiteral,
+    MatchExpression, NullLiteral, Pattern, PrefixExpression, Program, ReturnStatement,
+    SliceExpression, SpreadExpression, Statement, StringLiteral, StructStatement, YieldExpression,
 };
 
 pub trait Visitor {
@@ -10,6 +12,8 @@ pub trait Visitor {
     fn visit_mut_expression_statement(&self, _node: &mut ExpressionStatement) {}
     fn visit_mut_let_statement(&self, _node: &mut LetStatement) {}
     fn visit_mut_return_statement(&self, _node: &mut ReturnStatement) {}
+    fn visit_mut_struct_statement(&self, _node: &mut StructStatement) {}
+    fn visit_mut_enum_statement(&self, _node: &mut EnumStatement) {}
     fn visit_mut_null_literal(&self, _node: &mut NullLiteral) {}
     fn visit_mut_integer_literal(&self, _node: &mut IntegerLiteral) {}
     fn visit_mut_string_literal(&self, _node: &mut StringLiteral) {}
@@ -19,12 +23,26 @@ pub trait Visitor {
     fn visit_mut_macro_literal(&self, _node: &mut MacroLiteral) {}
     fn visit_mut_identifer(&self, _node: &mut Identifier) {}
     fn visit_mut_expression(&self, _node: &mut Expression) {}
+    /// Whether to go on into the children of `node`, so a visitor can leave
+    /// parts of the tree alone.
+    fn descend_into_expression(&self, _node: &Expression) -> bool {
+        true
+    }
     fn visit_mut_boolean_expression(&self, _node: &mut BooleanExpression) {}
     fn visit_mut_if_expression(&self, _node: &mut IfExpression) {}
+    fn visit_mut_match_expression(&self, _node: &mut MatchExpression) {}
     fn visit_mut_prefix_expression(&self, _node: &mut PrefixExpression) {}
     fn visit_mut_infix_expression(&self, _node: &mut InfixExpression) {}
     fn visit_mut_index_expression(&self, _node: &mut IndexExpression) {}
+    fn visit_mut_slice_expression(&self, _node: &mut SliceExpression) {}
+    fn visit_mut_spread_expression(&self, _node: &mut SpreadExpression) {}
+    fn visit_mut_list_comprehension(&self, _node: &mut ListComprehension) {}
+    fn visit_mut_hash_comprehension(&self, _node: &mut HashComprehension) {}
+    fn visit_mut_yield_expression(&self, _node: &mut YieldExpression) {}
+    fn visit_mut_assign_expression(&self, _node: &mut AssignExpression) {}
+    fn visit_mut_grouped_expression(&self, _node: &mut GroupedExpression) {}
     fn visit_mut_call_expression(&self, _node: &mut CallExpression) {}
+    fn visit_mut_block_statement(&self, _node: &mut BlockStatement) {}
 }
 
 pub trait Traverable {
@@ -47,6 +65,10 @@ impl Traverable for Statement {
             Statement::Let(n) => n.visit_mut(visitor),
             Statement::Return(n) => n.visit_mut(visitor),
             Statement::Expression(n) => n.visit_mut(visitor),
+            Statement::Struct(n) => n.visit_mut(visitor),
+            Statement::Enum(n) => n.visit_mut(visitor),
+            Statement::Export(n) => n.statement.visit_mut(visitor),
+            Statement::Import(_) => {}
         }
     }
 }
@@ -61,6 +83,9 @@ impl Traverable for ExpressionStatement {
 impl Traverable for Expression {
     fn visit_mut(&mut self, visitor: &impl Visitor) {
         visitor.visit_mut_expression(self);
+        if !visitor.descend_into_expression(self) {
+            return;
+        }
         match self {
             Expression::NullLiteral(n) => n.visit_mut(visitor),
             Expression::IntegerLiteral(n) => n.visit_mut(visitor),
@@ -74,8 +99,17 @@ impl Traverable for Expression {
             Expression::Prefix(n) => n.visit_mut(visitor),
             Expression::Infix(n) => n.visit_mut(visitor),
             Expression::Index(n) => n.visit_mut(visitor),
+            Expression::Slice(n) => n.visit_mut(visitor),
             Expression::Call(n) => n.visit_mut(visitor),
             Expression::If(n) => n.visit_mut(visitor),
+            Expression::Match(n) => n.visit_mut(visitor),
+            Expression::Spread(n) => n.visit_mut(visitor),
+            Expression::ListComprehension(n) => n.visit_mut(visitor),
+            Expression::HashComprehension(n) => n.visit_mut(visitor),
+            Expression::Yield(n) => n.visit_mut(visitor),
+            Expression::Assign(n) => n.visit_mut(visitor),
+            Expression::Group(n) => n.visit_mut(visitor),
+            Expression::Block(n) => n.visit_mut(visitor),
         }
     }
 }
@@ -176,8 +210,107 @@ impl Traverable for ReturnStatement {
     }
 }
 
+impl Traverable for StructStatement {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_struct_statement(self);
+        self.name.visit_mut(visitor);
+        for field in self.fields.iter_mut() {
+            field.visit_mut(visitor);
+        }
+    }
+}
+
+impl Traverable for EnumStatement {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_enum_statement(self);
+        self.name.visit_mut(visitor);
+        for variant in self.variants.iter_mut() {
+            variant.name.visit_mut(visitor);
+            for field in variant.fields.iter_mut() {
+                field.visit_mut(visitor);
+            }
+        }
+    }
+}
+
+impl Traverable for SpreadExpression {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_spread_expression(self);
+        self.value.visit_mut(visitor);
+    }
+}
+
+impl Traverable for YieldExpression {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_yield_expression(self);
+        self.value.visit_mut(visitor);
+    }
+}
+
+impl Traverable for AssignExpression {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_assign_expression(self);
+        self.name.visit_mut(visitor);
+        self.value.visit_mut(visitor);
+    }
+}
+
+impl Traverable for GroupedExpression {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_grouped_expression(self);
+        self.value.visit_mut(visitor);
+    }
+}
+
+impl Traverable for ListComprehension {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_list_comprehension(self);
+        self.element.visit_mut(visitor);
+        self.clause.visit_mut(visitor);
+    }
+}
+
+impl Traverable for HashComprehension {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_hash_comprehension(self);
+        self.key.visit_mut(visitor);
+        self.value.visit_mut(visitor);
+        self.clause.visit_mut(visitor);
+    }
+}
+
+impl Traverable for ComprehensionClause {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        for binding in self.bindings.iter_mut() {
+            binding.visit_mut(visitor);
+        }
+        self.iterable.visit_mut(visitor);
+        if let Some(mut condition) = self.condition.take() {
+            condition.visit_mut(visitor);
+            self.condition = Some(condition);
+        }
+    }
+}
+
+impl Traverable for MatchExpression {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_match_expression(self);
+        self.subject.visit_mut(visitor);
+        for arm in self.arms.iter_mut() {
+            if let Pattern::Variant(ref mut name, ref mut bindings) = arm.pattern {
+                name.visit_mut(visitor);
+                for binding in bindings.iter_mut() {
+                    binding.visit_mut(visitor);
+                }
+            }
+            arm.body.visit_mut(visitor);
+        }
+    }
+}
+
 impl Traverable for BlockStatement {
     fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_block_statement(self);
         for stmt in self.statements.iter_mut() {
             stmt.visit_mut(visitor);
         }
@@ -217,6 +350,19 @@ impl Traverable for IndexExpression {
     }
 }
 
+impl Traverable for SliceExpression {
+    fn visit_mut(&mut self, visitor: &impl Visitor) {
+        visitor.visit_mut_slice_expression(self);
+        self.left.visit_mut(visitor);
+        if let Some(ref mut start) = self.start {
+            start.visit_mut(visitor);
+        }
+        if let Some(ref mut end) = self.end {
+            end.visit_mut(visitor);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
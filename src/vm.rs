@@ -1,22 +1,52 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    mem,
+    rc::Rc,
+};
 
 use crate::{
-    builtin::{Builtin, BUILTINS},
-    code::Opcode,
+    builtin::{Builtin, BUILTINS, TASK_BUILTINS},
+    code::{Instructions, Opcode},
     compiler::Bytecode,
     frame::Frame,
     object::{
-        Array, Boolean, BuiltinFunction, Closure, CompiledFunction, Hash, HashKeyable, Integer,
-        Null, Object, RuntimeError, Str,
+        Array, Boolean, BuiltinFunction, Cell, Channel, Closure, CompiledFunction, Generator,
+        GeneratorState, Hash, HashPair, Integer, Null, Object, Range, RuntimeError, Str, Struct,
+        StructDefinition, VariantDefinition,
     },
 };
 
-const STACK_SIZE: usize = 2048;
+pub const STACK_SIZE: usize = 2048;
 pub const GLOBAL_SIZE: usize = 65536;
-const MAX_FRAMES: usize = 1024;
+pub const MAX_FRAMES: usize = 1024;
+/// How many instructions a task runs before others get a turn.
+const TASK_QUANTUM: usize = 1000;
+const MAIN_TASK: usize = 0;
+
+/// Sizes the value stack, the call frame stack and the globals of a `Vm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    pub stack_size: usize,
+    pub max_frames: usize,
+    pub global_size: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            stack_size: STACK_SIZE,
+            max_frames: MAX_FRAMES,
+            global_size: GLOBAL_SIZE,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Vm {
+    config: VmConfig,
+
     true_object: Rc<Object>,
     false_object: Rc<Object>,
     null_object: Rc<Object>,
@@ -28,20 +58,37 @@ pub struct Vm {
 
     builtin: Builtin,
 
-    pub globals: Vec<Rc<Object>>,
+    /// The globals of the main program, followed by those of each module
+    /// by id once it has run.
+    globals: Vec<Vec<Rc<Object>>>,
+    /// The exports of each module that has run, by id.
+    exports: HashMap<usize, Rc<Object>>,
 
     frames: Vec<Rc<RefCell<Frame>>>,
     frame_index: usize,
+
+    /// The error that halted the program, if any.
+    error: Option<Rc<Object>>,
+
+    // The running task owns the stack and frames above; the others wait in
+    // `tasks` until the scheduler swaps them in.
+    task_id: usize,
+    waiting: Option<Waiting>,
+    tasks: VecDeque<Task>,
+    ticks: usize,
+    next_task_id: usize,
+    next_channel_id: usize,
 }
 
 impl Vm {
     pub fn from(bytecode: Bytecode, other: Vm) -> Self {
-        let mut vm = Self::new(bytecode);
+        let mut vm = Self::new(bytecode, other.config);
         vm.globals = other.globals;
+        vm.exports = other.exports;
         vm
     }
 
-    pub fn new(bytecode: Bytecode) -> Self {
+    pub fn new(bytecode: Bytecode, config: VmConfig) -> Self {
         let true_object = Rc::new(Object::Boolean(Boolean::new(true)));
         let false_object = Rc::new(Object::Boolean(Boolean::new(false)));
         let null_object = Rc::new(Object::Null(Null::default()));
@@ -49,24 +96,36 @@ impl Vm {
         let main_func = CompiledFunction::new(bytecode.instructions, 0, 0);
         let main_closure = Closure::new(main_func, vec![]);
         let main_frame = Rc::new(RefCell::new(Frame::new(main_closure, 0)));
-        let mut frames = Vec::with_capacity(MAX_FRAMES);
+        let mut frames = Vec::with_capacity(config.max_frames);
         frames.push(main_frame);
 
         Self {
+            config,
+
             true_object,
             false_object,
             null_object: Rc::clone(&null_object),
 
             constants: bytecode.constants,
-            stack: vec![Rc::clone(&null_object); STACK_SIZE],
+            stack: vec![Rc::clone(&null_object); config.stack_size],
             sp: 0,
 
             builtin: Builtin::default(),
 
-            globals: vec![Rc::clone(&null_object); GLOBAL_SIZE],
+            globals: vec![vec![Rc::clone(&null_object); config.global_size]],
+            exports: HashMap::new(),
 
             frames,
             frame_index: 1,
+
+            error: None,
+
+            task_id: MAIN_TASK,
+            waiting: None,
+            tasks: VecDeque::new(),
+            ticks: 0,
+            next_task_id: MAIN_TASK + 1,
+            next_channel_id: 0,
         }
     }
 
@@ -86,23 +145,26 @@ impl Vm {
     }
 
     pub fn run(&mut self) {
-        while self.current_frame().borrow().ip
-            < self.current_frame().borrow().instructions().len() as isize - 1
-        {
+        while self.has_instructions() {
             let frame = self.current_frame();
             frame.borrow_mut().ip += 1;
-            let op = frame
+            let mut op = frame
                 .borrow()
                 .instructions()
                 .read_op_at(frame.borrow().ip as usize);
+            // A `Wide` prefix doubles the operand widths of the next opcode.
+            let wide = op == Opcode::Wide;
+            if wide {
+                frame.borrow_mut().ip += 1;
+                op = frame
+                    .borrow()
+                    .instructions()
+                    .read_op_at(frame.borrow().ip as usize);
+            }
             match op {
                 Opcode::Constant => {
-                    let const_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 2;
-                    let constant = self.constants.get(const_index as usize).unwrap().to_owned();
+                    let const_index = frame.borrow_mut().read_operand(2, wide);
+                    let constant = self.constants.get(const_index).unwrap().to_owned();
                     self.push(constant.into());
                 }
                 Opcode::True => self.push(Rc::clone(&self.true_object)),
@@ -119,11 +181,7 @@ impl Vm {
                     self.pop();
                 }
                 Opcode::JumpNotTruth => {
-                    let pos = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 2;
+                    let pos = frame.borrow_mut().read_operand(4, wide);
 
                     let confition = self.pop();
 
@@ -132,128 +190,443 @@ impl Vm {
                     }
                 }
                 Opcode::Jump => {
-                    let pos = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from((frame.borrow().ip + 1) as usize);
+                    let pos = frame.borrow_mut().read_operand(4, wide);
                     frame.borrow_mut().ip = pos as isize - 1;
                 }
+                Opcode::JumpIfNull => {
+                    let pos = frame.borrow_mut().read_operand(4, wide);
+
+                    // Unlike `JumpNotTruth` the operand stays on the stack,
+                    // since it is also the result when the jump is taken.
+                    if matches!(*self.stack[self.sp - 1], Object::Null(_)) {
+                        frame.borrow_mut().ip = pos as isize - 1;
+                    }
+                }
                 Opcode::Null => self.push(Rc::clone(&self.null_object)),
                 Opcode::GetGlobal => {
-                    let global_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from((frame.borrow().ip + 1) as usize);
-                    frame.borrow_mut().ip += 2;
-                    let var = Rc::clone(self.globals.get(global_index as usize).unwrap());
-                    self.push(var);
+                    let global_index = frame.borrow_mut().read_operand(2, wide);
+                    let module = frame.borrow().closure.func.module;
+                    let var = Rc::clone(self.globals[module].get(global_index).unwrap());
+                    // Block globals captured by a closure live in a shared cell.
+                    match *var {
+                        Object::Cell(ref cell) => self.push(cell.get()),
+                        _ => self.push(var),
+                    }
                 }
                 Opcode::SetGlobal => {
-                    let global_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 2;
-                    self.globals[global_index as usize] = self.pop();
+                    let global_index = frame.borrow_mut().read_operand(2, wide);
+                    let module = frame.borrow().closure.func.module;
+                    let value = self.pop();
+                    match self.globals[module].get_mut(global_index) {
+                        Some(global) => *global = value,
+                        None => self.halt("too many globals"),
+                    }
+                }
+                Opcode::AssignGlobal => {
+                    let global_index = frame.borrow_mut().read_operand(2, wide);
+                    let module = frame.borrow().closure.func.module;
+                    let value = self.pop();
+                    match self.globals[module].get_mut(global_index) {
+                        Some(global) => match **global {
+                            Object::Cell(ref cell) => cell.set(value),
+                            _ => *global = value,
+                        },
+                        None => self.halt("too many globals"),
+                    }
+                }
+                Opcode::CaptureGlobal => {
+                    let global_index = frame.borrow_mut().read_operand(2, wide);
+                    let module = frame.borrow().closure.func.module;
+                    let global = &mut self.globals[module][global_index];
+                    if !matches!(**global, Object::Cell(_)) {
+                        *global = Object::Cell(Cell::new(Rc::clone(global))).into();
+                    }
+                    let cell = Rc::clone(global);
+                    self.push(cell);
                 }
                 Opcode::Array => {
-                    let num_elements = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 2;
-
-                    let array = self.build_array(self.sp - num_elements as usize, self.sp);
-                    self.sp -= num_elements as usize;
+                    let num_elements = frame.borrow_mut().read_operand(2, wide);
+
+                    let array = self.build_array(self.sp - num_elements, self.sp);
+                    self.sp -= num_elements;
                     self.push(array);
                 }
                 Opcode::Hash => {
-                    let num_elements = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 2;
-
-                    let hash = self.build_hash(self.sp - num_elements as usize, self.sp);
-                    self.sp -= num_elements as usize;
+                    let num_elements = frame.borrow_mut().read_operand(2, wide);
+
+                    let hash = self.build_hash(self.sp - num_elements, self.sp);
+                    self.sp -= num_elements;
+                    self.push(hash);
+                }
+                Opcode::ConcatArrays => {
+                    let num_segments = frame.borrow_mut().read_operand(2, wide);
+
+                    let array = self.concat_arrays(self.sp - num_segments, self.sp);
+                    self.sp -= num_segments;
+                    self.push(array);
+                }
+                Opcode::MergeHashes => {
+                    let num_segments = frame.borrow_mut().read_operand(2, wide);
+
+                    let hash = self.merge_hashes(self.sp - num_segments, self.sp);
+                    self.sp -= num_segments;
                     self.push(hash);
                 }
+                Opcode::Range => {
+                    let inclusive = frame.borrow_mut().read_operand(1, wide);
+                    self.execute_range(inclusive == 1);
+                }
+                Opcode::In => self.execute_in_operator(),
+                Opcode::GetIter => self.execute_get_iter(),
+                Opcode::IterNext => {
+                    let pos = frame.borrow_mut().read_operand(4, wide);
+
+                    if !self.execute_iter_next() {
+                        frame.borrow_mut().ip = pos as isize - 1;
+                    }
+                }
+                Opcode::Unpack => {
+                    let num_elements = frame.borrow_mut().read_operand(1, wide);
+                    self.execute_unpack(num_elements);
+                }
+                Opcode::Collect => {
+                    let num_values = frame.borrow_mut().read_operand(1, wide);
+                    self.execute_collect(num_values);
+                }
                 Opcode::Index => self.execute_index_expression(),
+                Opcode::Member => self.execute_member_expression(),
+                Opcode::Slice => self.execute_slice_expression(),
                 Opcode::Call => {
-                    let num_args = frame
-                        .borrow()
-                        .instructions()
-                        .read_u8_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 1;
+                    let num_args = frame.borrow_mut().read_operand(1, wide);
                     self.execute_call(num_args);
                 }
+                Opcode::TailCall => {
+                    let num_args = frame.borrow_mut().read_operand(1, wide);
+                    self.execute_tail_call(num_args);
+                }
+                Opcode::CallSpread => {
+                    let args = self.pop();
+                    match *args {
+                        Object::Array(ref array) => {
+                            for arg in array.elements.iter() {
+                                self.push(Rc::clone(arg));
+                            }
+                            self.execute_call(array.elements.len());
+                        }
+                        _ => {
+                            // The callee is replaced by the error from `ConcatArrays`.
+                            self.pop();
+                            self.push(Rc::clone(&args));
+                        }
+                    }
+                }
                 Opcode::ReturnValue => {
                     let return_value = self.pop();
-                    let frame2 = self.pop_frame();
-                    self.sp = frame2.borrow().base_pointer - 1;
-                    self.push(return_value);
-                }
-                Opcode::Return => {
-                    let frame2 = self.pop_frame();
-                    self.sp = frame2.borrow().base_pointer - 1;
-                    self.push(Rc::clone(&self.null_object));
+                    self.return_from_frame(return_value);
                 }
+                Opcode::Return => self.return_from_frame(Rc::clone(&self.null_object)),
+                Opcode::Yield => self.execute_yield(),
                 Opcode::GetLocal => {
-                    let local_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u8_from((frame.borrow().ip + 1) as usize);
-                    frame.borrow_mut().ip += 1;
+                    let local_index = frame.borrow_mut().read_operand(1, wide);
                     let var = Rc::clone(
                         self.stack
-                            .get(frame.borrow().base_pointer + local_index as usize)
+                            .get(frame.borrow().base_pointer + local_index)
                             .unwrap(),
                     );
-                    self.push(var);
+                    // Locals captured by a closure live in a shared cell.
+                    match *var {
+                        Object::Cell(ref cell) => self.push(cell.get()),
+                        _ => self.push(var),
+                    }
                 }
                 Opcode::SetLocal => {
-                    let local_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u8_from((frame.borrow().ip + 1) as usize);
-                    frame.borrow_mut().ip += 1;
-                    self.stack[frame.borrow().base_pointer + local_index as usize] = self.pop();
+                    let local_index = frame.borrow_mut().read_operand(1, wide);
+                    self.stack[frame.borrow().base_pointer + local_index] = self.pop();
+                }
+                Opcode::AssignLocal => {
+                    let local_index = frame.borrow_mut().read_operand(1, wide);
+                    let slot = frame.borrow().base_pointer + local_index;
+                    let value = self.pop();
+                    if let Object::Cell(ref cell) = *self.stack[slot] {
+                        cell.set(value);
+                    } else {
+                        self.stack[slot] = value;
+                    }
+                }
+                Opcode::CaptureLocal => {
+                    let local_index = frame.borrow_mut().read_operand(1, wide);
+                    let slot = frame.borrow().base_pointer + local_index;
+                    if !matches!(*self.stack[slot], Object::Cell(_)) {
+                        let value = Rc::clone(&self.stack[slot]);
+                        self.stack[slot] = Object::Cell(Cell::new(value)).into();
+                    }
+                    self.push(Rc::clone(&self.stack[slot]));
                 }
                 Opcode::GetBuiltin => {
-                    let builtin_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u8_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 1;
-                    let name = BUILTINS[builtin_index as usize];
+                    let builtin_index = frame.borrow_mut().read_operand(1, wide);
+                    let name = BUILTINS[builtin_index];
                     self.push(Object::BuiltinFunction(BuiltinFunction::new(name.into())).into());
                 }
                 Opcode::Closure => {
-                    let const_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u16_from(frame.borrow().ip as usize + 1);
-                    let num_free = frame
-                        .borrow()
-                        .instructions()
-                        .read_u8_from(frame.borrow().ip as usize + 3);
-                    frame.borrow_mut().ip += 3;
-                    self.push_closure(const_index, num_free as u16);
+                    let const_index = frame.borrow_mut().read_operand(2, wide);
+                    let num_free = frame.borrow_mut().read_operand(1, wide);
+                    self.push_closure(const_index, num_free);
                 }
                 Opcode::GetFree => {
-                    let free_index = frame
-                        .borrow()
-                        .instructions()
-                        .read_u8_from(frame.borrow().ip as usize + 1);
-                    frame.borrow_mut().ip += 1;
-                    let current_closure = &frame.borrow().closure;
-                    self.push(Rc::clone(&current_closure.free[free_index as usize]));
+                    let free_index = frame.borrow_mut().read_operand(1, wide);
+                    let value = frame.borrow().closure.free_cell(free_index).get();
+                    self.push(value);
+                }
+                Opcode::SetFree => {
+                    let free_index = frame.borrow_mut().read_operand(1, wide);
+                    let value = self.pop();
+                    frame.borrow().closure.free_cell(free_index).set(value);
+                }
+                Opcode::CaptureFree => {
+                    let free_index = frame.borrow_mut().read_operand(1, wide);
+                    let cell = Rc::clone(&frame.borrow().closure.free[free_index]);
+                    self.push(cell);
+                }
+                Opcode::SetField => {
+                    let const_index = frame.borrow_mut().read_operand(2, wide);
+                    self.execute_set_field(const_index);
+                }
+                Opcode::TestTag => {
+                    let num_bindings = frame.borrow_mut().read_operand(1, wide);
+                    self.execute_test_tag(num_bindings);
+                }
+                Opcode::GetPayload => {
+                    let payload_index = frame.borrow_mut().read_operand(1, wide);
+                    let subject = Rc::clone(&self.stack[self.sp - 1]);
+                    if let Object::Variant(ref variant) = *subject {
+                        self.push(Rc::clone(&variant.values[payload_index]));
+                    } else {
+                        self.halt(&format!("payload of non-variant: {}", subject.kind()));
+                    }
                 }
+                Opcode::Import => {
+                    let const_index = frame.borrow_mut().read_operand(2, wide);
+                    self.execute_import(const_index);
+                }
+                Opcode::Export => {
+                    let module = frame.borrow().closure.func.module;
+                    let exports = Rc::clone(&self.stack[self.sp - 1]);
+                    self.exports.insert(module, exports);
+                }
+                Opcode::Wide => unreachable!(),
                 Opcode::CurrentClosure => {
                     let current_closure = frame.borrow().closure.to_owned();
                     self.push(Rc::new(Object::Closure(current_closure)));
                 }
             }
+
+            self.schedule();
+        }
+    }
+
+    /// Reports whether the current task has instructions left. Spawned tasks
+    /// are dropped once their bottom frame finishes, while finishing the main
+    /// task ends the program.
+    fn has_instructions(&mut self) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+        loop {
+            let frame = self.current_frame();
+            if frame.borrow().ip < frame.borrow().instructions().len() as isize - 1 {
+                return true;
+            }
+            if self.task_id == MAIN_TASK {
+                return false;
+            }
+            self.take_task();
+            self.run_next_task();
+        }
+    }
+
+    /// Switches tasks when the current one is blocked or has run for its
+    /// whole quantum.
+    fn schedule(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        self.ticks += 1;
+        if self.waiting.is_some() || self.ticks >= TASK_QUANTUM && !self.tasks.is_empty() {
+            let task = self.take_task();
+            self.tasks.push_back(task);
+            self.run_next_task();
+        }
+    }
+
+    fn take_task(&mut self) -> Task {
+        Task {
+            id: self.task_id,
+            stack: mem::take(&mut self.stack),
+            sp: self.sp,
+            frames: mem::take(&mut self.frames),
+            frame_index: self.frame_index,
+            waiting: self.waiting.take(),
+        }
+    }
+
+    /// Swaps in the first task that is not blocked. If every task is
+    /// blocked, the main task is woken with a deadlock error instead.
+    fn run_next_task(&mut self) {
+        let index = match self.tasks.iter().position(|t| t.waiting.is_none()) {
+            Some(index) => index,
+            None => self.break_deadlock(),
+        };
+        let task = self.tasks.remove(index).unwrap();
+
+        self.task_id = task.id;
+        self.stack = task.stack;
+        self.sp = task.sp;
+        self.frames = task.frames;
+        self.frame_index = task.frame_index;
+        self.waiting = task.waiting;
+        self.ticks = 0;
+    }
+
+    /// Fails the main task's channel operation with an error listing every
+    /// blocked task, and returns the main task's index in `tasks`.
+    fn break_deadlock(&mut self) -> usize {
+        let mut blocked: Vec<&Task> = self.tasks.iter().collect();
+        blocked.sort_by_key(|t| t.id);
+        let message = format!(
+            "deadlock: all tasks are blocked: {}",
+            blocked
+                .iter()
+                .map(|t| format!("task {} {}", t.id, t.waiting.as_ref().unwrap()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+
+        let index = self.tasks.iter().position(|t| t.id == MAIN_TASK).unwrap();
+        let main = &mut self.tasks[index];
+        if let Some(waiting) = main.waiting.take() {
+            waiting.cancel(MAIN_TASK);
+        }
+        main.push(Object::RuntimeError(RuntimeError::new(message)).into());
+        index
+    }
+
+    /// Unblocks a waiting task, giving `value` as the result of its `send` or
+    /// `recv` call.
+    fn wake_task(&mut self, id: usize, value: Rc<Object>) {
+        let task = self.tasks.iter_mut().find(|t| t.id == id).unwrap();
+        task.waiting = None;
+        task.push(value);
+    }
+
+    fn call_task_builtin(&mut self, name: &str, args: Vec<Rc<Object>>) {
+        let want = match name {
+            "channel" => 0,
+            "send" => 2,
+            _ => 1,
+        };
+        if args.len() != want {
+            return self.push(
+                Object::RuntimeError(RuntimeError::new(format!(
+                    "wrong number of arguments. got={}, want={}",
+                    args.len(),
+                    want
+                )))
+                .into(),
+            );
+        }
+
+        if name == "channel" {
+            let channel = Channel::new(self.next_channel_id);
+            self.next_channel_id += 1;
+            return self.push(Object::Channel(channel).into());
+        }
+        if name == "spawn" {
+            return self.spawn_task(&args[0]);
+        }
+
+        let channel = match *args[0] {
+            Object::Channel(ref channel) => channel.to_owned(),
+            _ => {
+                return self.push(
+                    Object::RuntimeError(RuntimeError::new(format!(
+                        "argument to `{}` must be CHANNEL, got {}",
+                        name,
+                        args[0].kind()
+                    )))
+                    .into(),
+                )
+            }
+        };
+        if name == "send" {
+            self.send(channel, Rc::clone(&args[1]));
+        } else {
+            self.receive(channel);
+        }
+    }
+
+    /// Queues a task that calls `callee` and ends when the call returns.
+    fn spawn_task(&mut self, callee: &Rc<Object>) {
+        match **callee {
+            Object::Closure(ref closure) if closure.func.num_parameters == 0 => {}
+            _ => {
+                return self.push(
+                    Object::RuntimeError(RuntimeError::new(format!(
+                        "argument to `spawn` must be a function without parameters, got {}",
+                        callee.kind()
+                    )))
+                    .into(),
+                )
+            }
+        }
+
+        let mut stack = vec![Rc::clone(&self.null_object); self.config.stack_size];
+        stack[0] = Rc::clone(callee);
+        let instructions: Instructions = Opcode::Call.make(vec![0]);
+        let start = Closure::new(CompiledFunction::new(instructions, 0, 0), vec![]);
+        self.tasks.push_back(Task {
+            id: self.next_task_id,
+            stack,
+            sp: 1,
+            frames: vec![Rc::new(RefCell::new(Frame::new(start, 0)))],
+            frame_index: 1,
+            waiting: None,
+        });
+        self.next_task_id += 1;
+
+        self.push(Rc::clone(&self.null_object));
+    }
+
+    /// Hands `value` to a waiting receiver, or blocks until one arrives.
+    fn send(&mut self, channel: Channel, value: Rc<Object>) {
+        let receiver = channel.state.borrow_mut().receivers.pop_front();
+        match receiver {
+            Some(receiver) => {
+                self.wake_task(receiver, value);
+                self.push(Rc::clone(&self.null_object));
+            }
+            None => {
+                channel
+                    .state
+                    .borrow_mut()
+                    .senders
+                    .push_back((self.task_id, value));
+                self.waiting = Some(Waiting::Send(channel));
+            }
+        }
+    }
+
+    /// Takes the value of a waiting sender, or blocks until one arrives.
+    fn receive(&mut self, channel: Channel) {
+        let sender = channel.state.borrow_mut().senders.pop_front();
+        match sender {
+            Some((sender, value)) => {
+                self.wake_task(sender, Rc::clone(&self.null_object));
+                self.push(value);
+            }
+            None => {
+                channel.state.borrow_mut().receivers.push_back(self.task_id);
+                self.waiting = Some(Waiting::Receive(channel));
+            }
         }
     }
 
@@ -358,83 +731,415 @@ impl Vm {
         }
     }
 
+    fn execute_member_expression(&mut self) {
+        let name = self.pop();
+        let left = self.pop();
+
+        if let Object::Str(ref name) = *name {
+            if let Some(method) = self.builtin.lookup_method(&left, &name.value) {
+                return self.push(method);
+            }
+        }
+
+        self.execute_index(&left, &name)
+    }
+
     fn execute_index_expression(&mut self) {
         let index = self.pop();
         let left = self.pop();
+        self.execute_index(&left, &index)
+    }
 
+    fn execute_index(&mut self, left: &Object, index: &Object) {
         match *left {
-            Object::Array(ref array) => self.execute_array_index(array, &index),
-            Object::Hash(ref hash) => self.execute_hash_index(hash, &index),
-            _ => panic!("index operator not supported: {}", left.kind()),
+            Object::Array(ref array) => self.execute_array_index(array, index),
+            Object::Str(ref string) => self.execute_string_index(string, index),
+            Object::Hash(ref hash) => self.execute_hash_index(hash, index),
+            Object::Struct(ref instance) => self.execute_struct_field(instance, index),
+            Object::Range(ref range) => self.execute_range_index(range, index),
+            _ => self.halt(&format!("index operator not supported: {}", left.kind())),
+        }
+    }
+
+    /// Replaces the struct on top of the stack with a copy whose field is
+    /// set to the value below it.
+    fn execute_set_field(&mut self, const_index: usize) {
+        let field = match self.constants[const_index] {
+            Object::Str(ref field) => field.value.to_owned(),
+            _ => panic!("field name is not a string"),
+        };
+
+        let target = self.pop();
+        let value = Rc::clone(&self.stack[self.sp - 1]);
+        match *target {
+            Object::Struct(ref instance) => match instance.with_field(&field, value) {
+                Some(updated) => self.push(Object::Struct(updated).into()),
+                None => self.error = Some(instance.unknown_field(&field).into()),
+            },
+            _ => self.halt(&format!(
+                "field assignment not supported: {}",
+                target.kind()
+            )),
         }
     }
 
+    /// Pops the variant a match arm names and tests whether the subject below
+    /// it was built from that variant.
+    fn execute_test_tag(&mut self, num_bindings: usize) {
+        let pattern = self.pop();
+        let definition = match *pattern {
+            Object::VariantDefinition(ref definition) => definition,
+            Object::Variant(ref variant) => &variant.definition,
+            _ => return self.halt(&format!("not an enum variant: {}", pattern.inspect())),
+        };
+
+        let subject = Rc::clone(&self.stack[self.sp - 1]);
+        let matched = match *subject {
+            Object::Variant(ref variant) if variant.tag() == definition.tag() => {
+                if variant.values.len() != num_bindings {
+                    return self.halt(&format!(
+                        "wrong number of bindings for {}: got={}, want={}",
+                        definition.name,
+                        num_bindings,
+                        variant.values.len()
+                    ));
+                }
+                true
+            }
+            _ => false,
+        };
+
+        self.push(self.native_bool_to_boolean_object(matched));
+    }
+
     fn execute_array_index(&mut self, left: &Array, index: &Object) {
         if let Object::Integer(integer) = index {
-            let index = integer.value;
-            if index < 0 || index > (left.elements.len() as i64 - 1) {
-                self.push(Rc::clone(&self.null_object))
-            } else {
-                self.push(Rc::clone(&left.elements[index as usize]))
+            match left.get(integer.value) {
+                Some(element) => self.push(element),
+                None => self.push(Rc::clone(&self.null_object)),
             }
         } else {
             self.push(Rc::clone(&self.null_object))
         }
     }
 
+    fn execute_range_index(&mut self, left: &Range, index: &Object) {
+        if let Object::Integer(integer) = index {
+            match left.get(integer.value) {
+                Some(value) => self.push(Object::Integer(Integer::new(value)).into()),
+                None => self.push(Rc::clone(&self.null_object)),
+            }
+        } else {
+            self.halt(&format!("index is not a integer: {}", index.kind()))
+        }
+    }
+
+    fn execute_range(&mut self, inclusive: bool) {
+        let end = self.pop();
+        let start = self.pop();
+        match (&*start, &*end) {
+            (Object::Integer(start), Object::Integer(end)) => {
+                self.push(Object::Range(Range::new(start.value, end.value, inclusive)).into())
+            }
+            _ => self.push(
+                Object::RuntimeError(RuntimeError::new(format!(
+                    "unsupported range bounds: {}..{}",
+                    start.kind(),
+                    end.kind()
+                )))
+                .into(),
+            ),
+        }
+    }
+
+    fn execute_in_operator(&mut self) {
+        let container = self.pop();
+        let value = self.pop();
+        match container.contains(&value) {
+            Some(found) => self.push(self.native_bool_to_boolean_object(found)),
+            None => self.push(
+                Object::RuntimeError(RuntimeError::new(format!(
+                    "in operator not supported: {}",
+                    container.kind()
+                )))
+                .into(),
+            ),
+        }
+    }
+
+    fn execute_string_index(&mut self, left: &Str, index: &Object) {
+        if let Object::Integer(integer) = index {
+            match left.get(integer.value) {
+                Some(ch) => self.push(Object::Str(ch).into()),
+                None => self.push(Rc::clone(&self.null_object)),
+            }
+        } else {
+            self.halt(&format!("index is not a integer: {}", index.kind()))
+        }
+    }
+
+    fn execute_slice_expression(&mut self) {
+        let end = self.pop();
+        let start = self.pop();
+        let left = self.pop();
+
+        let mut bounds = vec![];
+        for bound in [&start, &end] {
+            match **bound {
+                Object::Integer(ref integer) => bounds.push(Some(integer.value)),
+                Object::Null(_) => bounds.push(None),
+                _ => {
+                    return self.push(
+                        Object::RuntimeError(RuntimeError::new(format!(
+                            "slice index is not a integer: {}",
+                            bound.kind()
+                        )))
+                        .into(),
+                    )
+                }
+            }
+        }
+
+        match *left {
+            Object::Array(ref array) => {
+                self.push(Object::Array(array.slice(bounds[0], bounds[1])).into())
+            }
+            Object::Str(ref string) => {
+                self.push(Object::Str(string.slice(bounds[0], bounds[1])).into())
+            }
+            _ => self.halt(&format!("slice operator not supported: {}", left.kind())),
+        }
+    }
+
+    fn execute_struct_field(&mut self, instance: &Struct, field: &Object) {
+        if let Object::Str(ref field) = *field {
+            match instance.get(&field.value) {
+                Some(value) => self.push(value),
+                None => self.push(instance.unknown_field(&field.value).into()),
+            }
+        } else {
+            panic!("struct field must be STRING, got {}", field.kind())
+        }
+    }
+
     fn execute_hash_index(&mut self, left: &Hash, key: &Object) {
-        let hash_key = match *key {
-            Object::Str(ref o) => o.hash_key(),
-            Object::Integer(ref o) => o.hash_key(),
-            Object::Boolean(ref o) => o.hash_key(),
-            _ => return self.push(Rc::clone(&self.null_object)),
+        let hash_key = match key.hash_key() {
+            Some(hash_key) => hash_key,
+            None => return self.push(Rc::clone(&self.null_object)),
         };
 
-        if let Some(object) = left.value.get(&hash_key) {
+        if let Some(object) = left.get(&hash_key) {
             self.push(Rc::clone(object));
         } else {
             self.push(Rc::clone(&self.null_object))
         }
     }
 
-    fn execute_call(&mut self, num_args: u8) {
-        let callee = Rc::clone(&self.stack[self.sp - 1 - num_args as usize]);
+    fn execute_call(&mut self, num_args: usize) {
+        let callee = Rc::clone(&self.stack[self.sp - 1 - num_args]);
         match *callee {
             Object::Closure(ref closure) => self.call_closure(closure, num_args),
             Object::BuiltinFunction(ref func) => self.call_builtin(func, num_args),
+            Object::StructDefinition(ref definition) => self.call_struct(definition, num_args),
+            Object::VariantDefinition(ref definition) => self.call_variant(definition, num_args),
             _ => panic!("calling non-closure and non-built-in"),
         }
     }
 
-    fn call_closure(&mut self, closure: &Closure, num_args: u8) {
-        if num_args != closure.func.num_parameters {
+    /// Calls a closure in place of the current frame, reusing its base
+    /// pointer, since the frame would only return the result. Any other
+    /// callee is called as usual and its result returned by the next
+    /// instruction.
+    fn execute_tail_call(&mut self, num_args: usize) {
+        let callee_pos = self.sp - 1 - num_args;
+        let callee = Rc::clone(&self.stack[callee_pos]);
+        let closure = match *callee {
+            Object::Closure(ref closure)
+                if !closure.func.is_generator
+                    && closure.func.num_parameters as usize == num_args =>
+            {
+                closure
+            }
+            _ => return self.execute_call(num_args),
+        };
+
+        let base_pointer = self.current_frame().borrow().base_pointer;
+        if base_pointer + closure.func.num_locals as usize > self.stack.len() {
+            return self.halt("stack overflow");
+        }
+        for i in 0..=num_args {
+            self.stack[base_pointer - 1 + i] = Rc::clone(&self.stack[callee_pos + i]);
+        }
+        self.frames[self.frame_index - 1] =
+            Rc::new(RefCell::new(Frame::new(closure.to_owned(), base_pointer)));
+        self.sp = base_pointer + closure.func.num_locals as usize;
+    }
+
+    fn call_closure(&mut self, closure: &Closure, num_args: usize) {
+        if num_args != closure.func.num_parameters as usize {
             panic!(
                 "wrong number of arguments: want={}, got={}",
                 closure.func.num_parameters, num_args
             );
         }
-        let frame = Rc::new(RefCell::new(Frame::new(
-            closure.to_owned(),
-            self.sp - num_args as usize,
-        )));
+        if closure.func.is_generator {
+            // The call is parked before its first instruction, owning its
+            // arguments and the rest of its locals.
+            let mut stack = self.stack[self.sp - num_args..self.sp].to_vec();
+            stack.resize(
+                closure.func.num_locals as usize,
+                Rc::clone(&self.null_object),
+            );
+            self.sp -= num_args + 1;
+            self.push(
+                Object::Generator(Generator::new(GeneratorState::Frame {
+                    closure: closure.to_owned(),
+                    ip: -1,
+                    stack,
+                }))
+                .into(),
+            );
+            return;
+        }
+        let base_pointer = self.sp - num_args;
+        if !self.frame_fits(base_pointer, closure.func.num_locals as usize) {
+            return;
+        }
+        let frame = Rc::new(RefCell::new(Frame::new(closure.to_owned(), base_pointer)));
         self.push_frame(Rc::clone(&frame));
         self.sp = frame.borrow().base_pointer + closure.func.num_locals as usize;
     }
 
-    fn call_builtin(&mut self, func: &BuiltinFunction, num_args: u8) {
+    fn call_builtin(&mut self, func: &BuiltinFunction, num_args: usize) {
         let mut args = vec![];
-        let mut i = self.sp - num_args as usize;
+        let mut i = self.sp - num_args;
         while i < self.sp {
             args.push(Rc::clone(&self.stack[i]));
             i += 1;
         }
-        if let Some(result) = self.builtin.apply_function(&func.name, args) {
+        self.sp -= num_args + 1;
+        if func.name == "next" {
+            match self.builtin.next_generator(func, args) {
+                Ok(generator) => self.resume_generator(generator),
+                Err(error) => self.push(error),
+            }
+            return;
+        }
+        if TASK_BUILTINS.contains(&func.name.as_str()) {
+            let args = self.builtin.bind_receiver(func, args);
+            return self.call_task_builtin(&func.name, args);
+        }
+        if let Some(result) = self.builtin.call(func, args) {
             self.push(result);
         } else {
             self.push(Object::Null(Null::default()).into());
         }
     }
 
+    /// Pushes a suspended generator's frame back with its saved slots, in
+    /// place of the `next` call. Its result is pushed once it yields or
+    /// returns.
+    fn resume_generator(&mut self, generator: Generator) {
+        match generator.state.replace(GeneratorState::Running) {
+            GeneratorState::Frame { closure, ip, stack } => {
+                let base_pointer = self.sp;
+                if !self.frame_fits(base_pointer, stack.len()) {
+                    return;
+                }
+                for value in stack.into_iter() {
+                    self.push(value);
+                }
+                let mut frame = Frame::new(closure, base_pointer);
+                frame.ip = ip;
+                frame.generator = Some(generator);
+                self.push_frame(Rc::new(RefCell::new(frame)));
+            }
+            GeneratorState::Running => self.push(
+                Object::RuntimeError(RuntimeError::new("generator is already running".into()))
+                    .into(),
+            ),
+            GeneratorState::Done | GeneratorState::Continuation(_) => {
+                generator.state.replace(GeneratorState::Done);
+                self.push(Rc::clone(&self.null_object));
+            }
+        }
+    }
+
+    /// Parks the current generator frame along with its stack slots and
+    /// returns the yielded value from `next`.
+    fn execute_yield(&mut self) {
+        let value = self.pop();
+        // What the `yield` evaluates to once resumed.
+        self.push(Rc::clone(&self.null_object));
+
+        let frame = self.pop_frame();
+        let frame = frame.borrow();
+        let generator = frame
+            .generator
+            .as_ref()
+            .expect("yield outside of generator function");
+        generator.state.replace(GeneratorState::Frame {
+            closure: frame.closure.to_owned(),
+            ip: frame.ip,
+            stack: self.stack[frame.base_pointer..self.sp].to_vec(),
+        });
+
+        self.sp = frame.base_pointer;
+        self.push(value);
+    }
+
+    /// Pops the current frame and leaves `value` in place of its callee. A
+    /// generator's frame finishes the generator instead, and its `next` call
+    /// returns null.
+    fn return_from_frame(&mut self, value: Rc<Object>) {
+        let frame = self.pop_frame();
+        let frame = frame.borrow();
+        match frame.generator {
+            Some(ref generator) => {
+                generator.state.replace(GeneratorState::Done);
+                self.sp = frame.base_pointer;
+                self.push(Rc::clone(&self.null_object));
+            }
+            None => {
+                self.sp = frame.base_pointer - 1;
+                self.push(value);
+            }
+        }
+    }
+
+    /// Pushes the exports of the module whose body is the constant at
+    /// `const_index`, first running the body in a fresh set of globals if it
+    /// has not run yet. The body returns its exports.
+    fn execute_import(&mut self, const_index: usize) {
+        let body = match self.constants[const_index] {
+            Object::CompiledFunction(ref body) => body.to_owned(),
+            ref other => panic!("importing non-module: {}", other.kind()),
+        };
+        if let Some(exports) = self.exports.get(&body.module) {
+            return self.push(Rc::clone(exports));
+        }
+
+        if self.globals.len() <= body.module {
+            self.globals.resize(body.module + 1, vec![]);
+        }
+        self.globals[body.module] = vec![Rc::clone(&self.null_object); self.config.global_size];
+        let closure = Closure::new(body, vec![]);
+        self.push(Object::Closure(closure.to_owned()).into());
+        self.call_closure(&closure, 0);
+    }
+
+    fn call_struct(&mut self, definition: &StructDefinition, num_args: usize) {
+        let args = self.stack[self.sp - num_args..self.sp].to_vec();
+        self.sp -= num_args + 1;
+        self.push(definition.construct(args).into());
+    }
+
+    fn call_variant(&mut self, definition: &VariantDefinition, num_args: usize) {
+        let args = self.stack[self.sp - num_args..self.sp].to_vec();
+        self.sp -= num_args + 1;
+        self.push(definition.construct(args).into());
+    }
+
     fn native_bool_to_boolean_object(&self, native: bool) -> Rc<Object> {
         match native {
             true => Rc::clone(&self.true_object),
@@ -462,20 +1167,18 @@ impl Vm {
             let key = &self.stack[i];
             let value = Rc::clone(&self.stack[i + 1]);
 
-            let hash_key = match **key {
-                Object::Str(ref o) => o.hash_key(),
-                Object::Integer(ref o) => o.hash_key(),
-                Object::Boolean(ref o) => o.hash_key(),
-                _ => {
+            let hash_key = match key.hash_key() {
+                Some(hash_key) => hash_key,
+                None => {
                     return Object::RuntimeError(RuntimeError::new(format!(
-                        "only string, integer and boolean can be hash key, found {}",
+                        "only string, integer, boolean and struct can be hash key, found {}",
                         key.kind()
                     )))
                     .into()
                 }
             };
 
-            members.insert(hash_key, value);
+            members.insert(hash_key, HashPair::new(Rc::clone(key), value));
 
             i += 2;
         }
@@ -483,75 +1186,284 @@ impl Vm {
         Object::Hash(Hash::new(members)).into()
     }
 
-    fn is_truthy(&self, object: &Rc<Object>) -> bool {
-        match **object {
-            Object::Boolean(ref o) => o.value.to_owned(),
-            Object::Null(_) => false,
-            _ => true,
+    fn concat_arrays(&self, start_index: usize, end_index: usize) -> Rc<Object> {
+        let mut elements = vec![];
+
+        for segment in self.stack[start_index..end_index].iter() {
+            match **segment {
+                Object::Array(ref array) => elements.extend(array.elements.iter().cloned()),
+                Object::Range(_) => elements.extend(segment.iter_items().unwrap()),
+                _ => {
+                    return Object::RuntimeError(RuntimeError::new(format!(
+                        "spread operator requires ARRAY, got {}",
+                        segment.kind()
+                    )))
+                    .into()
+                }
+            }
         }
+
+        Object::Array(Array::new(elements)).into()
     }
 
-    fn push_closure(&mut self, const_index: u16, num_free: u16) {
-        let constant = self.constants.get(const_index as usize).unwrap().to_owned();
-        if let Object::CompiledFunction(func) = constant {
-            let mut free = vec![];
-            let mut i = 0;
-            while i < num_free {
-                free.push(Rc::clone(
-                    &self.stack[self.sp - num_free as usize + i as usize],
-                ));
-                i += 1;
+    fn merge_hashes(&self, start_index: usize, end_index: usize) -> Rc<Object> {
+        let mut members = HashMap::new();
+
+        for segment in self.stack[start_index..end_index].iter() {
+            match **segment {
+                Object::Hash(ref hash) => members.extend(
+                    hash.value
+                        .iter()
+                        .map(|(k, pair)| (k.to_owned(), pair.to_owned())),
+                ),
+                // A plain run that failed in `build_hash` is passed through.
+                Object::RuntimeError(_) => return Rc::clone(segment),
+                _ => {
+                    return Object::RuntimeError(RuntimeError::new(format!(
+                        "spread operator requires HASH, got {}",
+                        segment.kind()
+                    )))
+                    .into()
+                }
             }
-            self.sp -= num_free as usize;
-            self.push(Object::Closure(Closure::new(func, free)).into());
         }
-    }
 
-    fn push(&mut self, object: Rc<Object>) {
-        self.stack[self.sp] = object;
-        self.sp += 1;
+        Object::Hash(Hash::new(members)).into()
     }
 
-    fn pop(&mut self) -> Rc<Object> {
-        self.sp -= 1;
-        Rc::clone(&self.stack[self.sp])
+    /// Replaces the iterable on top of the stack with its items and a loop
+    /// index starting at zero.
+    fn execute_get_iter(&mut self) {
+        let iterable = self.pop();
+        let items = match *iterable {
+            Object::Array(_) | Object::Range(_) => Rc::clone(&iterable),
+            _ => match iterable.iter_items() {
+                Some(items) => Object::Array(Array::new(items.collect())).into(),
+                None => return self.halt(&format!("not iterable: {}", iterable.kind())),
+            },
+        };
+        self.push(items);
+        self.push(Object::Integer(Integer::new(0)).into());
     }
 
-    pub fn last_popped_stack_elem(&self) -> Rc<Object> {
-        if let Some(object) = self.stack.get(self.sp) {
-            Rc::clone(object)
-        } else {
-            Rc::clone(&self.null_object)
+    /// Pushes the next item and advances the loop index, or pops the items and
+    /// the index and returns false once they are exhausted.
+    fn execute_iter_next(&mut self) -> bool {
+        let index = match *self.stack[self.sp - 1] {
+            Object::Integer(ref index) => index.value as usize,
+            _ => unreachable!(),
+        };
+        let item = match *self.stack[self.sp - 2] {
+            Object::Array(ref items) => items.elements.get(index).cloned(),
+            Object::Range(ref range) => range
+                .get(index as i64)
+                .map(|value| Object::Integer(Integer::new(value)).into()),
+            _ => unreachable!(),
+        };
+
+        match item {
+            Some(item) => {
+                self.stack[self.sp - 1] = Object::Integer(Integer::new(index as i64 + 1)).into();
+                self.push(item);
+                true
+            }
+            None => {
+                self.sp -= 2;
+                false
+            }
+        }
+    }
+
+    fn execute_unpack(&mut self, num_elements: usize) {
+        let value = self.pop();
+        match *value {
+            Object::Array(ref array) if array.elements.len() == num_elements => {
+                for element in array.elements.iter() {
+                    self.push(Rc::clone(element));
+                }
+            }
+            _ => self.halt(&format!(
+                "cannot destructure {} into {} bindings",
+                value.kind(),
+                num_elements
+            )),
+        }
+    }
+
+    /// Adds the top `num_values` values to the accumulator, which sits below
+    /// the items and the index of the running loop.
+    fn execute_collect(&mut self, num_values: usize) {
+        let values = self.stack[self.sp - num_values..self.sp].to_vec();
+        self.sp -= num_values;
+
+        let accumulator = Rc::make_mut(&mut self.stack[self.sp - 3]);
+        match accumulator {
+            Object::Array(ref mut array) => array.elements.push(Rc::clone(&values[0])),
+            Object::Hash(ref mut hash) => match values[0].hash_key() {
+                Some(hash_key) => {
+                    let pair = HashPair::new(Rc::clone(&values[0]), Rc::clone(&values[1]));
+                    hash.value.insert(hash_key, pair);
+                }
+                None => {
+                    *accumulator = Object::RuntimeError(RuntimeError::new(format!(
+                        "only string, integer, boolean and struct can be hash key, found {}",
+                        values[0].kind()
+                    )))
+                }
+            },
+            // An earlier key was unusable; keep its error.
+            Object::RuntimeError(_) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_truthy(&self, object: &Rc<Object>) -> bool {
+        match **object {
+            Object::Boolean(ref o) => o.value.to_owned(),
+            Object::Null(_) => false,
+            _ => true,
+        }
+    }
+
+    fn push_closure(&mut self, const_index: usize, num_free: usize) {
+        let constant = self.constants.get(const_index).unwrap().to_owned();
+        if let Object::CompiledFunction(func) = constant {
+            let mut free = vec![];
+            let mut i = 0;
+            while i < num_free {
+                // Captured locals arrive as cells. Anything else, such as the
+                // enclosing closure itself, gets a cell of its own.
+                let value = Rc::clone(&self.stack[self.sp - num_free + i]);
+                free.push(match *value {
+                    Object::Cell(_) => value,
+                    _ => Object::Cell(Cell::new(value)).into(),
+                });
+                i += 1;
+            }
+            self.sp -= num_free;
+            self.push(Object::Closure(Closure::new(func, free)).into());
+        }
+    }
+
+    fn push(&mut self, object: Rc<Object>) {
+        if self.sp == self.stack.len() {
+            return self.halt("stack overflow");
+        }
+        self.stack[self.sp] = object;
+        self.sp += 1;
+    }
+
+    /// Reports whether another frame fits within the limits, using
+    /// `num_slots` stack slots from `base_pointer`. If not, the program is
+    /// halted with a stack overflow.
+    fn frame_fits(&mut self, base_pointer: usize, num_slots: usize) -> bool {
+        if self.frame_index < self.config.max_frames && base_pointer + num_slots <= self.stack.len()
+        {
+            return true;
+        }
+        self.halt("stack overflow");
+        false
+    }
+
+    /// Stops the program, with an error carrying `message` as its result.
+    /// The current instruction still finishes, but nothing runs after it.
+    fn halt(&mut self, message: &str) {
+        self.error = Some(Object::RuntimeError(RuntimeError::new(message.into())).into());
+    }
+
+    fn pop(&mut self) -> Rc<Object> {
+        self.sp -= 1;
+        Rc::clone(&self.stack[self.sp])
+    }
+
+    pub fn last_popped_stack_elem(&self) -> Rc<Object> {
+        if let Some(ref error) = self.error {
+            Rc::clone(error)
+        } else if let Some(object) = self.stack.get(self.sp) {
+            Rc::clone(object)
+        } else {
+            Rc::clone(&self.null_object)
+        }
+    }
+}
+
+/// A task that is not running, with the state the VM swaps in to run it.
+#[derive(Debug)]
+struct Task {
+    id: usize,
+    stack: Vec<Rc<Object>>,
+    sp: usize,
+    frames: Vec<Rc<RefCell<Frame>>>,
+    frame_index: usize,
+    waiting: Option<Waiting>,
+}
+
+impl Task {
+    fn push(&mut self, object: Rc<Object>) {
+        self.stack[self.sp] = object;
+        self.sp += 1;
+    }
+}
+
+/// The channel operation a blocked task waits to complete.
+#[derive(Debug)]
+enum Waiting {
+    Send(Channel),
+    Receive(Channel),
+}
+
+impl Waiting {
+    /// Withdraws the blocked operation of task `id` from its channel.
+    fn cancel(&self, id: usize) {
+        match self {
+            Self::Send(channel) => channel.state.borrow_mut().senders.retain(|s| s.0 != id),
+            Self::Receive(channel) => channel.state.borrow_mut().receivers.retain(|r| *r != id),
+        }
+    }
+}
+
+impl Display for Waiting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Send(channel) => write!(f, "sending to channel {}", channel.id),
+            Self::Receive(channel) => write!(f, "receiving from channel {}", channel.id),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::panic;
+    use std::{cell::RefCell, panic, rc::Rc};
 
     use crate::{
         compiler::Compiler,
+        enviroment::Enviroment,
+        evaluator::{with_stack, Evaluator},
+        makro::MacroExpension,
         object::Object,
         test_helper::{
             parse, test_array_object, test_boolean_object, test_closure_object, test_error_object,
             test_hash_object, test_integer_object, test_null_object, test_string_object,
-            ExpectedValue,
+            write_modules, ExpectedValue,
         },
     };
 
-    use super::Vm;
+    use super::{Vm, VmConfig};
 
     #[derive(Debug)]
     struct VmTestCase<'a>(&'a str, ExpectedValue<'a>);
 
     fn run_vm_tests(tests: &[VmTestCase]) {
+        run_vm_tests_with_config(tests, VmConfig::default());
+    }
+
+    fn run_vm_tests_with_config(tests: &[VmTestCase], config: VmConfig) {
         for test in tests.iter() {
             println!("{}", test.0);
             let program = parse(test.0);
             let mut compiler = Compiler::new();
             compiler.compile(&program);
-            let mut vm = Vm::new(compiler.bytecode());
+            let mut vm = Vm::new(compiler.bytecode(), config);
             vm.run();
 
             test_expected_object(&vm.last_popped_stack_elem(), &test.1)
@@ -589,6 +1501,9 @@ mod tests {
             VmTestCase("-5", ExpectedValue::Integer(-5)),
             VmTestCase("-10", ExpectedValue::Integer(-10)),
             VmTestCase("-50 + 100 + -50", ExpectedValue::Integer(0)),
+            VmTestCase("0xFF + 0o17 + 0b101", ExpectedValue::Integer(275)),
+            VmTestCase("1_000_000", ExpectedValue::Integer(1000000)),
+            VmTestCase("-9223372036854775808", ExpectedValue::Integer(i64::MIN)),
             VmTestCase(
                 "(5 + 10 * 2 + 15 / 3) * 2 + -10",
                 ExpectedValue::Integer(50),
@@ -720,14 +1635,615 @@ mod tests {
             VmTestCase("[[1, 1, 1]][0][0]", ExpectedValue::Integer(1)),
             VmTestCase("[][0]", ExpectedValue::Null),
             VmTestCase("[1, 2, 3][99]", ExpectedValue::Null),
-            VmTestCase("[1][-1]", ExpectedValue::Null),
+            VmTestCase("[1][-1]", ExpectedValue::Integer(1)),
+            VmTestCase("[1, 2, 3][-3]", ExpectedValue::Integer(1)),
+            VmTestCase("[1][-2]", ExpectedValue::Null),
+            VmTestCase(r#""hello"[1]"#, ExpectedValue::String("e")),
+            VmTestCase(r#""hello"[-1]"#, ExpectedValue::String("o")),
+            VmTestCase(r#""héllo"[1]"#, ExpectedValue::String("é")),
+            VmTestCase(r#""hello"[5]"#, ExpectedValue::Null),
             VmTestCase("{1: 1, 2: 2}[1]", ExpectedValue::Integer(1)),
             VmTestCase("{1: 1, 2: 2}[2]", ExpectedValue::Integer(2)),
             VmTestCase("{1: 1}[0]", ExpectedValue::Null),
             VmTestCase("{}[0]", ExpectedValue::Null),
+            VmTestCase(
+                r#""abc"["a"]"#,
+                ExpectedValue::Error("index is not a integer: STRING"),
+            ),
+            VmTestCase(
+                r#"(0..3)["a"]"#,
+                ExpectedValue::Error("index is not a integer: STRING"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_slice_expressions() {
+        let tests = [
+            VmTestCase("[1, 2, 3, 4][1:3]", ExpectedValue::Array(vec![2, 3])),
+            VmTestCase("[1, 2, 3, 4][:2]", ExpectedValue::Array(vec![1, 2])),
+            VmTestCase("[1, 2, 3, 4][2:]", ExpectedValue::Array(vec![3, 4])),
+            VmTestCase("[1, 2, 3, 4][:]", ExpectedValue::Array(vec![1, 2, 3, 4])),
+            VmTestCase("[1, 2, 3, 4][-2:]", ExpectedValue::Array(vec![3, 4])),
+            VmTestCase("[1, 2, 3, 4][:-1]", ExpectedValue::Array(vec![1, 2, 3])),
+            VmTestCase("[1, 2, 3, 4][3:1]", ExpectedValue::Array(vec![])),
+            VmTestCase("[1, 2, 3, 4][-10:10]", ExpectedValue::Array(vec![1, 2, 3, 4])),
+            VmTestCase(r#""monkey"[1:3]"#, ExpectedValue::String("on")),
+            VmTestCase(r#""monkey"[3:]"#, ExpectedValue::String("key")),
+            VmTestCase(r#""monkey"[:-3]"#, ExpectedValue::String("mon")),
+            VmTestCase(r#""héllo"[1:3]"#, ExpectedValue::String("él")),
+            VmTestCase(r#""monkey"[10:]"#, ExpectedValue::String("")),
+            VmTestCase(
+                "[1, 2][true:]",
+                ExpectedValue::Error("slice index is not a integer: BOOLEAN"),
+            ),
+            VmTestCase(
+                "{}[1:2]",
+                ExpectedValue::Error("slice operator not supported: HASH"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_member_expressions() {
+        let tests = [
+            VmTestCase(
+                r#"let book = {"title": "Monkey"}; book.title"#,
+                ExpectedValue::String("Monkey"),
+            ),
+            VmTestCase(
+                r#"let book = {"title": "Monkey"}; book.author"#,
+                ExpectedValue::Null,
+            ),
+            VmTestCase(r#"{"a": {"b": 2}}.a.b"#, ExpectedValue::Integer(2)),
+            VmTestCase(
+                r#"let counter = {"next": fn(x) { x + 1 }}; counter.next(1)"#,
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase("[1, 2, 3].push(4)", ExpectedValue::Array(vec![1, 2, 3, 4])),
+            VmTestCase(r#""monkey".len()"#, ExpectedValue::Integer(6)),
+            VmTestCase(
+                "let arr = [1, 2, 3]; arr.rest().first()",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase("let f = [1].len; f()", ExpectedValue::Integer(1)),
+            VmTestCase(
+                "[1].len(2)",
+                ExpectedValue::Error("wrong number of arguments. got=2, want=1"),
+            ),
+            VmTestCase(
+                r#"let h = {"a": 1, "b": 2}; h.len()"#,
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(r#"let h = {"len": 5}; h.len"#, ExpectedValue::Integer(5)),
+            VmTestCase(r#"[1, 2]["push"]"#, ExpectedValue::Null),
+            VmTestCase(
+                "5.gensym()",
+                ExpectedValue::Error("index operator not supported: INTEGER"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_structs() {
+        let tests = [
+            VmTestCase(
+                "struct Point { x, y }; let p = Point(1, 2); p.y",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                r#"struct Point { x, y }; Point(1, 2)["x"]"#,
+                ExpectedValue::Integer(1),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; Point(1, 2) == Point(1, 2)",
+                ExpectedValue::Boolean(true),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; Point(1, 2) != Point(2, 1)",
+                ExpectedValue::Boolean(true),
+            ),
+            VmTestCase(
+                "struct A { x }; struct B { x }; A(1) == B(1)",
+                ExpectedValue::Boolean(false),
+            ),
+            VmTestCase("struct Size { len }; Size(3).len", ExpectedValue::Integer(3)),
+            VmTestCase(
+                r#"struct P { x }; let h = {P(1): "one"}; h[P(1)]"#,
+                ExpectedValue::String("one"),
+            ),
+            VmTestCase(
+                "let make = fn() { struct Pair { a, b }; Pair(1, [2]) }; make().b",
+                ExpectedValue::Array(vec![2]),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; Point(1)",
+                ExpectedValue::Error("wrong number of fields for Point: got=1, want=2"),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; Point(1, 2).z",
+                ExpectedValue::Error("unknown field z for struct Point"),
+            ),
+            VmTestCase(
+                "struct P { x }; let h = {P(P(1)): 2}; h[P(P(1))]",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "struct P { x }; {P([1]): 1}",
+                ExpectedValue::Error(
+                    "only string, integer, boolean and struct can be hash key, found STRUCT",
+                ),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_field_assignment() {
+        let tests = [
+            VmTestCase(
+                "struct Point { x, y }; let p = Point(1, 2); p.x = 5; [p.x, p.y]",
+                ExpectedValue::Array(vec![5, 2]),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; let p = Point(1, 2); p.x = 5",
+                ExpectedValue::Integer(5),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; let p = Point(1, 2); let q = p; p.x = 5; q.x",
+                ExpectedValue::Integer(1),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; let f = fn() { let p = Point(1, 2); p.y = p.y + 1; p.y }; f()",
+                ExpectedValue::Integer(3),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; let f = fn() { let p = Point(1, 2); let set = fn(v) { p.x = v }; set(7); p.x }; f()",
+                ExpectedValue::Integer(7),
+            ),
+            VmTestCase(
+                "struct Point { x, y }; let p = Point(1, 2); p.z = 1",
+                ExpectedValue::Error("unknown field z for struct Point"),
+            ),
+            VmTestCase(
+                r#"let h = {"a": 1}; h.a = 2"#,
+                ExpectedValue::Error("field assignment not supported: HASH"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_enums() {
+        let shapes = "
+enum Shape { Circle(r), Rect(w, h), Empty }
+let area = fn(shape) {
+    match (shape) {
+        Circle(r) => 3 * r * r,
+        Rect(w, h) => { w * h }
+        Empty => 0
+    }
+};
+";
+        let inputs = [
+            ("area(Circle(2))", ExpectedValue::Integer(12)),
+            ("area(Rect(2, 3))", ExpectedValue::Integer(6)),
+            ("area(Empty)", ExpectedValue::Integer(0)),
+            ("Rect(1, 2) == Rect(1, 2)", ExpectedValue::Boolean(true)),
+            ("Rect(1, 2) == Rect(2, 1)", ExpectedValue::Boolean(false)),
+            ("match (5) { Circle(r) => r }", ExpectedValue::Null),
+            ("match (5) { Circle(r) => r, _ => 7 }", ExpectedValue::Integer(7)),
+            ("match (Empty) { Empty => { let x = 1; } }", ExpectedValue::Null),
+            (
+                "let f = fn() { match (Circle(1)) { Circle(r) => { return r; } }; 9 }; f()",
+                ExpectedValue::Integer(1),
+            ),
+            (
+                "let c = Circle(1); enum Round { Circle(r) } match (c) { Circle(r) => r, _ => 0 }",
+                ExpectedValue::Integer(0),
+            ),
+            (
+                "Rect(1)",
+                ExpectedValue::Error("wrong number of fields for Rect: got=1, want=2"),
+            ),
+            (
+                "match (Rect(1, 2)) { Rect(w) => w }",
+                ExpectedValue::Error("wrong number of bindings for Rect: got=1, want=2"),
+            ),
+            (
+                "let one = 1; match (Empty) { one => 1 }",
+                ExpectedValue::Error("not an enum variant: 1"),
+            ),
+        ];
+
+        let sources: Vec<_> = inputs
+            .iter()
+            .map(|(input, _)| format!("{}{}", shapes, input))
+            .collect();
+        let tests: Vec<_> = sources
+            .iter()
+            .zip(inputs)
+            .map(|(source, (_, expected))| VmTestCase(source, expected))
+            .collect();
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_pipe_expressions() {
+        let tests = [
+            VmTestCase(
+                "let double = fn(x) { x * 2 }; 3 |> double",
+                ExpectedValue::Integer(6),
+            ),
+            VmTestCase(
+                "let add = fn(a, b) { a + b }; 1 |> add(2) |> add(3)",
+                ExpectedValue::Integer(6),
+            ),
+            VmTestCase(
+                "[1, 2, 3] |> rest |> push(4)",
+                ExpectedValue::Array(vec![2, 3, 4]),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_null_safe_expressions() {
+        let tests = [
+            VmTestCase(
+                r#"let h = {"a": {"b": 1}}; h?.a?.b"#,
+                ExpectedValue::Integer(1),
+            ),
+            VmTestCase(r#"let h = {"a": {"b": 1}}; h?.x?.b"#, ExpectedValue::Null),
+            VmTestCase(r#"let h = {}; h?.x?.y?["z"]"#, ExpectedValue::Null),
+            VmTestCase(
+                "let n = if (false) { 1 }; n?[len([1, 2].push(3))]",
+                ExpectedValue::Null,
+            ),
+            VmTestCase(r#"{}["x"] ?? 5"#, ExpectedValue::Integer(5)),
+            VmTestCase("false ?? 5", ExpectedValue::Boolean(false)),
+            VmTestCase("0 ?? 5", ExpectedValue::Integer(0)),
+            VmTestCase(
+                r#"let h = {"port": 0}; h?.port ?? 80"#,
+                ExpectedValue::Integer(0),
+            ),
+            VmTestCase(r#"{}.a?.b ?? "none""#, ExpectedValue::String("none")),
+            VmTestCase(r#"{}.a ?? {}.b ?? 3"#, ExpectedValue::Integer(3)),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_spread_expressions() {
+        let tests = [
+            VmTestCase(
+                "let a = [1, 2]; let b = [3]; [...a, ...b, 4]",
+                ExpectedValue::Array(vec![1, 2, 3, 4]),
+            ),
+            VmTestCase("[...[], 1, ...[]]", ExpectedValue::Array(vec![1])),
+            VmTestCase(
+                "let add = fn(a, b, c) { a + b + c }; let args = [2, 3]; add(1, ...args)",
+                ExpectedValue::Integer(6),
+            ),
+            VmTestCase("push(...[[1], 2])", ExpectedValue::Array(vec![1, 2])),
+            VmTestCase(
+                "let f = fn(a, b) { a - b }; f(...[5, 3]) + f(...[1], 1)",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "let d = {1: 1, 2: 2}; {...d, 2: 3}",
+                ExpectedValue::Hash(vec![(1, 1), (2, 3)]),
+            ),
+            VmTestCase(
+                "let d = {1: 1, 2: 2}; {2: 3, ...d}",
+                ExpectedValue::Hash(vec![(1, 1), (2, 2)]),
+            ),
+            VmTestCase(
+                "[...1]",
+                ExpectedValue::Error("spread operator requires ARRAY, got INTEGER"),
+            ),
+            VmTestCase(
+                "{...[1]}",
+                ExpectedValue::Error("spread operator requires HASH, got ARRAY"),
+            ),
+            VmTestCase(
+                "len(...1)",
+                ExpectedValue::Error("spread operator requires ARRAY, got INTEGER"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_comprehensions() {
+        let tests = [
+            VmTestCase(
+                "[x * 2 for x in [1, 2, 3]]",
+                ExpectedValue::Array(vec![2, 4, 6]),
+            ),
+            VmTestCase(
+                "[x * 2 for x in [1, 2, 3] if x > 1]",
+                ExpectedValue::Array(vec![4, 6]),
+            ),
+            VmTestCase("[x for x in []]", ExpectedValue::Array(vec![])),
+            VmTestCase(
+                "[i * x for i, x in [[1, 2], [3, 4]]]",
+                ExpectedValue::Array(vec![2, 12]),
+            ),
+            VmTestCase(
+                "let xs = [1, 2]; len([[y * x for y in xs] for x in xs])",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "let h = {1: 2, 3: 4}; {k: v * 10 for k, v in h}",
+                ExpectedValue::Hash(vec![(1, 20), (3, 40)]),
+            ),
+            VmTestCase(
+                "{x: x * x for x in [1, 2, 3] if x != 2}",
+                ExpectedValue::Hash(vec![(1, 1), (3, 9)]),
+            ),
+            VmTestCase(
+                "let f = fn(xs) { let n = 1; [x + n for x in xs] }; f([1, 2])",
+                ExpectedValue::Array(vec![2, 3]),
+            ),
+            VmTestCase(
+                "let f = fn(x) { x * 2 }; [f(x) for x in [1, 2] if f(x) > 2]",
+                ExpectedValue::Array(vec![4]),
+            ),
+            VmTestCase(
+                "{fn() {}: 1 for x in [1]}",
+                ExpectedValue::Error(
+                    "only string, integer, boolean and struct can be hash key, found CLOSURE",
+                ),
+            ),
+            VmTestCase("let x = 1; [x for x in [5]]; x", ExpectedValue::Integer(1)),
+            VmTestCase(
+                "let f = fn() { let x = 1; {x: 1 for x in [5]}; x }; f()",
+                ExpectedValue::Integer(1),
+            ),
+            VmTestCase(
+                "[a for a, b in [1]]",
+                ExpectedValue::Error("cannot destructure INTEGER into 2 bindings"),
+            ),
+            VmTestCase(
+                "[x for x in 5]",
+                ExpectedValue::Error("not iterable: INTEGER"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let tests = [
+            VmTestCase("len(0..10)", ExpectedValue::Integer(10)),
+            VmTestCase("len(1..=10)", ExpectedValue::Integer(10)),
+            VmTestCase("len(5..1)", ExpectedValue::Integer(0)),
+            VmTestCase(
+                "(0..1000000000000)[-1]",
+                ExpectedValue::Integer(999999999999),
+            ),
+            VmTestCase("(2..=4)[0]", ExpectedValue::Integer(2)),
+            VmTestCase("(2..4)[2]", ExpectedValue::Null),
+            VmTestCase("(0..3).len()", ExpectedValue::Integer(3)),
+            VmTestCase(
+                "len(0..9223372036854775807)",
+                ExpectedValue::Integer(9223372036854775807),
+            ),
+            VmTestCase(
+                "len(-1..9223372036854775807)",
+                ExpectedValue::Error("range -1..9223372036854775807 is too long for `len`"),
+            ),
+            VmTestCase(
+                "len(-9223372036854775808..=9223372036854775807)",
+                ExpectedValue::Error(
+                    "range -9223372036854775808..=9223372036854775807 is too long for `len`",
+                ),
+            ),
+            VmTestCase(
+                "let r = -9223372036854775808..=9223372036854775807; [r[0], r[-1]]",
+                ExpectedValue::Array(vec![i64::MIN, i64::MAX]),
+            ),
+            VmTestCase("[...0..3]", ExpectedValue::Array(vec![0, 1, 2])),
+            VmTestCase(
+                "let n = 3; [x * x for x in 1..=n]",
+                ExpectedValue::Array(vec![1, 4, 9]),
+            ),
+            VmTestCase(
+                "{x: x for x in 0..2}",
+                ExpectedValue::Hash(vec![(0, 0), (1, 1)]),
+            ),
+            VmTestCase("5 in 0..1000000000000", ExpectedValue::Boolean(true)),
+            VmTestCase("3 in 0..3", ExpectedValue::Boolean(false)),
+            VmTestCase("3 in 0..=3", ExpectedValue::Boolean(true)),
+            VmTestCase("2 in [1, 2]", ExpectedValue::Boolean(true)),
+            VmTestCase(r#""b" in {"a": 1}"#, ExpectedValue::Boolean(false)),
+            VmTestCase(
+                r#"0.."a""#,
+                ExpectedValue::Error("unsupported range bounds: INTEGER..STRING"),
+            ),
+            VmTestCase(
+                "1 in 2",
+                ExpectedValue::Error("in operator not supported: INTEGER"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_generators() {
+        let tests = [
+            VmTestCase(
+                "fn* gen() { yield 1; yield 2; } let g = gen(); [next(g), g.next()]",
+                ExpectedValue::Array(vec![1, 2]),
+            ),
+            VmTestCase(
+                "fn* gen() { yield 1; } let g = gen(); next(g); next(g)",
+                ExpectedValue::Null,
+            ),
+            VmTestCase(
+                "let count = fn*(n) { [yield i for i in 0..n] }; let g = count(2); [next(g), next(g)]",
+                ExpectedValue::Array(vec![0, 1]),
+            ),
+            VmTestCase(
+                "fn* naturals() { [yield n for n in 0..9223372036854775807]; } let g = naturals(); next(g); next(g); next(g)",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "fn* gen(x) { if (x > 1) { yield 1; yield x } else { yield 0 }; yield -1 } let g = gen(5); [next(g), next(g), next(g)]",
+                ExpectedValue::Array(vec![1, 5, -1]),
+            ),
+            VmTestCase(
+                "fn* gen() { let a = 10; let b = yield a; yield a + 1 } let g = gen(); [next(g), next(g)]",
+                ExpectedValue::Array(vec![10, 11]),
+            ),
+            VmTestCase(
+                "fn* gen() { let h = {k: yield k for k in [1, 2]}; yield 2 in h } let g = gen(); [next(g), next(g)]; next(g)",
+                ExpectedValue::Boolean(true),
+            ),
+            VmTestCase(
+                "let f = fn(x) { fn*() { yield x; yield x * 2 } }; let g = f(3)(); let h = f(4)(); [next(g), next(h), next(g)]",
+                ExpectedValue::Array(vec![3, 4, 6]),
+            ),
+            VmTestCase(
+                "fn* gen() { yield 1; return 2; yield 3 } let g = gen(); next(g); next(g)",
+                ExpectedValue::Null,
+            ),
+            VmTestCase(
+                "next(1)",
+                ExpectedValue::Error("argument to `next` must be GENERATOR, got INTEGER"),
+            ),
+            VmTestCase(
+                "let g = fn*() { yield next(g) }(); next(g)",
+                ExpectedValue::Error("generator is already running"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_tasks() {
+        let tests = [
+            VmTestCase(
+                "let ch = channel(); spawn(fn() { send(ch, 42) }); recv(ch)",
+                ExpectedValue::Integer(42),
+            ),
+            VmTestCase(
+                "let ch = channel(); spawn(fn() { [send(ch, x) for x in 1..=3] }); [recv(ch), recv(ch), recv(ch)]",
+                ExpectedValue::Array(vec![1, 2, 3]),
+            ),
+            VmTestCase(
+                "let a = channel(); let b = channel();
+                spawn(fn() { [send(a, x) for x in 1..=3] });
+                spawn(fn() { [send(b, recv(a) * 10) for _ in 1..=3] });
+                [recv(b), recv(b), recv(b)]",
+                ExpectedValue::Array(vec![10, 20, 30]),
+            ),
+            VmTestCase(
+                "let ch = channel();
+                spawn(fn() { [x for x in 0..9223372036854775807] });
+                spawn(fn() { send(ch, 7) });
+                recv(ch)",
+                ExpectedValue::Integer(7),
+            ),
+            VmTestCase(
+                "let ch = channel(); ch.send(3)",
+                ExpectedValue::Error("deadlock: all tasks are blocked: task 0 sending to channel 0"),
+            ),
+            VmTestCase(
+                "let a = channel(); let b = channel(); spawn(fn() { recv(b) }); a.recv()",
+                ExpectedValue::Error(
+                    "deadlock: all tasks are blocked: task 0 receiving from channel 0, task 1 receiving from channel 1",
+                ),
+            ),
+            VmTestCase(
+                "let ch = channel(); spawn(fn() { ch.send(3) }); ch.recv()",
+                ExpectedValue::Integer(3),
+            ),
+            VmTestCase(
+                "spawn(fn() { recv(channel()) }); 5",
+                ExpectedValue::Integer(5),
+            ),
+            VmTestCase(
+                "send(1, 2)",
+                ExpectedValue::Error("argument to `send` must be CHANNEL, got INTEGER"),
+            ),
+            VmTestCase(
+                "spawn(fn(x) { x })",
+                ExpectedValue::Error(
+                    "argument to `spawn` must be a function without parameters, got CLOSURE",
+                ),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_long_comprehension() {
+        // Longer than both the stack and the frame limit allow for recursion.
+        let input = format!(
+            "let a = [{}]; let xs = [{}]; len([x + 1 for x in xs])",
+            vec!["1"; 100].join(", "),
+            vec!["...a"; 50].join(", ")
+        );
+
+        run_vm_tests(&[VmTestCase(&input, ExpectedValue::Integer(5000))]);
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let tests = [
+            (
+                "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } }; sum(500)",
+                "125250",
+            ),
+            (
+                "let f = fn(n) { if (n == 0) { 0 } else { [x + 1 for x in [f(n - 1)]][0] } }; f(500)",
+                "500",
+            ),
+            (
+                "fn* gen(n) { if (n == 0) { yield 0 } else { yield 1 + next(gen(n - 1)) } } next(gen(300))",
+                "300",
+            ),
         ];
 
-        run_vm_tests(&tests);
+        for (input, expected) in tests.iter() {
+            let program = parse(input);
+
+            let evaluated = with_stack(|| {
+                let env = Rc::new(RefCell::new(Enviroment::default()));
+                Evaluator::new(env).eval(&program).inspect()
+            });
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&program);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated, *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
     }
 
     #[test]
@@ -921,7 +2437,7 @@ mod tests {
                 let program = parse(input);
                 let mut compiler = Compiler::new();
                 compiler.compile(&program);
-                let mut vm = Vm::new(compiler.bytecode());
+                let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
                 vm.run();
             });
             if let Err(error) = result {
@@ -968,6 +2484,20 @@ mod tests {
                 r#"push(1, 1)"#,
                 ExpectedValue::Error("first argument to `push` must be ARRAY, got INTEGER"),
             ),
+            // A builtin call leaves only its result, in place of the callee
+            // and arguments.
+            VmTestCase(
+                "push(rest([1, 2, 3]), len([1]))",
+                ExpectedValue::Array(vec![2, 3, 1]),
+            ),
+            VmTestCase(
+                "let f = fn(a) { let n = len(a); n + len(a) }; f([1, 2])",
+                ExpectedValue::Integer(4),
+            ),
+            VmTestCase(
+                "len([len([i]) for i in 0..3000])",
+                ExpectedValue::Integer(3000),
+            ),
         ];
 
         run_vm_tests(&tests);
@@ -1035,6 +2565,423 @@ mod tests {
         run_vm_tests(&tests);
     }
 
+    #[test]
+    fn test_mutable_closures() {
+        let tests = [
+            VmTestCase(
+                "let makeCounter = fn() { let n = 0; fn() { n = n + 1; n } };
+                let a = makeCounter();
+                let b = makeCounter();
+                a(); a(); b(); a()",
+                ExpectedValue::Integer(3),
+            ),
+            VmTestCase(
+                "let f = fn() { let n = 1; let inc = fn() { n = n + 10 }; inc(); inc(); n }; f()",
+                ExpectedValue::Integer(21),
+            ),
+            VmTestCase(
+                "let f = fn() { let n = 1; let get = fn() { n }; n = 5; get() }; f()",
+                ExpectedValue::Integer(5),
+            ),
+            VmTestCase(
+                "let f = fn() { let n = 0; let g = fn() { fn() { n = n + 1 } }; g()(); g()(); n }; f()",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "let f = fn() { let n = 0; fn() { n } };
+                let g = f();
+                let h = fn() { let m = 7; m };
+                h();
+                g()",
+                ExpectedValue::Integer(0),
+            ),
+            VmTestCase(
+                "let f = fn(k, acc) {
+                    let n = k;
+                    let g = fn() { n };
+                    if (k == 0) { acc } else { f(k - 1, push(acc, g)) }
+                };
+                f(3, [])[0]()",
+                ExpectedValue::Integer(3),
+            ),
+            VmTestCase(
+                "fn* gen() { let n = 0; let inc = fn() { n = n + 1 }; yield inc(); yield inc() }
+                let g = gen();
+                next(g);
+                next(g)",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "let x = 1; let f = fn() { x = x + 1 }; f(); f(); x",
+                ExpectedValue::Integer(3),
+            ),
+            VmTestCase(
+                "let a = 1; let b = a = 5; a + b",
+                ExpectedValue::Integer(10),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_block_scoping() {
+        let tests = [
+            ("let x = 1; if (true) { let x = 2; x }", "2"),
+            ("let x = 1; if (true) { let x = 2 }; x", "1"),
+            ("if (true) { let x = 2 }", "null"),
+            ("fn() { let x = 1 }()", "null"),
+            ("let x = 1; if (true) { x = 2 }; x", "2"),
+            (
+                "let f = fn() { let x = 1; if (true) { x = x + 1; let x = 10; x = x + 1 }; x }; f()",
+                "2",
+            ),
+            (
+                "let f = fn(n) { if (n > 0) { let a = n; fn() { a } } else { let b = 7; b } };
+                let g = f(3);
+                [g(), f(0), g()]",
+                "[3, 7, 3]",
+            ),
+            (
+                "let f = fn() {
+                    let g = if (true) { let a = 1; fn() { a } };
+                    let r = if (true) { let b = 5; b };
+                    [g(), r]
+                };
+                f()",
+                "[1, 5]",
+            ),
+            (
+                "enum E { A(x), B }; let x = 5; let y = match (A(1)) { A(x) => { x }, _ => { 0 } }; [x, y]",
+                "[5, 1]",
+            ),
+            ("let x = 1; let g = fn() { x }; let x = 2; g()", "2"),
+            (
+                "let f = fn() { let x = 1; let g = fn() { x }; let x = 2; g() }; f()",
+                "2",
+            ),
+            (
+                "fn* gen() { let x = 1; if (true) { let x = 2; yield x }; yield x } let g = gen(); [next(g), next(g)]",
+                "[2, 1]",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = parse(input);
+
+            let env = Rc::new(RefCell::new(Enviroment::default()));
+            let evaluated = Evaluator::new(env).eval(&program);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&program);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated.inspect(), *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
+
+        let input = "if (true) { let y = 1 }; y";
+        let env = Rc::new(RefCell::new(Enviroment::default()));
+        let evaluated = Evaluator::new(env).eval(&parse(input));
+        assert_eq!(evaluated.inspect(), "Error: identifier not found: y");
+
+        let result = panic::catch_unwind(|| Compiler::new().compile(&parse(input)));
+        assert_eq!(
+            *result.unwrap_err().downcast::<String>().unwrap(),
+            "undefined variable y"
+        );
+    }
+
+    #[test]
+    fn test_null_safe_chains() {
+        let tests = [
+            ("n?.x.y", "null"),
+            ("n?.len()", "null"),
+            (r#"let h = {"a": n}; h.a?.b.c[0]"#, "null"),
+            (r#"let h = {"a": {"b": [1, 2]}}; h?.a.b[1]"#, "2"),
+            ("n?[0][1:]", "null"),
+            ("let f = fn(h) { h?.x.y() }; f(n)", "null"),
+            ("[n?.x.y, 1]", "[null, 1]"),
+            ("n?.a ?? n?.b.c ?? 3", "3"),
+            (r#"let h = {"f": fn(x) { x }}; h?.f(n?.y.z)"#, "null"),
+            ("n.x.y", "Error: index operator not supported: NULL"),
+            ("(n?.a).b", "Error: index operator not supported: NULL"),
+            ("(n?.a) ?? 1", "1"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = parse(&format!("let n = if (false) {{ 1 }}; {}", input));
+
+            let env = Rc::new(RefCell::new(Enviroment::default()));
+            let evaluated = Evaluator::new(env).eval(&program);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&program);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated.inspect(), *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_global_block_bindings() {
+        let tests = [
+            ("let fs = [fn() { x } for x in [1, 2]]; fs[0]()", "1"),
+            (
+                "let fs = [if (true) { let y = x; fn() { y } } for x in [1, 2]]; [fs[0](), fs[1]()]",
+                "[1, 2]",
+            ),
+            ("let f = if (true) { let x = 1; let g = fn() { x }; x = 5; g }; f()", "5"),
+            ("let f = if (true) { let x = 1; let g = fn() { x = x + 1; x }; g(); g }; f()", "3"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = parse(input);
+
+            let env = Rc::new(RefCell::new(Enviroment::default()));
+            let evaluated = Evaluator::new(env).eval(&program);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&program);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated.inspect(), *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        let tests = [
+            ("const a = 1; a", "1"),
+            ("const a = 1; let f = fn() { a + 1 }; f()", "2"),
+            (
+                "const a = 1; let f = fn() { let a = 2; a = a + 1; a }; [f(), a]",
+                "[3, 1]",
+            ),
+            ("const a = 1; if (true) { const a = 2; a }", "2"),
+            ("let f = fn() { const a = 5; fn() { a } }; f()()", "5"),
+            (
+                "enum E { A(x) }; const x = 1; let r = match (A(2)) { A(x) => { x } }; [x, r]",
+                "[1, 2]",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = parse(input);
+
+            let env = Rc::new(RefCell::new(Enviroment::default()));
+            let evaluated = Evaluator::new(env).eval(&program);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&program);
+            assert!(compiler.errors.is_empty(), "{}", input);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated.inspect(), *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_macros() {
+        let tests = [
+            ("let reverse = macro(a, b) { quote(unquote(b) - unquote(a)) }; reverse(2 + 2, 10 - 5)", "1"),
+            (
+                "let unless = macro(cond, then, otherwise) {
+                    quote(if (!(unquote(cond))) { unquote(then) } else { unquote(otherwise) })
+                };
+                let f = fn(x) { unless(x > 5, x * 2, x) };
+                [f(3), f(10)]",
+                "[6, 10]",
+            ),
+            (
+                "let defpair = macro(a, b) { quote({ let first = unquote(a); let second = unquote(b) }, [first, second]) };
+                defpair(1, 2);
+                [first, second]",
+                "[1, 2]",
+            ),
+            (
+                "let twice = macro(x) { quote({ let y = unquote(x); y + y }) };
+                let f = fn(y) { [twice(y + 1), y] };
+                f(2)",
+                "[6, 2]",
+            ),
+            ("let nothing = macro() { quote({}) }; 1 + 1; nothing()", "2"),
+            ("let nothing = macro() { quote({}) }; 1 + 1; let r = nothing(); r", "null"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let mut program = parse(input);
+            let env = Rc::new(RefCell::new(Enviroment::default()));
+            MacroExpension::new(Rc::clone(&env))
+                .expand(&mut program)
+                .unwrap();
+
+            let evaluated = Evaluator::new(env).eval(&program);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&program);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated.inspect(), *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_modules() {
+        let dir = write_modules(
+            "vm",
+            &[
+                (
+                    "lib/strings.mk",
+                    "import \"counter.mk\" as counter;
+                    let suffix = \"!\";
+                    export fn shout(s) { counter.bump(); s + suffix }
+                    export const greeting = \"hi\";
+                    export struct Pair { first, second }
+                    export enum Option { Some(value), Nothing }",
+                ),
+                (
+                    "lib/counter.mk",
+                    "let count = 0;
+                    export fn bump() { count = count + 1 }
+                    export fn get() { count }",
+                ),
+                ("a.mk", "import \"b.mk\" as b; export let a = 1;"),
+                ("b.mk", "import \"a.mk\" as a; export let b = 2;"),
+                ("failing.mk", "export let x = 1 + true;"),
+            ],
+        );
+        let tests = [
+            (
+                "import \"lib/strings.mk\" as strings;
+                let suffix = \"?\";
+                [strings.shout(strings.greeting), suffix]",
+                "[hi!, ?]",
+            ),
+            (
+                "import \"lib/strings.mk\" as strings;
+                import \"lib/strings.mk\" as again;
+                import \"lib/counter.mk\" as counter;
+                strings.shout(\"a\");
+                again.shout(\"b\");
+                counter.bump();
+                counter.get()",
+                "3",
+            ),
+            (
+                "import \"lib/strings.mk\" as strings;
+                let p = strings.Pair(1, 2);
+                [p.second, strings.Some(3), strings.Nothing, strings.suffix]",
+                "[2, Some(3), Nothing, null]",
+            ),
+            (
+                "import \"lib/counter.mk\" as counter;
+                let get = counter.get;
+                let count = 10;
+                counter.bump();
+                [get(), count]",
+                "[1, 10]",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = parse(input);
+            let env = Rc::new(RefCell::new(Enviroment::default()));
+            let mut evaluator = Evaluator::new(env);
+            evaluator.set_main_file(&dir.join("main.mk"));
+            let evaluated = evaluator.eval(&program);
+
+            let mut compiler = Compiler::new();
+            compiler.set_main_file(&dir.join("main.mk"));
+            compiler.compile(&program);
+            let mut vm = Vm::new(compiler.bytecode(), VmConfig::default());
+            vm.run();
+
+            assert_eq!(evaluated.inspect(), *expected, "evaluator: {}", input);
+            assert_eq!(
+                vm.last_popped_stack_elem().inspect(),
+                *expected,
+                "vm: {}",
+                input
+            );
+        }
+
+        let a = dir.join("a.mk").canonicalize().unwrap();
+        let b = dir.join("b.mk").canonicalize().unwrap();
+        let failing = dir.join("failing.mk").canonicalize().unwrap();
+        let tests = [
+            (
+                "import \"a.mk\" as a",
+                format!(
+                    "{}: {}: import cycle: {} -> {} -> {}",
+                    a.display(),
+                    b.display(),
+                    a.display(),
+                    b.display(),
+                    a.display()
+                ),
+            ),
+            (
+                "import \"missing.mk\" as missing; missing.x",
+                "module not found: missing.mk".into(),
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = parse(input);
+            let mut evaluator = Evaluator::new(Rc::new(RefCell::new(Enviroment::default())));
+            evaluator.set_main_file(&dir.join("main.mk"));
+            test_error_object(&evaluator.eval(&program), expected);
+
+            let mut compiler = Compiler::new();
+            compiler.set_main_file(&dir.join("main.mk"));
+            compiler.compile(&program);
+            assert_eq!(compiler.errors, vec![expected.to_owned()], "{}", input);
+        }
+
+        let program = parse("import \"failing.mk\" as failing");
+        let mut evaluator = Evaluator::new(Rc::new(RefCell::new(Enviroment::default())));
+        evaluator.set_main_file(&dir.join("main.mk"));
+        test_error_object(
+            &evaluator.eval(&program),
+            &format!("{}: type mismatch: INTEGER + BOOLEAN", failing.display()),
+        );
+    }
+
     #[test]
     fn test_recursive_fibonacci() {
         let test = VmTestCase(
@@ -1055,4 +3002,139 @@ mod tests {
 
         run_vm_tests(&[test]);
     }
+
+    #[test]
+    fn test_tail_calls() {
+        let tests = [
+            VmTestCase(
+                "let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+                count(100000, 0)",
+                ExpectedValue::Integer(100000),
+            ),
+            VmTestCase(
+                "let map = fn(arr, f) {
+                    let iter = fn(arr, accumulated) {
+                        if (len(arr) == 0) {
+                            accumulated
+                        } else {
+                            iter(rest(arr), push(accumulated, f(first(arr))));
+                        }
+                    };
+                    iter(arr, []);
+                };
+                len(map([x for x in 0..3000], fn(x) { x * 2 }))",
+                ExpectedValue::Integer(3000),
+            ),
+            VmTestCase(
+                "let g = fn(x) { x + 1 }; let f = fn(x) { return g(x); 0 }; f(1)",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "let f = fn(a) { len(a) }; f([1, 2])",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "fn* gen() { yield 3 } let f = fn() { gen() }; next(f())",
+                ExpectedValue::Integer(3),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let tests = [
+            VmTestCase(
+                "let f = fn(n) { 1 + f(n + 1) }; f(0)",
+                ExpectedValue::Error("stack overflow"),
+            ),
+            VmTestCase(
+                "let ch = channel(); spawn(fn() { let f = fn() { 1 + f() }; f() }); recv(ch)",
+                ExpectedValue::Error("stack overflow"),
+            ),
+            VmTestCase(
+                "fn* gen(n) { yield n + next(gen(n + 1)) } next(gen(0))",
+                ExpectedValue::Error("stack overflow"),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+
+        let config = VmConfig {
+            stack_size: 16,
+            max_frames: 4,
+            global_size: 2,
+        };
+        let tests = [
+            VmTestCase(
+                "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(2)",
+                ExpectedValue::Integer(2),
+            ),
+            VmTestCase(
+                "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(3)",
+                ExpectedValue::Error("stack overflow"),
+            ),
+            VmTestCase(
+                &format!("len([{}])", vec!["1"; 16].join(", ")),
+                ExpectedValue::Error("stack overflow"),
+            ),
+            VmTestCase(
+                "let a = 1; let b = 2; let c = 3; a",
+                ExpectedValue::Error("too many globals"),
+            ),
+        ];
+
+        run_vm_tests_with_config(&tests, config);
+    }
+
+    #[test]
+    fn test_wide_operands() {
+        let letter = |i: usize| (b'a' + (i % 26) as u8) as char;
+        let names: Vec<String> = (0..300)
+            .map(|i| format!("x{}{}", letter(i / 26), letter(i)))
+            .collect();
+        let lets: String = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("let {} = {}; ", name, i))
+            .collect();
+        let zeros = vec!["0"; 70000].join("; ");
+
+        let tests = [
+            VmTestCase(
+                &format!("let f = fn() {{ {}xaa + xln }}; f()", lets),
+                ExpectedValue::Integer(299),
+            ),
+            VmTestCase(
+                &format!(
+                    "let f = fn({}) {{ xaa + xln }}; f({})",
+                    names.join(", "),
+                    (0..300)
+                        .map(|i| i.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                ExpectedValue::Integer(299),
+            ),
+            VmTestCase(
+                &format!(
+                    "let f = fn() {{ {}fn() {{ {} }} }}; f()()",
+                    lets,
+                    names.join(" + ")
+                ),
+                ExpectedValue::Integer(44850),
+            ),
+            VmTestCase(
+                &format!("if (false) {{ {} }} else {{ 7 }}", zeros),
+                ExpectedValue::Integer(7),
+            ),
+            VmTestCase(
+                &format!("if (true) {{ {}; 8 }}", zeros),
+                ExpectedValue::Integer(8),
+            ),
+        ];
+
+        run_vm_tests(&tests);
+    }
 }